@@ -0,0 +1,109 @@
+use crate::api::auth::ApiKeyStore;
+use crate::model::api_key::{ApiKeyModel, CreateApiKeyReq, CreatedApiKeyModel};
+use crate::model::{ApiError, ApiResponse, Empty};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use std::sync::Arc;
+
+#[utoipa::path(
+    post,
+    path = "/api-key",
+    request_body = CreateApiKeyReq,
+    responses(
+        (status = 201, description = "API key created. The plaintext key is returned only once", body = ApiResponse<CreatedApiKeyModel>),
+        (status = 400, description = "Bad Request", body = ApiResponse<Empty>),
+        (status = 500, description = "Server Error", body = ApiResponse<Empty>)
+    ),
+    tag = "API Keys"
+)]
+pub async fn create_api_key(
+    State(api_keys): State<Arc<ApiKeyStore>>,
+    Json(payload): Json<CreateApiKeyReq>,
+) -> Result<(StatusCode, Json<ApiResponse<CreatedApiKeyModel>>), ApiError> {
+    if payload.name.trim().is_empty() {
+        return Err(ApiError::BadRequest("Name must not be empty".into()));
+    }
+
+    if payload.scopes.is_empty() {
+        return Err(ApiError::BadRequest("At least one scope is required".into()));
+    }
+
+    let (key, plaintext) = api_keys.create(payload.name, payload.scopes).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+
+    let created = CreatedApiKeyModel {
+        key: plaintext,
+        signing_secret: key.signing_secret.clone().unwrap_or_default(),
+        info: key.into(),
+    };
+
+    Ok((StatusCode::CREATED, Json(ApiResponse::success(created))))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api-key",
+    responses(
+        (status = 200, description = "List all API keys, including revoked ones", body = ApiResponse<Vec<ApiKeyModel>>),
+        (status = 500, description = "Server Error", body = ApiResponse<Empty>)
+    ),
+    tag = "API Keys"
+)]
+pub async fn get_api_keys(
+    State(api_keys): State<Arc<ApiKeyStore>>,
+) -> Result<(StatusCode, Json<ApiResponse<Vec<ApiKeyModel>>>), ApiError> {
+    let keys = api_keys.list().await
+        .into_iter()
+        .map(ApiKeyModel::from)
+        .collect();
+
+    Ok((StatusCode::OK, Json(ApiResponse::success(keys))))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api-key/{id}",
+    params(
+        ("id" = String, Path, description = "API key UUID")
+    ),
+    responses(
+        (status = 200, description = "API key data", body = ApiResponse<ApiKeyModel>),
+        (status = 404, description = "API key not found", body = ApiResponse<Empty>),
+        (status = 500, description = "Server Error", body = ApiResponse<Empty>)
+    ),
+    tag = "API Keys"
+)]
+pub async fn get_api_key(
+    State(api_keys): State<Arc<ApiKeyStore>>,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<ApiResponse<ApiKeyModel>>), ApiError> {
+    let key = api_keys.get(&id).await
+        .ok_or_else(|| ApiError::NotFound("API key not found".into()))?;
+
+    Ok((StatusCode::OK, Json(ApiResponse::success(key.into()))))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api-key/{id}",
+    params(
+        ("id" = String, Path, description = "API key UUID")
+    ),
+    responses(
+        (status = 200, description = "API key revoked", body = ApiResponse<Empty>),
+        (status = 404, description = "API key not found", body = ApiResponse<Empty>),
+        (status = 500, description = "Server Error", body = ApiResponse<Empty>)
+    ),
+    tag = "API Keys"
+)]
+pub async fn revoke_api_key(
+    State(api_keys): State<Arc<ApiKeyStore>>,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<ApiResponse<String>>), ApiError> {
+    api_keys.revoke(&id).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?
+        .ok_or_else(|| ApiError::NotFound("API key not found".into()))?;
+
+    Ok((StatusCode::OK, Json(ApiResponse::ok())))
+}
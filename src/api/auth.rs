@@ -1,44 +0,0 @@
-use necko3_core::state::AppState;
-use axum::extract::{Request, State};
-use axum::http::{HeaderMap, StatusCode};
-use axum::middleware::Next;
-use axum::response::IntoResponse;
-use std::sync::Arc;
-use utoipa::Modify;
-use utoipa::openapi::OpenApi;
-use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
-
-pub struct SecurityAddon;
-
-impl Modify for SecurityAddon {
-    fn modify(&self, openapi: &mut OpenApi) {
-        if let Some(components) = openapi.components.as_mut() {
-            components.add_security_scheme(
-                "api_key",
-                SecurityScheme::ApiKey(
-                    ApiKey::Header(
-                        ApiKeyValue::new("x-api-key"),
-                    ),
-                ),
-            );
-        }
-    }
-}
-
-pub async fn auth_middleware(
-    State(state): State<Arc<AppState>>,
-    headers: HeaderMap,
-    request: Request,
-    next: Next,
-) -> Result<impl IntoResponse, StatusCode> {
-    let auth_header = headers
-        .get("x-api-key")
-        .and_then(|value| value.to_str().ok());
-
-    match auth_header {
-        Some(key) if key == state.api_key.as_str() => {
-            Ok(next.run(request).await)
-        }
-        _ => Err(StatusCode::UNAUTHORIZED),
-    }
-}
\ No newline at end of file
@@ -0,0 +1,156 @@
+use crate::model::api_key::{ApiKey, ApiScope};
+use crate::store::JsonStore;
+use rand::distr::{Alphanumeric, SampleString};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use subtle::ConstantTimeEq;
+
+const KEY_PREFIX: &str = "nk3_";
+const SIGNING_SECRET_PREFIX: &str = "nk3s_";
+const KEY_LENGTH: usize = 48;
+const DISPLAY_PREFIX_LENGTH: usize = 8;
+
+/// Who made the request, attached to request extensions by `auth_middleware`.
+#[derive(Debug, Clone)]
+pub struct Principal {
+    /// `None` for the root key from `API_KEY`
+    pub key_id: Option<String>,
+    pub scopes: Vec<ApiScope>,
+}
+
+impl Principal {
+    pub fn root() -> Self {
+        Self {
+            key_id: None,
+            scopes: ApiScope::ALL.to_vec(),
+        }
+    }
+
+    pub fn has_scope(&self, required: ApiScope) -> bool {
+        self.scopes.iter().any(|scope| scope.grants(required))
+    }
+}
+
+pub struct ApiKeyStore {
+    root_key_hash: String,
+    keys: JsonStore<ApiKey>,
+}
+
+impl ApiKeyStore {
+    /// `root_key` is the `API_KEY` from env and always carries every scope
+    pub async fn open(root_key: &str, path: PathBuf) -> anyhow::Result<Self> {
+        Ok(Self {
+            root_key_hash: hash_key(root_key),
+            keys: JsonStore::open(path).await?,
+        })
+    }
+
+    /// Compares hashes in constant time and walks every stored key regardless of
+    /// where (or whether) a match is found, so timing doesn't hint at the key.
+    pub async fn authenticate(&self, key: &str) -> Option<Principal> {
+        let hash = hash_key(key);
+
+        let is_root = bool::from(hash.as_bytes().ct_eq(self.root_key_hash.as_bytes()));
+
+        let mut matched = None;
+        for k in self.keys.list().await {
+            let equal = bool::from(hash.as_bytes().ct_eq(k.hash.as_bytes()));
+            if equal && k.revoked_at.is_none() {
+                matched = Some(k);
+            }
+        }
+
+        if is_root {
+            return Some(Principal::root());
+        }
+
+        matched.map(|k| Principal {
+            key_id: Some(k.id),
+            scopes: k.scopes,
+        })
+    }
+
+    /// Looks up the HMAC secret for signed requests. Only managed keys can sign
+    pub async fn signing_key(&self, key_id: &str) -> Option<(Principal, String)> {
+        let key = self.keys.get(key_id).await
+            .filter(|k| k.revoked_at.is_none())?;
+        let secret = key.signing_secret?;
+
+        Some((Principal { key_id: Some(key.id), scopes: key.scopes }, secret))
+    }
+
+    /// Returns the stored key together with its plaintext, which is not kept anywhere
+    pub async fn create(&self, name: String, scopes: Vec<ApiScope>) -> anyhow::Result<(ApiKey, String)> {
+        let plaintext = format!("{}{}", KEY_PREFIX,
+                                Alphanumeric.sample_string(&mut rand::rng(), KEY_LENGTH));
+        let signing_secret = format!("{}{}", SIGNING_SECRET_PREFIX,
+                                     Alphanumeric.sample_string(&mut rand::rng(), KEY_LENGTH));
+
+        let key = ApiKey {
+            id: uuid::Uuid::new_v4().to_string(),
+            name,
+            prefix: plaintext[..DISPLAY_PREFIX_LENGTH].to_owned(),
+            hash: hash_key(&plaintext),
+            signing_secret: Some(signing_secret),
+            scopes,
+            created_at: chrono::Utc::now(),
+            revoked_at: None,
+        };
+
+        self.keys.insert(key.id.clone(), key.clone()).await?;
+
+        Ok((key, plaintext))
+    }
+
+    pub async fn list(&self) -> Vec<ApiKey> {
+        let mut keys = self.keys.list().await;
+        keys.sort_by_key(|k| k.created_at);
+        keys
+    }
+
+    pub async fn get(&self, id: &str) -> Option<ApiKey> {
+        self.keys.get(id).await
+    }
+
+    /// Revoked keys are kept for audit, they just stop authenticating
+    pub async fn revoke(&self, id: &str) -> anyhow::Result<Option<ApiKey>> {
+        self.keys.update(id, |k| {
+            k.revoked_at.get_or_insert_with(chrono::Utc::now);
+        }).await
+    }
+}
+
+fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn signing_keeps_the_key_scopes() {
+        let dir = std::env::temp_dir().join(format!("necko3-keys-{}", uuid::Uuid::new_v4()));
+        let store = ApiKeyStore::open("root", dir.join("api_keys.jsonl")).await.unwrap();
+
+        let (key, plaintext) = store.create("storefront".into(), vec![ApiScope::InvoicesWrite]).await.unwrap();
+
+        let (principal, secret) = store.signing_key(&key.id).await.unwrap();
+        assert_eq!(Some(secret), key.signing_secret);
+        assert_eq!(principal.key_id.as_deref(), Some(key.id.as_str()));
+        assert!(principal.has_scope(ApiScope::InvoicesWrite));
+        assert!(principal.has_scope(ApiScope::InvoicesRead));
+        assert!(!principal.has_scope(ApiScope::PaymentsRead));
+        assert!(!principal.has_scope(ApiScope::ApiKeysAdmin));
+
+        // the root key has no id and can't sign
+        assert!(store.authenticate("root").await.is_some_and(|root| root.has_scope(ApiScope::ApiKeysAdmin)));
+        assert!(store.signing_key("root").await.is_none());
+
+        store.revoke(&key.id).await.unwrap();
+        assert!(store.signing_key(&key.id).await.is_none());
+        assert!(store.authenticate(&plaintext).await.is_none());
+
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+}
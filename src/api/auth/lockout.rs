@@ -0,0 +1,272 @@
+use axum::http::HeaderMap;
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::time::MissedTickBehavior;
+
+/// Most clients tracked at once, the oldest one makes room for a new one past that
+const MAX_ENTRIES: usize = 100_000;
+
+/// How often entries past their window and lockout are dropped
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Entry {
+    failures: u32,
+    window_start: Instant,
+    locked_until: Option<Instant>,
+    /// matches the entry's place in `Entries::order`
+    created: Instant,
+}
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<IpAddr, Entry>,
+    /// clients oldest first. Removed entries leave theirs behind, skipped by `created`
+    order: VecDeque<(IpAddr, Instant)>,
+}
+
+impl Entries {
+    fn evict_oldest(&mut self) {
+        while let Some((client, created)) = self.order.pop_front() {
+            if self.map.get(&client).is_some_and(|entry| entry.created == created) {
+                self.map.remove(&client);
+                return;
+            }
+        }
+    }
+}
+
+/// Counts failed authentications per client and locks the client out once `max_failures`
+/// happen within `window`. A client is an IPv4 address or an IPv6 /64, which is what a
+/// single host usually gets to pick addresses from.
+pub struct FailedAuthTracker {
+    max_failures: u32,
+    window: Duration,
+    lockout: Duration,
+    trust_forwarded_for: bool,
+    entries: Mutex<Entries>,
+}
+
+impl FailedAuthTracker {
+    pub fn new(max_failures: u32, window: Duration, lockout: Duration, trust_forwarded_for: bool) -> Self {
+        Self {
+            max_failures,
+            window,
+            lockout,
+            trust_forwarded_for,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    /// Drops stale entries every `SWEEP_INTERVAL`, so requests never pay for it
+    pub fn spawn_sweeping(self: Arc<Self>) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(SWEEP_INTERVAL);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // the first tick completes right away, there is nothing to sweep yet
+            ticker.tick().await;
+
+            loop {
+                ticker.tick().await;
+                self.sweep();
+            }
+        });
+    }
+
+    /// Peer address, or the last `X-Forwarded-For` hop (the one appended by our own proxy)
+    /// when running behind a trusted reverse proxy
+    pub fn client_ip(&self, headers: &HeaderMap, peer: SocketAddr) -> IpAddr {
+        if self.trust_forwarded_for {
+            let forwarded = headers
+                .get("x-forwarded-for")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.rsplit(',').next())
+                .and_then(|ip| ip.trim().parse::<IpAddr>().ok());
+
+            if let Some(ip) = forwarded {
+                return ip;
+            }
+        }
+
+        peer.ip()
+    }
+
+    /// Time left until the client may try again, if it's currently locked out
+    pub fn locked_for(&self, ip: IpAddr) -> Option<Duration> {
+        let entries = self.entries.lock().unwrap();
+        let locked_until = entries.map.get(&client(ip))?.locked_until?;
+
+        locked_until.checked_duration_since(Instant::now())
+    }
+
+    /// Returns `Some(failures)` when this failure is the one that triggered the lockout
+    pub fn record_failure(&self, ip: IpAddr) -> Option<u32> {
+        let now = Instant::now();
+        let client = client(ip);
+        let mut entries = self.entries.lock().unwrap();
+
+        if !entries.map.contains_key(&client) {
+            if entries.map.len() >= MAX_ENTRIES {
+                entries.evict_oldest();
+            }
+            entries.order.push_back((client, now));
+        }
+
+        let entry = entries.map.entry(client).or_insert(Entry {
+            failures: 0,
+            window_start: now,
+            locked_until: None,
+            created: now,
+        });
+
+        if now.duration_since(entry.window_start) > self.window {
+            entry.failures = 0;
+            entry.window_start = now;
+        }
+
+        entry.failures += 1;
+
+        if entry.failures >= self.max_failures && entry.locked_until.is_none_or(|t| t <= now) {
+            entry.locked_until = Some(now + self.lockout);
+            return Some(entry.failures);
+        }
+
+        None
+    }
+
+    pub fn record_success(&self, ip: IpAddr) {
+        self.entries.lock().unwrap().map.remove(&client(ip));
+    }
+
+    pub fn lockout(&self) -> Duration {
+        self.lockout
+    }
+
+    fn sweep(&self) {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        let Entries { map, order } = &mut *entries;
+
+        map.retain(|_, e| !self.is_stale(e, now));
+        order.retain(|(client, created)| map.get(client).is_some_and(|entry| entry.created == *created));
+    }
+
+    fn is_stale(&self, entry: &Entry, now: Instant) -> bool {
+        let window_over = now.duration_since(entry.window_start) > self.window;
+        let unlocked = entry.locked_until.is_none_or(|t| t <= now);
+
+        window_over && unlocked
+    }
+}
+
+/// What failures are counted against: the IPv4 address, or the /64 of an IPv6 one
+fn client(ip: IpAddr) -> IpAddr {
+    match ip.to_canonical() {
+        IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from_bits(ip.to_bits() & !((1u128 << 64) - 1))),
+        ip => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7));
+
+    #[test]
+    fn locks_out_after_max_failures() {
+        let tracker = FailedAuthTracker::new(3, Duration::from_secs(60), Duration::from_secs(300), false);
+
+        assert_eq!(tracker.record_failure(CLIENT), None);
+        assert_eq!(tracker.record_failure(CLIENT), None);
+        assert!(tracker.locked_for(CLIENT).is_none());
+
+        assert_eq!(tracker.record_failure(CLIENT), Some(3));
+        assert!(tracker.locked_for(CLIENT).is_some_and(|left| left > Duration::from_secs(290)));
+
+        // only the failure that triggered it reports the lockout
+        assert_eq!(tracker.record_failure(CLIENT), None);
+        assert!(tracker.locked_for(IpAddr::V4(Ipv4Addr::LOCALHOST)).is_none());
+    }
+
+    #[test]
+    fn success_resets() {
+        let tracker = FailedAuthTracker::new(2, Duration::from_secs(60), Duration::from_secs(300), false);
+
+        assert_eq!(tracker.record_failure(CLIENT), None);
+        tracker.record_success(CLIENT);
+        assert_eq!(tracker.record_failure(CLIENT), None);
+    }
+
+    #[test]
+    fn window_and_lockout_expire() {
+        let tracker = FailedAuthTracker::new(2, Duration::from_millis(50), Duration::from_millis(50), false);
+
+        assert_eq!(tracker.record_failure(CLIENT), None);
+        std::thread::sleep(Duration::from_millis(60));
+        // the first failure fell out of the window
+        assert_eq!(tracker.record_failure(CLIENT), None);
+        assert_eq!(tracker.record_failure(CLIENT), Some(2));
+        assert!(tracker.locked_for(CLIENT).is_some());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(tracker.locked_for(CLIENT).is_none());
+    }
+
+    #[test]
+    fn client_ip() {
+        let peer: SocketAddr = "10.0.0.2:4000".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "198.51.100.1, 203.0.113.7".parse().unwrap());
+
+        let direct = FailedAuthTracker::new(3, Duration::from_secs(60), Duration::from_secs(300), false);
+        assert_eq!(direct.client_ip(&headers, peer), peer.ip());
+
+        // the last hop is the one our proxy appended, earlier ones are up to the client
+        let proxied = FailedAuthTracker::new(3, Duration::from_secs(60), Duration::from_secs(300), true);
+        assert_eq!(proxied.client_ip(&headers, peer), CLIENT);
+        assert_eq!(proxied.client_ip(&HeaderMap::new(), peer), peer.ip());
+    }
+
+    #[test]
+    fn ipv6_counts_per_64() {
+        let tracker = FailedAuthTracker::new(2, Duration::from_secs(60), Duration::from_secs(300), false);
+        let first: IpAddr = "2001:db8:1:2::1".parse().unwrap();
+        let neighbour: IpAddr = "2001:db8:1:2:ffff::9".parse().unwrap();
+        let other: IpAddr = "2001:db8:1:3::1".parse().unwrap();
+
+        assert_eq!(tracker.record_failure(first), None);
+        assert_eq!(tracker.record_failure(neighbour), Some(2));
+        assert!(tracker.locked_for(first).is_some());
+        assert!(tracker.locked_for(other).is_none());
+    }
+
+    #[test]
+    fn stays_within_the_cap() {
+        let tracker = FailedAuthTracker::new(3, Duration::from_secs(60), Duration::from_secs(300), false);
+
+        for n in 0..MAX_ENTRIES as u32 + 1 {
+            tracker.record_failure(IpAddr::V4(Ipv4Addr::from_bits(n)));
+        }
+
+        let entries = tracker.entries.lock().unwrap();
+        assert_eq!(entries.map.len(), MAX_ENTRIES);
+        // the oldest client made room for the newest
+        assert!(!entries.map.contains_key(&IpAddr::V4(Ipv4Addr::from_bits(0))));
+        assert!(entries.map.contains_key(&IpAddr::V4(Ipv4Addr::from_bits(MAX_ENTRIES as u32))));
+    }
+
+    #[test]
+    fn sweeps_stale_entries() {
+        let tracker = FailedAuthTracker::new(2, Duration::from_millis(50), Duration::from_millis(50), false);
+
+        assert_eq!(tracker.record_failure(CLIENT), None);
+        std::thread::sleep(Duration::from_millis(60));
+        tracker.sweep();
+
+        let entries = tracker.entries.lock().unwrap();
+        assert!(entries.map.is_empty() && entries.order.is_empty());
+    }
+}
@@ -0,0 +1,196 @@
+mod keys;
+mod lockout;
+mod signature;
+
+pub use keys::*;
+pub use lockout::*;
+pub use signature::*;
+
+use crate::api::ApiState;
+use crate::model::api_key::ApiScope;
+use axum::body::{to_bytes, Body};
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::net::SocketAddr;
+use tracing::{debug, warn};
+use utoipa::Modify;
+use utoipa::openapi::OpenApi;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+
+pub struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "api_key",
+                SecurityScheme::ApiKey(
+                    ApiKey::Header(
+                        ApiKeyValue::new("x-api-key"),
+                    ),
+                ),
+            );
+            components.add_security_scheme(
+                "signature_key_id",
+                SecurityScheme::ApiKey(
+                    ApiKey::Header(
+                        ApiKeyValue::with_description(
+                            "x-key-id",
+                            "ID of the API key whose signing secret produced X-Signature",
+                        ),
+                    ),
+                ),
+            );
+            components.add_security_scheme(
+                "signature_timestamp",
+                SecurityScheme::ApiKey(
+                    ApiKey::Header(
+                        ApiKeyValue::with_description(
+                            "x-timestamp",
+                            "Unix timestamp (seconds) of the request, must be within the replay window",
+                        ),
+                    ),
+                ),
+            );
+            components.add_security_scheme(
+                "signature",
+                SecurityScheme::ApiKey(
+                    ApiKey::Header(
+                        ApiKeyValue::with_description(
+                            "x-signature",
+                            "hex(HMAC-SHA256(signing_secret, METHOD + \"\\n\" + PATH_AND_QUERY + \"\\n\" \
+                            + X-Timestamp + \"\\n\" + hex(SHA256(body)))). Each signature is accepted once",
+                        ),
+                    ),
+                ),
+            );
+        }
+    }
+}
+
+/// Signed request bodies are buffered to be hashed, so they get a cap
+const MAX_SIGNED_BODY: usize = 1024 * 1024;
+
+pub async fn auth_middleware(
+    State(state): State<ApiState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let client_ip = state.auth_failures.client_ip(&headers, peer);
+
+    if let Some(retry_after) = state.auth_failures.locked_for(client_ip) {
+        return too_many_attempts(retry_after.as_secs().max(1));
+    }
+
+    let principal = if headers.contains_key("x-signature") {
+        let (principal, buffered) = match verify_signed(&state, &headers, request).await {
+            Ok(verified) => verified,
+            Err(response) => return response,
+        };
+        request = buffered;
+        principal
+    } else {
+        match header_str(&headers, "x-api-key") {
+            Some(key) => state.api_keys.authenticate(key).await,
+            None => None,
+        }
+    };
+
+    match principal {
+        Some(principal) => {
+            state.auth_failures.record_success(client_ip);
+            request.extensions_mut().insert(principal);
+            next.run(request).await
+        }
+        None => {
+            if let Some(failures) = state.auth_failures.record_failure(client_ip) {
+                let lockout_secs = state.auth_failures.lockout().as_secs();
+
+                warn!(
+                    event = "auth_lockout",
+                    client_ip = %client_ip,
+                    failures,
+                    lockout_secs,
+                    "Client locked out after repeated failed authentication"
+                );
+
+                return too_many_attempts(lockout_secs);
+            }
+
+            StatusCode::UNAUTHORIZED.into_response()
+        }
+    }
+}
+
+/// Buffers the body to check the signature and hands back an equivalent request
+async fn verify_signed(
+    state: &ApiState,
+    headers: &HeaderMap,
+    request: Request,
+) -> Result<(Option<Principal>, Request), Response> {
+    let (parts, body) = request.into_parts();
+
+    let body = to_bytes(body, MAX_SIGNED_BODY).await
+        .map_err(|_| StatusCode::PAYLOAD_TOO_LARGE.into_response())?;
+
+    let principal = match (
+        header_str(headers, "x-key-id"),
+        header_str(headers, "x-timestamp"),
+        header_str(headers, "x-signature"),
+    ) {
+        (Some(key_id), Some(timestamp), Some(signature)) => {
+            match state.api_keys.signing_key(key_id).await {
+                Some((principal, secret)) => {
+                    let path_and_query = parts.uri.path_and_query()
+                        .map(|pq| pq.as_str())
+                        .unwrap_or_else(|| parts.uri.path());
+
+                    match state.signatures.verify(&secret, parts.method.as_str(), path_and_query,
+                                                  timestamp, &body, signature) {
+                        Ok(()) => Some(principal),
+                        Err(e) => {
+                            debug!(key_id, reason = ?e, "Rejected signed request");
+                            None
+                        }
+                    }
+                }
+                None => None,
+            }
+        }
+        _ => None,
+    };
+
+    Ok((principal, Request::from_parts(parts, Body::from(body))))
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn too_many_attempts(retry_after_secs: u64) -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, retry_after_secs.to_string())],
+    ).into_response()
+}
+
+/// Must run after `auth_middleware`, i.e. be layered on the route itself.
+pub async fn scope_middleware(
+    State(required): State<ApiScope>,
+    request: Request,
+    next: Next,
+) -> Result<impl IntoResponse, StatusCode> {
+    let allowed = request.extensions()
+        .get::<Principal>()
+        .is_some_and(|principal| principal.has_scope(required));
+
+    if !allowed {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    Ok(next.run(request).await)
+}
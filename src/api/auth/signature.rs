@@ -0,0 +1,172 @@
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Nonces are swept once the cache grows past this many entries
+const SWEEP_THRESHOLD: usize = 10_000;
+
+#[derive(Debug)]
+pub enum SignatureError {
+    Malformed,
+    Expired,
+    Replayed,
+    Mismatch,
+}
+
+/// Verifies `X-Signature` requests:
+/// `hex(HMAC-SHA256(secret, "{METHOD}\n{PATH_AND_QUERY}\n{TIMESTAMP}\n{hex(SHA256(body))}"))`.
+/// Each signature is accepted once; the nonce cache only has to remember it
+/// for as long as its timestamp stays inside the replay window.
+pub struct SignatureVerifier {
+    max_skew: Duration,
+    nonces: Mutex<HashMap<String, Instant>>,
+}
+
+impl SignatureVerifier {
+    pub fn new(max_skew: Duration) -> Self {
+        Self {
+            max_skew,
+            nonces: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn verify(
+        &self,
+        secret: &str,
+        method: &str,
+        path_and_query: &str,
+        timestamp: &str,
+        body: &[u8],
+        signature: &str,
+    ) -> Result<(), SignatureError> {
+        let ts = timestamp.parse::<i64>().map_err(|_| SignatureError::Malformed)?;
+        let skew = (chrono::Utc::now().timestamp() - ts).unsigned_abs();
+        if skew > self.max_skew.as_secs() {
+            return Err(SignatureError::Expired);
+        }
+
+        let signature_bytes = hex::decode(signature).map_err(|_| SignatureError::Malformed)?;
+
+        let canonical = format!("{}\n{}\n{}\n{}", method, path_and_query, timestamp,
+                                hex::encode(Sha256::digest(body)));
+
+        let mut mac = HmacSha256::new_from_slice(secret.as_bytes())
+            .map_err(|_| SignatureError::Malformed)?;
+        mac.update(canonical.as_bytes());
+        mac.verify_slice(&signature_bytes).map_err(|_| SignatureError::Mismatch)?;
+
+        self.remember(signature)
+    }
+
+    fn remember(&self, signature: &str) -> Result<(), SignatureError> {
+        let now = Instant::now();
+        // a signature is valid for max_skew on either side of its timestamp,
+        // anything older would be rejected as expired anyway
+        let ttl = self.max_skew * 2;
+
+        let mut nonces = self.nonces.lock().unwrap();
+
+        if nonces.len() >= SWEEP_THRESHOLD {
+            nonces.retain(|_, seen_at| now.duration_since(*seen_at) <= ttl);
+        }
+
+        let key = signature.to_lowercase();
+        if nonces.get(&key).is_some_and(|seen_at| now.duration_since(*seen_at) <= ttl) {
+            return Err(SignatureError::Replayed);
+        }
+
+        nonces.insert(key, now);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "nk3s_test";
+
+    fn verifier() -> SignatureVerifier {
+        SignatureVerifier::new(Duration::from_secs(300))
+    }
+
+    fn now() -> String {
+        chrono::Utc::now().timestamp().to_string()
+    }
+
+    /// Signs the way clients are told to, independently of `verify`
+    fn sign(method: &str, path_and_query: &str, timestamp: &str, body: &[u8]) -> String {
+        let canonical = format!("{}\n{}\n{}\n{}", method, path_and_query, timestamp,
+                                hex::encode(Sha256::digest(body)));
+
+        let mut mac = HmacSha256::new_from_slice(SECRET.as_bytes()).unwrap();
+        mac.update(canonical.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    #[test]
+    fn canonical_string() {
+        let verifier = verifier();
+        let timestamp = now();
+        let body = br#"{"amount":"10"}"#;
+        let signature = sign("POST", "/invoice?dry=1", &timestamp, body);
+
+        // every part of the canonical string is covered
+        assert!(matches!(verifier.verify(SECRET, "GET", "/invoice?dry=1", &timestamp, body, &signature),
+                         Err(SignatureError::Mismatch)));
+        assert!(matches!(verifier.verify(SECRET, "POST", "/invoice", &timestamp, body, &signature),
+                         Err(SignatureError::Mismatch)));
+        assert!(matches!(verifier.verify(SECRET, "POST", "/invoice?dry=1", &timestamp, b"{}", &signature),
+                         Err(SignatureError::Mismatch)));
+        assert!(matches!(verifier.verify("other", "POST", "/invoice?dry=1", &timestamp, body, &signature),
+                         Err(SignatureError::Mismatch)));
+
+        assert!(verifier.verify(SECRET, "POST", "/invoice?dry=1", &timestamp, body, &signature).is_ok());
+    }
+
+    #[test]
+    fn skew_window() {
+        let verifier = verifier();
+        let now = chrono::Utc::now().timestamp();
+
+        for (offset, accepted) in [(-310, false), (-290, true), (290, true), (310, false)] {
+            let timestamp = (now + offset).to_string();
+            let signature = sign("GET", "/invoice", &timestamp, b"");
+            let result = verifier.verify(SECRET, "GET", "/invoice", &timestamp, b"", &signature);
+
+            match accepted {
+                true => assert!(result.is_ok(), "{} seconds off", offset),
+                false => assert!(matches!(result, Err(SignatureError::Expired)), "{} seconds off", offset),
+            }
+        }
+    }
+
+    #[test]
+    fn nonce_replay() {
+        let verifier = verifier();
+        let timestamp = now();
+        let signature = sign("DELETE", "/invoice/1", &timestamp, b"");
+
+        assert!(verifier.verify(SECRET, "DELETE", "/invoice/1", &timestamp, b"", &signature).is_ok());
+        assert!(matches!(verifier.verify(SECRET, "DELETE", "/invoice/1", &timestamp, b"", &signature),
+                         Err(SignatureError::Replayed)));
+        // hex case doesn't make it a new signature
+        assert!(matches!(verifier.verify(SECRET, "DELETE", "/invoice/1", &timestamp, b"",
+                                         &signature.to_uppercase()),
+                         Err(SignatureError::Replayed)));
+    }
+
+    #[test]
+    fn malformed() {
+        let verifier = verifier();
+
+        assert!(matches!(verifier.verify(SECRET, "GET", "/", "yesterday", b"", "00"),
+                         Err(SignatureError::Malformed)));
+        assert!(matches!(verifier.verify(SECRET, "GET", "/", &now(), b"", "not-hex"),
+                         Err(SignatureError::Malformed)));
+    }
+}
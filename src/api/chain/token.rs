@@ -6,7 +6,8 @@ use axum::Json;
 use std::sync::Arc;
 use axum::http::StatusCode;
 use necko3_core::db::DatabaseAdapter;
-use crate::model::{ApiError, ApiResponse, Empty};
+use crate::model::{ApiError, ApiResponse, Empty, PaymentTolerance};
+use crate::tolerance::{self, ToleranceStore};
 
 #[utoipa::path(
     post,
@@ -102,4 +103,87 @@ pub async fn delete_token(
         .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
 
     Ok((StatusCode::OK, Json(ApiResponse::ok())))
-}
\ No newline at end of file
+}
+#[utoipa::path(
+    get,
+    path = "/chain/{name}/token/{symbol}/tolerance",
+    params(
+        ("name" = String, Path, description = "Network (chain) name"),
+        ("symbol" = String, Path, description = "Token symbol (e.g. USDC)")
+    ),
+    responses(
+        (status = 200, description = "Default payment tolerance of the token", body = ApiResponse<PaymentTolerance>),
+        (status = 404, description = "No tolerance set for this token", body = ApiResponse<Empty>),
+        (status = 500, description = "Server Error", body = ApiResponse<Empty>)
+    ),
+    tag = "Tokens"
+)]
+pub async fn get_token_tolerance(
+    State(tolerances): State<Arc<ToleranceStore>>,
+    Path((name, symbol)): Path<(String, String)>,
+) -> Result<(StatusCode, Json<ApiResponse<PaymentTolerance>>), ApiError> {
+    let tolerance = tolerances.get(&name, &symbol).await
+        .ok_or_else(|| ApiError::NotFound("No tolerance set for this token".into()))?;
+
+    Ok((StatusCode::OK, Json(ApiResponse::success(tolerance))))
+}
+
+#[utoipa::path(
+    put,
+    path = "/chain/{name}/token/{symbol}/tolerance",
+    params(
+        ("name" = String, Path, description = "Network (chain) name"),
+        ("symbol" = String, Path, description = "Token symbol (e.g. USDC)")
+    ),
+    request_body = PaymentTolerance,
+    responses(
+        (status = 200, description = "Tolerance set. Applies to invoices created from now on", body = ApiResponse<Empty>),
+        (status = 400, description = "Invalid tolerance", body = ApiResponse<Empty>),
+        (status = 404, description = "Network (chain) or token not found", body = ApiResponse<Empty>),
+        (status = 500, description = "Server Error", body = ApiResponse<Empty>)
+    ),
+    tag = "Tokens"
+)]
+pub async fn set_token_tolerance(
+    State(state): State<Arc<AppState>>,
+    State(tolerances): State<Arc<ToleranceStore>>,
+    Path((name, symbol)): Path<(String, String)>,
+    Json(payload): Json<PaymentTolerance>,
+) -> Result<(StatusCode, Json<ApiResponse<String>>), ApiError> {
+    state.db.get_token(&name, &symbol).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?
+        .ok_or_else(|| ApiError::NotFound("Token or chain not found".into()))?;
+
+    tolerance::validate(&payload)
+        .map_err(|e| ApiError::BadRequest(format!("Invalid tolerance: {}", e)))?;
+
+    tolerances.set(&name, &symbol, payload).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+
+    Ok((StatusCode::OK, Json(ApiResponse::ok())))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/chain/{name}/token/{symbol}/tolerance",
+    params(
+        ("name" = String, Path, description = "Network (chain) name"),
+        ("symbol" = String, Path, description = "Token symbol (e.g. USDC)")
+    ),
+    responses(
+        (status = 200, description = "Tolerance removed", body = ApiResponse<Empty>),
+        (status = 404, description = "No tolerance set for this token", body = ApiResponse<Empty>),
+        (status = 500, description = "Server Error", body = ApiResponse<Empty>)
+    ),
+    tag = "Tokens"
+)]
+pub async fn delete_token_tolerance(
+    State(tolerances): State<Arc<ToleranceStore>>,
+    Path((name, symbol)): Path<(String, String)>,
+) -> Result<(StatusCode, Json<ApiResponse<String>>), ApiError> {
+    tolerances.remove(&name, &symbol).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?
+        .ok_or_else(|| ApiError::NotFound("No tolerance set for this token".into()))?;
+
+    Ok((StatusCode::OK, Json(ApiResponse::ok())))
+}
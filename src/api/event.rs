@@ -0,0 +1,161 @@
+use crate::api::auth::Principal;
+use crate::event_log::{decode_cursor, encode_cursor, EventLog};
+use crate::events::{Event, EventBus};
+use crate::model::api_key::ApiScope;
+use crate::model::{ApiError, ApiResponse, Empty, EventFilterParams, EventLogPage, EventLogParams};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::{Extension, Json};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{Instant, MissedTickBehavior};
+
+/// Keeps idle connections (and proxies in between) alive
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+const DEFAULT_EVENT_LIMIT: u32 = 100;
+const MAX_EVENT_LIMIT: u32 = 500;
+const MAX_EVENT_WAIT: u64 = 60;
+
+#[utoipa::path(
+    get,
+    path = "/event",
+    params(
+        EventLogParams
+    ),
+    responses(
+        (status = 200, description = "Events after the cursor, oldest first. The same payloads \
+            webhooks carry", body = ApiResponse<EventLogPage>),
+        (status = 400, description = "Malformed cursor", body = ApiResponse<Empty>),
+        (status = 410, description = "Events after the cursor were pruned already, start over \
+            without `after`", body = ApiResponse<Empty>),
+        (status = 500, description = "Server error", body = ApiResponse<Empty>)
+    ),
+    tag = "Events"
+)]
+pub async fn get_events(
+    State(log): State<Arc<EventLog>>,
+    Query(params): Query<EventLogParams>,
+) -> Result<(StatusCode, Json<ApiResponse<EventLogPage>>), ApiError> {
+    let after = match &params.after {
+        Some(cursor) => decode_cursor(cursor)
+            .ok_or_else(|| ApiError::BadRequest("Malformed cursor".into()))?,
+        None => 0,
+    };
+
+    if params.after.is_some() && log.is_pruned(after).await {
+        return Err(ApiError::Gone("Events after this cursor are past the retention".into()));
+    }
+
+    let limit = params.limit.unwrap_or(DEFAULT_EVENT_LIMIT).clamp(1, MAX_EVENT_LIMIT);
+    let wait = Duration::from_secs(params.wait.unwrap_or_default().min(MAX_EVENT_WAIT));
+
+    let (entries, has_more) = log.after(after, limit as usize, wait).await;
+
+    let next_cursor = encode_cursor(entries.last().map(|entry| entry.sequence).unwrap_or(after));
+
+    Ok((StatusCode::OK, Json(ApiResponse::success(EventLogPage {
+        items: entries.into_iter().map(Into::into).collect(),
+        next_cursor,
+        has_more,
+    }))))
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum FeedMessage<'a> {
+    /// sent on connect and whenever the client replaces the filter
+    Subscribed { filter: &'a EventFilterParams },
+    Event(&'a Event),
+    Heartbeat { at: DateTime<Utc> },
+    /// the client read too slowly and `missed` events were dropped
+    Lagged { missed: u64 },
+    Error { message: String },
+}
+
+#[utoipa::path(
+    get,
+    path = "/event/ws",
+    params(
+        EventFilterParams
+    ),
+    responses(
+        (status = 101, description = "WebSocket of JSON text messages tagged by `type`: \
+            `subscribed` (current filter), `event` (kind, invoice, payment or webhook state), \
+            `heartbeat` every 30 seconds, `lagged` and `error`. Sending a filter object as \
+            a text message replaces the one from the query. Payment and webhook events also \
+            need the payments:read and webhooks:read scopes"),
+        (status = 400, description = "Not a WebSocket upgrade request")
+    ),
+    tag = "Events"
+)]
+pub async fn event_feed(
+    State(events): State<Arc<EventBus>>,
+    Extension(principal): Extension<Principal>,
+    Query(filter): Query<EventFilterParams>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    ws.on_upgrade(move |socket| feed(socket, events, principal, filter))
+}
+
+async fn feed(mut socket: WebSocket, events: Arc<EventBus>, principal: Principal,
+              mut filter: EventFilterParams) {
+    let mut receiver = events.subscribe();
+
+    let mut heartbeat = tokio::time::interval_at(Instant::now() + HEARTBEAT_INTERVAL,
+                                                 HEARTBEAT_INTERVAL);
+    heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let mut outgoing = text(&FeedMessage::Subscribed { filter: &filter });
+
+    loop {
+        if let Some(text) = outgoing.take()
+            && socket.send(Message::text(text)).await.is_err() {
+            break;
+        }
+
+        outgoing = tokio::select! {
+            received = receiver.recv() => match received {
+                Ok(event) if visible(&principal, &event) && filter.matches(&event) => {
+                    text(&FeedMessage::Event(&event))
+                }
+                Ok(_) => None,
+                Err(RecvError::Lagged(missed)) => text(&FeedMessage::Lagged { missed }),
+                Err(RecvError::Closed) => break,
+            },
+            _ = heartbeat.tick() => text(&FeedMessage::Heartbeat { at: Utc::now() }),
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Text(raw))) => match serde_json::from_str(raw.as_str()) {
+                    Ok(updated) => {
+                        filter = updated;
+                        text(&FeedMessage::Subscribed { filter: &filter })
+                    }
+                    Err(e) => text(&FeedMessage::Error { message: format!("Bad filter: {}", e) }),
+                },
+                // pings are answered by axum itself
+                Some(Ok(Message::Binary(_) | Message::Ping(_) | Message::Pong(_))) => None,
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+            },
+        };
+    }
+}
+
+/// Payment and webhook events are only sent to keys that may read those
+fn visible(principal: &Principal, event: &Event) -> bool {
+    if event.kind.is_webhook() {
+        principal.has_scope(ApiScope::WebhooksRead)
+    } else if event.payment.is_some() {
+        principal.has_scope(ApiScope::PaymentsRead)
+    } else {
+        true
+    }
+}
+
+fn text(message: &FeedMessage) -> Option<String> {
+    serde_json::to_string(message).ok()
+}
@@ -0,0 +1,203 @@
+use crate::store::JsonStore;
+use axum::http::{HeaderMap, StatusCode};
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+use tracing::warn;
+
+const MAX_KEY_LENGTH: usize = 255;
+
+/// How often records past the ttl are dropped
+const PRUNE_INTERVAL: Duration = Duration::from_secs(600);
+
+/// How long a request may hold its key before `begin` takes it as abandoned (e.g. after a crash).
+/// Creating an invoice takes a few seconds at most
+const IN_PROGRESS_LEASE: TimeDelta = TimeDelta::seconds(60);
+
+#[derive(Clone, Serialize, Deserialize)]
+struct IdempotencyRecord {
+    request_hash: String,
+    /// `None` while the original request is still being processed
+    status: Option<u16>,
+    response: Option<serde_json::Value>,
+    created_at: DateTime<Utc>,
+}
+
+pub enum Idempotency<T> {
+    /// First time this key is seen, go ahead and call `complete`/`abandon` afterwards
+    New,
+    /// Same key and same request, here is what we answered the first time
+    Replay(StatusCode, T),
+    /// Same key but a different request body
+    Mismatch,
+    /// Same key, the original request hasn't finished yet
+    InProgress,
+}
+
+/// Remembers responses by `Idempotency-Key` so client retries don't create duplicates.
+/// Keys are namespaced per API key, records live for `ttl`.
+pub struct IdempotencyStore {
+    ttl: TimeDelta,
+    records: JsonStore<IdempotencyRecord>,
+}
+
+impl IdempotencyStore {
+    pub async fn open(path: PathBuf, ttl: TimeDelta) -> anyhow::Result<Self> {
+        let store = Self {
+            ttl,
+            records: JsonStore::open(path).await?,
+        };
+        store.prune().await?;
+
+        // whatever was in progress died with the previous run
+        store.records.retain(|r| r.status.is_some()).await?;
+
+        Ok(store)
+    }
+
+    /// Drops expired records every `PRUNE_INTERVAL`. Until then `begin` treats them as unseen
+    pub fn spawn_pruning(self: Arc<Self>) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(PRUNE_INTERVAL);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // the first tick completes right away, `open` just pruned
+            ticker.tick().await;
+
+            loop {
+                ticker.tick().await;
+
+                if let Err(e) = self.prune().await {
+                    warn!(error = %e, "Failed to prune idempotency keys");
+                }
+            }
+        });
+    }
+
+    async fn prune(&self) -> anyhow::Result<()> {
+        let cutoff = Utc::now() - self.ttl;
+        self.records.retain(|r| r.created_at > cutoff).await
+    }
+
+    pub async fn begin<T, R>(&self, namespace: &str, key: &str, request: &R) -> anyhow::Result<Idempotency<T>>
+    where
+        T: DeserializeOwned,
+        R: Serialize,
+    {
+        let record = IdempotencyRecord {
+            request_hash: hex::encode(Sha256::digest(serde_json::to_vec(request)?)),
+            status: None,
+            response: None,
+            created_at: Utc::now(),
+        };
+        let request_hash = record.request_hash.clone();
+
+        let id = record_id(namespace, key);
+        let Some(existing) = self.records.try_insert(id.clone(), record.clone()).await? else {
+            return Ok(Idempotency::New);
+        };
+
+        // expired but not pruned yet, or abandoned mid-request: the key is free again.
+        // Swapped in one step so only one request gets it
+        let now = Utc::now();
+        if self.is_free(&existing, now) {
+            let mut replaced = false;
+            self.records.update(&id, |r| if self.is_free(r, now) {
+                *r = record;
+                replaced = true;
+            }).await?;
+
+            // otherwise a concurrent request with the same key got it just now
+            return Ok(match replaced {
+                true => Idempotency::New,
+                false => Idempotency::InProgress,
+            });
+        }
+
+        if existing.request_hash != request_hash {
+            return Ok(Idempotency::Mismatch);
+        }
+
+        match (existing.status, existing.response) {
+            (Some(status), Some(response)) => Ok(Idempotency::Replay(
+                StatusCode::from_u16(status)?,
+                serde_json::from_value(response)?,
+            )),
+            _ => Ok(Idempotency::InProgress),
+        }
+    }
+
+    fn is_free(&self, record: &IdempotencyRecord, now: DateTime<Utc>) -> bool {
+        record.created_at <= now - self.ttl
+            || (record.status.is_none() && record.created_at <= now - IN_PROGRESS_LEASE)
+    }
+
+    pub async fn complete<T: Serialize>(&self, namespace: &str, key: &str,
+                                        status: StatusCode, response: &T) -> anyhow::Result<()> {
+        let response = serde_json::to_value(response)?;
+
+        self.records.update(&record_id(namespace, key), |r| {
+            r.status = Some(status.as_u16());
+            r.response = Some(response);
+        }).await?;
+
+        Ok(())
+    }
+
+    /// Forgets the key after a failed request so the client can retry it
+    pub async fn abandon(&self, namespace: &str, key: &str) -> anyhow::Result<()> {
+        self.records.remove(&record_id(namespace, key)).await?;
+        Ok(())
+    }
+}
+
+/// Reads `Idempotency-Key`. `Err` carries the reason the header is unusable
+pub fn idempotency_key(headers: &HeaderMap) -> Result<Option<String>, String> {
+    let Some(value) = headers.get("idempotency-key") else {
+        return Ok(None);
+    };
+
+    let key = value.to_str()
+        .map_err(|_| "Idempotency-Key must be visible ASCII".to_owned())?
+        .trim();
+
+    if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+        return Err(format!("Idempotency-Key must be 1-{} characters long", MAX_KEY_LENGTH));
+    }
+
+    Ok(Some(key.to_owned()))
+}
+
+fn record_id(namespace: &str, key: &str) -> String {
+    format!("{}:{}", namespace, key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn frees_unfinished_keys_on_open() {
+        let path = std::env::temp_dir().join(format!("necko3-idempotency-{}", uuid::Uuid::new_v4()))
+            .join("keys.jsonl");
+        let ttl = TimeDelta::hours(24);
+
+        let store = IdempotencyStore::open(path.clone(), ttl).await.unwrap();
+        assert!(matches!(store.begin::<u32, _>("root", "a", &1).await.unwrap(), Idempotency::New));
+        assert!(matches!(store.begin::<u32, _>("root", "b", &1).await.unwrap(), Idempotency::New));
+        store.complete("root", "b", StatusCode::CREATED, &7).await.unwrap();
+        assert!(matches!(store.begin::<u32, _>("root", "a", &1).await.unwrap(), Idempotency::InProgress));
+        drop(store);
+
+        let store = IdempotencyStore::open(path.clone(), ttl).await.unwrap();
+        assert!(matches!(store.begin::<u32, _>("root", "a", &1).await.unwrap(), Idempotency::New));
+        assert!(matches!(store.begin::<u32, _>("root", "b", &1).await.unwrap(),
+                         Idempotency::Replay(StatusCode::CREATED, 7)));
+
+        tokio::fs::remove_dir_all(path.parent().unwrap()).await.unwrap();
+    }
+}
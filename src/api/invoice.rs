@@ -1,77 +1,481 @@
-use crate::model::core::{InvoiceFilterSchema, InvoiceSchema, PaginationParams};
-use crate::model::{ApiError, ApiResponse, CreateInvoiceReq, Empty, PaginatedVecPage};
+use crate::api::auth::Principal;
+use crate::api::idempotency::{idempotency_key, Idempotency, IdempotencyStore};
+use crate::api::webhook_endpoint::{DEFAULT_SECRET_GRACE_PERIOD, MAX_SECRET_GRACE_PERIOD};
+use crate::delivery::UrlGuard;
+use crate::model::core::{InvoiceFilterSchema, InvoiceSchema, InvoiceStatusSchema, PaginationParams};
+use crate::extras::{InvoiceExtras, InvoiceExtrasStore};
+use crate::model::{ApiError, ApiResponse, CreateInvoiceReq, Empty, ExtendInvoiceReq, FiatQuote,
+                   InvoiceModel, InvoiceView, PaginatedVecPage, PendingInvoiceModel, RotateInvoiceSecretReq};
+use crate::pending::{Cancellation, PendingInvoice, PendingInvoiceStore, QuotedOption};
+use crate::rates::{fiat_to_token, parse_fiat, RateProvider};
+use crate::tolerance::{self, InvoiceTolerance, ToleranceStore};
+use crate::watcher::WatchList;
 use axum::extract::{Path, Query, State};
-use axum::http::StatusCode;
-use axum::Json;
-use chrono::TimeDelta;
+use axum::http::{HeaderMap, StatusCode};
+use axum::{Extension, Json};
+use chrono::{DateTime, TimeDelta, Utc};
 use necko3_core::chain::BlockchainAdapter;
 use necko3_core::db::DatabaseAdapter;
-use necko3_core::deps::{parse_units, U256};
-use necko3_core::model::{Invoice, InvoiceStatus};
+use necko3_core::deps::{format_units, parse_units, U256};
+use necko3_core::model::{Invoice, InvoiceFilter, InvoiceStatus, Pagination};
 use necko3_core::state::AppState;
+use std::collections::HashSet;
 use std::sync::Arc;
 
 #[utoipa::path(
     post,
     path = "/invoice",
     request_body = CreateInvoiceReq,
+    params(
+        ("Idempotency-Key" = Option<String>, Header,
+            description = "Retries with the same key and body within 24h return the original invoice")
+    ),
     responses(
-        (status = 201, description = "Invoice created", body = ApiResponse<InvoiceSchema>),
-        (status = 400, description = "Bad Request", body = ApiResponse<Empty>),
+        (status = 201, description = "Invoice created. With `options` the body is a PendingInvoiceModel instead", body = ApiResponse<InvoiceSchema>),
+        (status = 400, description = "Bad Request (e.g. no rate for the requested fiat currency, bad tolerance, \
+            webhook_url not https or pointing at a private address)", body = ApiResponse<Empty>),
         (status = 404, description = "Chain/token decimals not found", body = ApiResponse<Empty>),
+        (status = 409, description = "order_id already used, or Idempotency-Key reused with a different body / still in progress", body = ApiResponse<Empty>),
         (status = 500, description = "Server Error", body = ApiResponse<Empty>)
     ),
     tag = "Invoices"
 )]
 pub async fn create_invoice(
     State(state): State<Arc<AppState>>,
+    State(stores): State<InvoiceStores>,
+    State(idempotency): State<Arc<IdempotencyStore>>,
+    Extension(principal): Extension<Principal>,
+    headers: HeaderMap,
     Json(payload): Json<CreateInvoiceReq>,
-) -> Result<(StatusCode, Json<ApiResponse<Invoice>>), ApiError>  {
-    let blockchain = state.db.get_chain(&payload.network).await
+) -> Result<(StatusCode, Json<ApiResponse<InvoiceView>>), ApiError> {
+    let Some(key) = idempotency_key(&headers).map_err(ApiError::BadRequest)? else {
+        return issue_invoice(&state, &stores, payload).await;
+    };
+
+    let namespace = principal.key_id.as_deref().unwrap_or("root");
+
+    match idempotency.begin(namespace, &key, &payload).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))? {
+        Idempotency::New => {}
+        Idempotency::Replay(status, invoice) => {
+            return Ok((status, Json(ApiResponse::success(invoice))));
+        }
+        Idempotency::Mismatch => {
+            return Err(ApiError::Conflict(
+                "Idempotency-Key was already used with a different request".into()));
+        }
+        Idempotency::InProgress => {
+            return Err(ApiError::Conflict(
+                "A request with this Idempotency-Key is still being processed".into()));
+        }
+    }
+
+    // in its own task, so a client hanging up can't leave the key claimed without an answer
+    let namespace = namespace.to_owned();
+    tokio::spawn(async move {
+        match issue_invoice(&state, &stores, payload).await {
+            Ok((status, Json(response))) => {
+                if let Some(invoice) = &response.data {
+                    idempotency.complete(&namespace, &key, status, invoice).await
+                        .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+                }
+                Ok((status, Json(response)))
+            }
+            Err(e) => {
+                idempotency.abandon(&namespace, &key).await
+                    .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+                Err(e)
+            }
+        }
+    }).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?
+}
+
+/// Biggest accepted `metadata`, serialized
+const MAX_METADATA_SIZE: usize = 16 * 1024;
+
+/// Most (network, token) pairs a single invoice may offer
+const MAX_OPTIONS: usize = 16;
+
+/// seconds, used when the request has no `expire_after`
+const DEFAULT_EXPIRE_AFTER: u64 = 900;
+
+/// seconds, longest accepted `expire_after` (30 days)
+const MAX_EXPIRE_AFTER: u64 = 30 * 24 * 60 * 60;
+
+/// The requested lifetime of an invoice, or the default one
+fn expire_after(requested: Option<u64>) -> Result<TimeDelta, String> {
+    let seconds = requested.unwrap_or(DEFAULT_EXPIRE_AFTER);
+
+    if seconds > MAX_EXPIRE_AFTER {
+        return Err(format!("expire_after must not exceed {} seconds", MAX_EXPIRE_AFTER));
+    }
+
+    Ok(TimeDelta::seconds(seconds as i64))
+}
+
+/// Backend services invoice creation needs besides the core state
+#[derive(Clone)]
+pub struct InvoiceStores {
+    pub extras: Arc<InvoiceExtrasStore>,
+    pub pending: Arc<PendingInvoiceStore>,
+    pub rates: Arc<dyn RateProvider>,
+    pub tolerances: Arc<ToleranceStore>,
+    pub url_guard: Arc<UrlGuard>,
+    pub watch_list: Arc<WatchList>,
+}
+
+async fn issue_invoice(
+    state: &AppState,
+    stores: &InvoiceStores,
+    payload: CreateInvoiceReq,
+) -> Result<(StatusCode, Json<ApiResponse<InvoiceView>>), ApiError> {
+    validate_pricing(&payload)?;
+
+    if let Some(metadata) = &payload.metadata {
+        if !metadata.is_object() {
+            return Err(ApiError::BadRequest("metadata must be a JSON object".into()));
+        }
+
+        if metadata.to_string().len() > MAX_METADATA_SIZE {
+            return Err(ApiError::BadRequest(format!("metadata must not exceed {} bytes",
+                                                    MAX_METADATA_SIZE)));
+        }
+    }
+
+    expire_after(payload.expire_after).map_err(ApiError::BadRequest)?;
+
+    if payload.order_id.as_ref().is_some_and(|order_id| order_id.trim().is_empty()) {
+        return Err(ApiError::BadRequest("order_id must not be empty".into()));
+    }
+
+    if let Some(tolerance) = &payload.tolerance {
+        tolerance::validate(tolerance)
+            .map_err(|e| ApiError::BadRequest(format!("Invalid tolerance: {}", e)))?;
+    }
+
+    if let Some(url) = &payload.webhook_url {
+        stores.url_guard.check(url).await
+            .map_err(|e| ApiError::BadRequest(format!("Invalid webhook_url: {}", e)))?;
+    }
+
+    let invoice_id = uuid::Uuid::new_v4().to_string();
+
+    if let Some(order_id) = &payload.order_id
+        && !stores.extras.reserve_order(order_id, &invoice_id).await {
+        return Err(ApiError::Conflict(format!("Invoice for order '{}' already exists", order_id)));
+    }
+
+    let result = match payload.options.is_some() {
+        true => add_pending_invoice(state, stores, invoice_id.clone(), payload).await,
+        false => add_invoice(state, stores, invoice_id.clone(), payload).await,
+    };
+
+    // nothing was created, so nothing may keep the order_id (or the extras) around either
+    if result.is_err() {
+        stores.extras.remove(&invoice_id).await
+            .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+    }
+
+    result
+}
+
+/// Checks that the request names either one network + token or a list of options,
+/// and prices every one of them exactly one way
+fn validate_pricing(payload: &CreateInvoiceReq) -> Result<(), ApiError> {
+    let fiat = match (&payload.fiat_amount, &payload.fiat_currency) {
+        (Some(fiat_amount), Some(_)) => {
+            parse_fiat(fiat_amount)
+                .map_err(|e| ApiError::BadRequest(format!("Invalid fiat amount: {}", e)))?;
+            true
+        }
+        (None, None) => false,
+        _ => return Err(ApiError::BadRequest(
+            "fiat_amount and fiat_currency must be given together".into())),
+    };
+
+    if fiat && payload.amount.is_some() {
+        return Err(ApiError::BadRequest(
+            "Specify either amount or fiat_amount + fiat_currency, not both".into()));
+    }
+
+    let Some(options) = &payload.options else {
+        if payload.network.is_none() || payload.token.is_none() {
+            return Err(ApiError::BadRequest("Specify network and token, or options".into()));
+        }
+
+        if !fiat && payload.amount.is_none() {
+            return Err(ApiError::BadRequest(
+                "Specify amount, or fiat_amount together with fiat_currency".into()));
+        }
+
+        return Ok(());
+    };
+
+    if payload.network.is_some() || payload.token.is_some() {
+        return Err(ApiError::BadRequest(
+            "network and token can't be combined with options".into()));
+    }
+
+    if options.is_empty() || options.len() > MAX_OPTIONS {
+        return Err(ApiError::BadRequest(format!("options must contain 1 to {} entries",
+                                                MAX_OPTIONS)));
+    }
+
+    let mut seen = HashSet::new();
+
+    for option in options {
+        if !seen.insert((&option.network, &option.token)) {
+            return Err(ApiError::BadRequest(format!("Duplicate option {} ({})",
+                                                    option.token, option.network)));
+        }
+
+        if fiat && option.amount.is_some() {
+            return Err(ApiError::BadRequest(
+                "Option amounts can't be combined with fiat pricing".into()));
+        }
+
+        if !fiat && option.amount.is_none() && payload.amount.is_none() {
+            return Err(ApiError::BadRequest(format!("No amount for option {} ({})",
+                                                    option.token, option.network)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Prices `token` on `network`, either from a token `amount` or from the fiat amount
+/// of the request, and picks the tolerance. Also checks that the pair is supported.
+async fn quote(
+    state: &AppState,
+    stores: &InvoiceStores,
+    network: &str,
+    token: &str,
+    amount: Option<&str>,
+    payload: &CreateInvoiceReq,
+) -> Result<QuotedOption, ApiError> {
+    if state.db.get_chain(network).await
         .map_err(|e| ApiError::InternalServerError(e.to_string()))?
-        .ok_or_else(|| ApiError::BadRequest(format!("Network '{}' not supported", payload.network)))?;
+        .is_none() {
+        return Err(ApiError::BadRequest(format!("Network '{}' not supported", network)));
+    }
 
-    let token_decimals = state.db.get_token_decimals(&payload.network, &payload.token).await
+    let decimals = state.db.get_token_decimals(network, token).await
         .map_err(|e| ApiError::InternalServerError(e.to_string()))?
         .ok_or_else(|| ApiError::BadRequest(format!("Token '{}' ({}) not supported",
-                                                    payload.token, payload.network)))?;
+                                                    token, network)))?;
+
+    let (amount, fiat) = match (amount, &payload.fiat_amount, &payload.fiat_currency) {
+        (Some(amount), _, _) => {
+            parse_units(amount, decimals)
+                .map_err(|e| ApiError::BadRequest(format!("Invalid amount format: {}", e)))?;
+
+            (amount.to_owned(), None)
+        }
+        (None, Some(fiat_amount), Some(fiat_currency)) => {
+            let fiat_currency = fiat_currency.to_uppercase();
+
+            let rate = stores.rates.rate(token, &fiat_currency).await
+                .map_err(|e| ApiError::InternalServerError(format!("Failed to get rate: {}", e)))?
+                .ok_or_else(|| ApiError::BadRequest(format!("No {}/{} rate available",
+                                                            token, fiat_currency)))?;
+
+            let amount_raw = fiat_to_token(fiat_amount, &rate, decimals)
+                .map_err(|e| ApiError::BadRequest(format!("Invalid fiat amount: {}", e)))?;
+
+            let amount = format_units(amount_raw, decimals)
+                .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+
+            let quote = FiatQuote {
+                amount: fiat_amount.clone(),
+                currency: fiat_currency,
+                rate,
+                source: stores.rates.name().to_owned(),
+                locked_at: Utc::now(),
+            };
+
+            (amount, Some(quote))
+        }
+        _ => return Err(ApiError::BadRequest(format!("No amount for {} ({})", token, network))),
+    };
+
+    let tolerance = match &payload.tolerance {
+        Some(tolerance) => Some(tolerance.clone()),
+        None => stores.tolerances.get(network, token).await,
+    };
+
+    // fail now rather than when the payer picks this option
+    if let Some(tolerance) = &tolerance {
+        InvoiceTolerance::resolve(tolerance, &amount, decimals)
+            .map_err(|e| ApiError::BadRequest(format!("Invalid tolerance for {} ({}): {}",
+                                                      token, network, e)))?;
+    }
+
+    Ok(QuotedOption {
+        network: network.to_owned(),
+        token: token.to_owned(),
+        amount,
+        decimals,
+        fiat,
+        tolerance,
+    })
+}
 
-    let amount_raw = parse_units(&payload.amount, token_decimals)
-        .map_err(|e| ApiError::BadRequest(format!("Invalid amount format: {}", e)))?;
+async fn add_invoice(
+    state: &AppState,
+    stores: &InvoiceStores,
+    invoice_id: String,
+    payload: CreateInvoiceReq,
+) -> Result<(StatusCode, Json<ApiResponse<InvoiceView>>), ApiError> {
+    let (Some(network), Some(token)) = (&payload.network, &payload.token) else {
+        return Err(ApiError::BadRequest("Specify network and token, or options".into()));
+    };
+
+    let option = quote(state, stores, network, token, payload.amount.as_deref(), &payload).await?;
+
+    // a single-option invoice goes through the same path as a selected one, just right away
+    let created_at = Utc::now();
+    let pending = PendingInvoice {
+        id: invoice_id,
+        options: vec![option.clone()],
+        webhook_url: payload.webhook_url,
+        webhook_secret: payload.webhook_secret,
+        webhook_max_retries: payload.webhook_max_retries,
+        created_at,
+        expires_at: created_at + expire_after(payload.expire_after).map_err(ApiError::BadRequest)?,
+        cancelled_at: None,
+        selecting_since: None,
+    };
+
+    let invoice_extras = InvoiceExtras {
+        order_id: payload.order_id,
+        metadata: payload.metadata,
+        fiat: option.fiat.clone(),
+        tolerance: applied_tolerance(&option)?,
+        webhook: pending.webhook(),
+    };
+
+    // before the core invoice exists, so the watcher never sees it without its webhook.
+    // `issue_invoice` drops them again if creating it fails
+    stores.extras.put(&pending.id, invoice_extras.clone()).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+
+    let invoice = open_invoice(state, &stores.watch_list, pending, option).await?;
+
+    Ok((StatusCode::CREATED, Json(ApiResponse::success(
+        InvoiceView::Invoice(Box::new(InvoiceModel::new(invoice, invoice_extras)))))))
+}
+
+async fn add_pending_invoice(
+    state: &AppState,
+    stores: &InvoiceStores,
+    invoice_id: String,
+    payload: CreateInvoiceReq,
+) -> Result<(StatusCode, Json<ApiResponse<InvoiceView>>), ApiError> {
+    let requested = payload.options.clone().unwrap_or_default();
+    let mut options = Vec::with_capacity(requested.len());
+
+    for option in &requested {
+        let amount = option.amount.as_deref().or(payload.amount.as_deref());
+        options.push(quote(state, stores, &option.network, &option.token, amount, &payload).await?);
+    }
+
+    let created_at = Utc::now();
+    let pending = PendingInvoice {
+        id: invoice_id,
+        options,
+        webhook_url: payload.webhook_url,
+        webhook_secret: payload.webhook_secret,
+        webhook_max_retries: payload.webhook_max_retries,
+        created_at,
+        expires_at: created_at + expire_after(payload.expire_after).map_err(ApiError::BadRequest)?,
+        cancelled_at: None,
+        selecting_since: None,
+    };
+
+    // the fiat quote, tolerance and webhook are attached once the payer picks an option
+    let invoice_extras = InvoiceExtras {
+        order_id: payload.order_id,
+        metadata: payload.metadata,
+        ..Default::default()
+    };
+
+    stores.extras.put(&pending.id, invoice_extras.clone()).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+
+    stores.pending.insert(pending.clone()).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+
+    Ok((StatusCode::CREATED, Json(ApiResponse::success(
+        InvoiceView::AwaitingSelection(PendingInvoiceModel::new(pending, invoice_extras))))))
+}
+
+/// Tolerance to keep in the invoice extras once `option` is chosen
+pub(crate) fn applied_tolerance(option: &QuotedOption) -> Result<Option<InvoiceTolerance>, ApiError> {
+    let Some(tolerance) = &option.tolerance else {
+        return Ok(None);
+    };
 
-    let index = state.get_free_slot(&payload.network).await
+    InvoiceTolerance::resolve(tolerance, &option.amount, option.decimals)
+        .map_err(|e| ApiError::BadRequest(format!("Invalid tolerance: {}", e)))
+}
+
+/// Derives an address for `option` and registers the invoice with the core.
+/// With a tolerance the core invoice asks for the lowest accepted amount. The webhook
+/// settings stay with the backend (`InvoiceExtras::webhook`), which does the delivery.
+pub(crate) async fn open_invoice(
+    state: &AppState,
+    watch_list: &WatchList,
+    pending: PendingInvoice,
+    option: QuotedOption,
+) -> Result<Invoice, ApiError> {
+    let blockchain = state.db.get_chain(&option.network).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?
+        .ok_or_else(|| ApiError::BadRequest(format!("Network '{}' not supported", option.network)))?;
+
+    let amount = match applied_tolerance(&option)? {
+        Some(tolerance) => tolerance.min_amount,
+        None => option.amount,
+    };
+
+    let amount_raw: U256 = parse_units(&amount, option.decimals)
+        .map_err(|e| ApiError::BadRequest(format!("Invalid amount format: {}", e)))?
+        .into();
+
+    let index = state.get_free_slot(&option.network).await
         .ok_or_else(|| ApiError::InternalServerError("No free slots available".to_owned()))?;
 
     let address = blockchain.derive_address(index).await
         .map_err(|e| ApiError::InternalServerError(format!("Failed to derive address: {}", e)))?;
 
     let invoice = Invoice {
-        id: uuid::Uuid::new_v4().to_string(),
+        id: pending.id,
         address_index: index,
         address: address.clone(),
-        amount: payload.amount,
-        amount_raw: amount_raw.into(),
+        amount,
+        amount_raw,
         paid: "0".to_string(),
         paid_raw: U256::from(0),
-        token: payload.token,
-        network: payload.network.clone(),
-        decimals: token_decimals,
-        webhook_url: payload.webhook_url,
-        webhook_secret: payload.webhook_secret,
-        webhook_max_retries: payload.webhook_max_retries,
-        created_at: chrono::Utc::now(),
-        expires_at: chrono::Utc::now()
-            + TimeDelta::seconds(payload.expire_after.unwrap_or(900) as i64),
+        token: option.token,
+        network: option.network.clone(),
+        decimals: option.decimals,
+        webhook_url: None,
+        webhook_secret: None,
+        webhook_max_retries: None,
+        created_at: pending.created_at,
+        expires_at: pending.expires_at,
         status: InvoiceStatus::Pending,
     };
 
     state.db.add_invoice(&invoice).await
         .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
 
-    state.db.add_watch_address(&payload.network, &address).await
+    state.db.add_watch_address(&option.network, &address).await
         .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
 
-    Ok((StatusCode::CREATED, Json(ApiResponse::success(invoice))))
+    watch_list.add(&invoice.id);
+
+    Ok(invoice)
 }
 
 #[utoipa::path(
@@ -89,12 +493,119 @@ pub async fn create_invoice(
 )]
 pub async fn get_invoices(
     State(state): State<Arc<AppState>>,
+    State(extras): State<Arc<InvoiceExtrasStore>>,
     Query(filter): Query<InvoiceFilterSchema>,
-) -> Result<(StatusCode, Json<ApiResponse<PaginatedVecPage<Invoice>>>), ApiError> {
+) -> Result<(StatusCode, Json<ApiResponse<PaginatedVecPage<InvoiceModel>>>), ApiError> {
+    let page_size = filter.pagination.page_size;
+    let page = filter.pagination.page;
+    let status = filter.status;
+
+    if let Some(order_id) = filter.order_id.clone() {
+        let filter: InvoiceFilter = filter.into();
+
+        // an order maps to at most one invoice, the remaining filters just have to agree with it
+        let invoice = match extras.find_by_order(&order_id).await {
+            Some(invoice_id) => state.db.get_invoice(&invoice_id).await
+                .map_err(|e| ApiError::InternalServerError(e.to_string()))?
+                .filter(|invoice| matches_filter(invoice, &filter)),
+            None => None,
+        };
+
+        let mut items = vec![];
+        if let Some(invoice) = invoice {
+            let invoice_extras = extras.get(&invoice.id).await;
+            let model = InvoiceModel::new(invoice, invoice_extras);
+
+            if status.is_none_or(|status| status.matches(model.status)) {
+                items.push(model);
+            }
+        }
+
+        let invoices_page = PaginatedVecPage {
+            total: items.len() as u64,
+            items,
+            page_size,
+            page,
+        };
+
+        return Ok((StatusCode::OK, Json(ApiResponse::success(invoices_page))));
+    }
+
+    if let Some(status @ (InvoiceStatusSchema::Underpaid | InvoiceStatusSchema::Overpaid)) = status {
+        let invoices_page = get_settled_invoices(&state, &extras, filter.into(), status,
+                                                 page_size, page).await?;
+        return Ok((StatusCode::OK, Json(ApiResponse::success(invoices_page))));
+    }
+
     let invoices = state.db.get_invoices(filter.into()).await
         .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
 
-    Ok((StatusCode::OK, Json(ApiResponse::success(invoices.into()))))
+    let mut invoices_page: PaginatedVecPage<Invoice> = invoices.into();
+    let mut items = Vec::with_capacity(invoices_page.items.len());
+
+    for invoice in invoices_page.items.drain(..) {
+        let invoice_extras = extras.get(&invoice.id).await;
+        items.push(InvoiceModel::new(invoice, invoice_extras));
+    }
+
+    let invoices_page = PaginatedVecPage {
+        items,
+        total: invoices_page.total,
+        page_size: invoices_page.page_size,
+        page: invoices_page.page,
+    };
+
+    Ok((StatusCode::OK, Json(ApiResponse::success(invoices_page))))
+}
+
+/// Core can only filter by Paid, so the invoices that settled as `status` come from the settlements
+/// the watcher records (newest first). Only the ones on the page are fetched from core
+async fn get_settled_invoices(
+    state: &AppState,
+    extras: &InvoiceExtrasStore,
+    filter: InvoiceFilter,
+    status: InvoiceStatusSchema,
+    page_size: u32,
+    page: u64,
+) -> Result<PaginatedVecPage<InvoiceModel>, ApiError> {
+    let pagination: Pagination = PaginationParams { page_size, page }.into();
+
+    let mut settled: Vec<_> = extras.settled_as(status).await
+        .into_iter()
+        .filter(|(_, settlement)| filter.address.as_ref().is_none_or(|address| *address == settlement.address)
+            && filter.network.as_ref().is_none_or(|network| *network == settlement.network)
+            && filter.token.as_ref().is_none_or(|token| *token == settlement.token))
+        .collect();
+    settled.sort_by_key(|(_, settlement)| std::cmp::Reverse(settlement.created_at));
+
+    let total = settled.len() as u64;
+    let mut items = vec![];
+
+    for (invoice_id, _) in settled.into_iter()
+        .skip(pagination.offset as usize)
+        .take(pagination.limit as usize) {
+        let Some(invoice) = state.db.get_invoice(&invoice_id).await
+            .map_err(|e| ApiError::InternalServerError(e.to_string()))? else {
+            continue;
+        };
+
+        let invoice_extras = extras.get(&invoice.id).await;
+        items.push(InvoiceModel::new(invoice, invoice_extras));
+    }
+
+    Ok(PaginatedVecPage {
+        items,
+        total,
+        page_size: pagination.limit,
+        page: (pagination.offset / pagination.limit.max(1) as u64) + 1,
+    })
+}
+
+fn matches_filter(invoice: &Invoice, filter: &InvoiceFilter) -> bool {
+    filter.address.as_ref().is_none_or(|address| *address == invoice.address)
+        && filter.network.as_ref().is_none_or(|network| *network == invoice.network)
+        && filter.token.as_ref().is_none_or(|token| *token == invoice.token)
+        && filter.status.as_ref().is_none_or(|status| *status == invoice.status)
 }
 
 #[utoipa::path(
@@ -104,7 +615,7 @@ pub async fn get_invoices(
         ("id" = String, Path, description = "Invoice UUID")
     ),
     responses(
-        (status = 200, description = "Invoice data", body = ApiResponse<InvoiceSchema>),
+        (status = 200, description = "Invoice data. PendingInvoiceModel while the payer hasn't chosen an option", body = ApiResponse<InvoiceSchema>),
         (status = 404, description = "Invoice not found", body = ApiResponse<Empty>),
         (status = 500, description = "Server error", body = ApiResponse<Empty>)
     ),
@@ -112,13 +623,27 @@ pub async fn get_invoices(
 )]
 pub async fn get_invoice_by_id(
     State(state): State<Arc<AppState>>,
+    State(extras): State<Arc<InvoiceExtrasStore>>,
+    State(pending): State<Arc<PendingInvoiceStore>>,
     Path(id): Path<String>,
-) -> Result<(StatusCode, Json<ApiResponse<Invoice>>), ApiError> {
+) -> Result<(StatusCode, Json<ApiResponse<InvoiceView>>), ApiError> {
     let invoice = state.db.get_invoice(&id).await
-        .map_err(|e| ApiError::InternalServerError(e.to_string()))?
-        .ok_or_else(|| ApiError::NotFound("Invoice not found".into()))?;
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
 
-    Ok((StatusCode::OK, Json(ApiResponse::success(invoice))))
+    let view = match invoice {
+        Some(invoice) => {
+            let invoice_extras = extras.get(&invoice.id).await;
+            InvoiceView::Invoice(Box::new(InvoiceModel::new(invoice, invoice_extras)))
+        }
+        None => {
+            let pending = pending.get(&id).await
+                .ok_or_else(|| ApiError::NotFound("Invoice not found".into()))?;
+            let invoice_extras = extras.get(&pending.id).await;
+            InvoiceView::AwaitingSelection(PendingInvoiceModel::new(pending, invoice_extras))
+        }
+    };
+
+    Ok((StatusCode::OK, Json(ApiResponse::success(view))))
 }
 
 #[utoipa::path(
@@ -128,16 +653,342 @@ pub async fn get_invoice_by_id(
         ("id" = String, Path, description = "Invoice UUID")
     ),
     responses(
-        (status = 200, description = "Invoice cancelled", body = ApiResponse<Empty>),
+        (status = 200, description = "Invoice cancelled, its order_id is free for a new invoice. \
+            PendingInvoiceModel if the payer hadn't chosen an option", body = ApiResponse<InvoiceSchema>),
+        (status = 404, description = "Invoice not found", body = ApiResponse<Empty>),
+        (status = 409, description = "Invoice is already paid, expired or cancelled (pending selection ones too)", body = ApiResponse<Empty>),
         (status = 500, description = "Server error", body = ApiResponse<Empty>)
     ),
     tag = "Invoices"
 )]
 pub async fn cancel_invoice(
     State(state): State<Arc<AppState>>,
+    State(extras): State<Arc<InvoiceExtrasStore>>,
+    State(pending): State<Arc<PendingInvoiceStore>>,
+    State(watch_list): State<Arc<WatchList>>,
     Path(id): Path<String>,
-) -> Result<(StatusCode, Json<ApiResponse<String>>), ApiError> {
+) -> Result<(StatusCode, Json<ApiResponse<InvoiceView>>), ApiError> {
+    // an invoice nobody picked an option for has no address to stop watching yet
+    match pending.cancel(&id).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))? {
+        Some(Cancellation::Cancelled(invoice)) => {
+            extras.release_order(&id).await
+                .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+
+            let invoice_extras = extras.get(&invoice.id).await;
+            return Ok((StatusCode::OK, Json(ApiResponse::success(
+                InvoiceView::AwaitingSelection(PendingInvoiceModel::new(invoice, invoice_extras))))));
+        }
+        Some(Cancellation::Refused(invoice)) if invoice.is_cancelled() => {
+            return Err(ApiError::Conflict("Invoice is already cancelled".into()));
+        }
+        Some(Cancellation::Refused(invoice)) if invoice.is_selecting() => {
+            return Err(ApiError::Conflict("Network/token is being chosen right now, retry".into()));
+        }
+        Some(Cancellation::Refused(_)) => return Err(ApiError::Conflict("Invoice is already expired".into())),
+        None => {}
+    }
+
+    let invoice = state.db.get_invoice(&id).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?
+        .ok_or_else(|| ApiError::NotFound("Invoice not found".into()))?;
+
+    match invoice.status {
+        InvoiceStatus::Pending => {}
+        InvoiceStatus::Paid => return Err(ApiError::Conflict("Invoice is already paid".into())),
+        InvoiceStatus::Expired => return Err(ApiError::Conflict("Invoice is already expired".into())),
+        InvoiceStatus::Cancelled => return Err(ApiError::Conflict("Invoice is already cancelled".into())),
+    }
+
     state.db.cancel_invoice(&id).await
         .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
-    Ok((StatusCode::OK, Json(ApiResponse::ok())))
-}
\ No newline at end of file
+    watch_list.add(&id);
+
+    extras.release_order(&id).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+
+    let invoice = state.db.get_invoice(&id).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?
+        .ok_or_else(|| ApiError::NotFound("Invoice not found".into()))?;
+
+    let invoice_extras = extras.get(&invoice.id).await;
+    Ok((StatusCode::OK, Json(ApiResponse::success(
+        InvoiceView::Invoice(Box::new(InvoiceModel::new(invoice, invoice_extras)))))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/invoice/{id}/extend",
+    params(
+        ("id" = String, Path, description = "Invoice UUID")
+    ),
+    request_body = ExtendInvoiceReq,
+    responses(
+        (status = 200, description = "Expiry moved. PendingInvoiceModel while the payer hasn't chosen an option", body = ApiResponse<InvoiceSchema>),
+        (status = 422, description = "New expiry isn't later than the current one, or expire_after is too long", body = ApiResponse<Empty>),
+        (status = 404, description = "Invoice not found", body = ApiResponse<Empty>),
+        (status = 409, description = "Invoice is no longer pending, or changed meanwhile", body = ApiResponse<Empty>),
+        (status = 500, description = "Server error", body = ApiResponse<Empty>)
+    ),
+    tag = "Invoices"
+)]
+pub async fn extend_invoice(
+    State(state): State<Arc<AppState>>,
+    State(extras): State<Arc<InvoiceExtrasStore>>,
+    State(pending): State<Arc<PendingInvoiceStore>>,
+    Path(id): Path<String>,
+    Json(payload): Json<ExtendInvoiceReq>,
+) -> Result<(StatusCode, Json<ApiResponse<InvoiceView>>), ApiError> {
+    let expires_at = Utc::now() + expire_after(payload.expire_after).map_err(ApiError::Unprocessable)?;
+
+    let Some(invoice) = state.db.get_invoice(&id).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))? else {
+        let current = pending.get(&id).await
+            .ok_or_else(|| ApiError::NotFound("Invoice not found".into()))?;
+
+        if current.is_cancelled() {
+            return Err(ApiError::Conflict("Only pending invoices can be extended".into()));
+        }
+
+        if current.is_expired() {
+            return Err(ApiError::Conflict("Invoice expired, reopen it instead".into()));
+        }
+
+        return set_pending_expiry(&pending, &extras, current, expires_at).await;
+    };
+
+    if invoice.status != InvoiceStatus::Pending {
+        return Err(ApiError::Conflict("Only pending invoices can be extended".into()));
+    }
+
+    if expires_at <= invoice.expires_at {
+        return Err(ApiError::Unprocessable("expire_after must move expires_at later".into()));
+    }
+
+    let invoice = write_expiry(&state, &invoice, InvoiceStatus::Pending, expires_at).await?;
+
+    watch_again(&state, &invoice).await?;
+
+    let invoice_extras = extras.get(&invoice.id).await;
+    Ok((StatusCode::OK, Json(ApiResponse::success(
+        InvoiceView::Invoice(Box::new(InvoiceModel::new(invoice, invoice_extras)))))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/invoice/{id}/reopen",
+    params(
+        ("id" = String, Path, description = "Invoice UUID")
+    ),
+    request_body = ExtendInvoiceReq,
+    responses(
+        (status = 200, description = "Invoice pending again. PendingInvoiceModel while the payer hasn't chosen an option", body = ApiResponse<InvoiceSchema>),
+        (status = 404, description = "Invoice not found", body = ApiResponse<Empty>),
+        (status = 422, description = "expire_after is too long", body = ApiResponse<Empty>),
+        (status = 409, description = "Invoice isn't expired, changed meanwhile, or its address was handed to another invoice", body = ApiResponse<Empty>),
+        (status = 500, description = "Server error", body = ApiResponse<Empty>)
+    ),
+    tag = "Invoices"
+)]
+pub async fn reopen_invoice(
+    State(state): State<Arc<AppState>>,
+    State(extras): State<Arc<InvoiceExtrasStore>>,
+    State(pending): State<Arc<PendingInvoiceStore>>,
+    Path(id): Path<String>,
+    Json(payload): Json<ExtendInvoiceReq>,
+) -> Result<(StatusCode, Json<ApiResponse<InvoiceView>>), ApiError> {
+    let expires_at = Utc::now() + expire_after(payload.expire_after).map_err(ApiError::Unprocessable)?;
+
+    let Some(invoice) = state.db.get_invoice(&id).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))? else {
+        // nobody picked an option yet, so there is no address to lose either
+        let current = pending.get(&id).await
+            .ok_or_else(|| ApiError::NotFound("Invoice not found".into()))?;
+
+        if current.is_cancelled() || !current.is_expired() {
+            return Err(ApiError::Conflict("Only expired invoices can be reopened".into()));
+        }
+
+        return set_pending_expiry(&pending, &extras, current, expires_at).await;
+    };
+
+    if invoice.status != InvoiceStatus::Expired {
+        return Err(ApiError::Conflict("Only expired invoices can be reopened".into()));
+    }
+
+    if slot_reused(&state, &invoice).await? {
+        return Err(ApiError::Conflict(
+            "The invoice address was handed to another invoice, create a new one".into()));
+    }
+
+    let reopened = write_expiry(&state, &invoice, InvoiceStatus::Pending, expires_at).await?;
+
+    // core may have handed the address out between the check and the write; once the invoice
+    // is pending again it won't, so checking once more settles it
+    if slot_reused(&state, &reopened).await? {
+        write_expiry(&state, &reopened, InvoiceStatus::Expired, invoice.expires_at).await?;
+        return Err(ApiError::Conflict(
+            "The invoice address was handed to another invoice, create a new one".into()));
+    }
+
+    let invoice = reopened;
+
+    watch_again(&state, &invoice).await?;
+
+    let invoice_extras = extras.get(&invoice.id).await;
+    Ok((StatusCode::OK, Json(ApiResponse::success(
+        InvoiceView::Invoice(Box::new(InvoiceModel::new(invoice, invoice_extras)))))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/invoice/{id}/rotate-secret",
+    params(
+        ("id" = String, Path, description = "Invoice UUID")
+    ),
+    request_body = RotateInvoiceSecretReq,
+    responses(
+        (status = 200, description = "Webhook secret replaced. Until the grace period ends webhooks carry one \
+            signature per secret in X-Signature, comma-separated, newest first. PendingInvoiceModel while the \
+            payer hasn't chosen an option", body = ApiResponse<InvoiceSchema>),
+        (status = 400, description = "Bad Request", body = ApiResponse<Empty>),
+        (status = 404, description = "Invoice not found", body = ApiResponse<Empty>),
+        (status = 409, description = "Invoice has no webhook_url, or its webhooks are sent by core", body = ApiResponse<Empty>),
+        (status = 500, description = "Server error", body = ApiResponse<Empty>)
+    ),
+    tag = "Invoices"
+)]
+pub async fn rotate_invoice_secret(
+    State(state): State<Arc<AppState>>,
+    State(extras): State<Arc<InvoiceExtrasStore>>,
+    State(pending): State<Arc<PendingInvoiceStore>>,
+    Path(id): Path<String>,
+    Json(payload): Json<RotateInvoiceSecretReq>,
+) -> Result<(StatusCode, Json<ApiResponse<InvoiceView>>), ApiError> {
+    if payload.secret.is_empty() {
+        return Err(ApiError::BadRequest("secret must not be empty".into()));
+    }
+
+    let grace_period = payload.grace_period.unwrap_or(DEFAULT_SECRET_GRACE_PERIOD);
+    if grace_period > MAX_SECRET_GRACE_PERIOD {
+        return Err(ApiError::BadRequest(format!(
+            "grace_period must not exceed {} seconds", MAX_SECRET_GRACE_PERIOD)));
+    }
+
+    let Some(invoice) = state.db.get_invoice(&id).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))? else {
+        let current = pending.get(&id).await
+            .ok_or_else(|| ApiError::NotFound("Invoice not found".into()))?;
+
+        if current.webhook_url.is_none() {
+            return Err(ApiError::Conflict("Invoice has no webhook_url".into()));
+        }
+
+        let invoice = pending.set_webhook_secret(&id, payload.secret).await
+            .map_err(|e| ApiError::InternalServerError(e.to_string()))?
+            .ok_or_else(|| ApiError::NotFound("Invoice not found".into()))?;
+
+        let invoice_extras = extras.get(&invoice.id).await;
+        return Ok((StatusCode::OK, Json(ApiResponse::success(
+            InvoiceView::AwaitingSelection(PendingInvoiceModel::new(invoice, invoice_extras))))));
+    };
+
+    let rotated = extras.rotate_secret(&id, payload.secret, TimeDelta::seconds(grace_period as i64)).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+
+    if rotated.is_none() {
+        return Err(ApiError::Conflict(match invoice.webhook_url {
+            Some(_) => "Webhooks of this invoice are sent by core, its secret can't be rotated".into(),
+            None => "Invoice has no webhook_url".into(),
+        }));
+    }
+
+    let invoice_extras = extras.get(&invoice.id).await;
+    Ok((StatusCode::OK, Json(ApiResponse::success(
+        InvoiceView::Invoice(Box::new(InvoiceModel::new(invoice, invoice_extras)))))))
+}
+
+async fn set_pending_expiry(
+    pending: &PendingInvoiceStore,
+    extras: &InvoiceExtrasStore,
+    current: PendingInvoice,
+    expires_at: DateTime<Utc>,
+) -> Result<(StatusCode, Json<ApiResponse<InvoiceView>>), ApiError> {
+    if expires_at <= current.expires_at {
+        return Err(ApiError::Unprocessable("expire_after must move expires_at later".into()));
+    }
+
+    // the payer may have picked an option in the meantime
+    let invoice = pending.set_expiry(&current.id, expires_at).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?
+        .ok_or_else(|| ApiError::Conflict("Network/token was chosen meanwhile, retry".into()))?;
+
+    let invoice_extras = extras.get(&invoice.id).await;
+    Ok((StatusCode::OK, Json(ApiResponse::success(
+        InvoiceView::AwaitingSelection(PendingInvoiceModel::new(invoice, invoice_extras))))))
+}
+
+/// Core batch size when going through the invoices of an address
+const SCAN_BATCH: u32 = 100;
+
+/// Whether another invoice was created for the same address after this one expired,
+/// in which case payments to it could no longer be told apart
+async fn slot_reused(state: &AppState, invoice: &Invoice) -> Result<bool, ApiError> {
+    let mut offset = 0;
+
+    loop {
+        let holders = state.db.get_invoices(InvoiceFilter {
+            status: None,
+            address: Some(invoice.address.clone()),
+            network: Some(invoice.network.clone()),
+            token: None,
+            pagination: Pagination { limit: SCAN_BATCH, offset },
+        }).await
+            .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+        let fetched = holders.items.len() as u64;
+
+        if holders.items.iter().any(|other| other.id != invoice.id
+            && (other.created_at >= invoice.created_at || other.status == InvoiceStatus::Pending)) {
+            return Ok(true);
+        }
+
+        offset += fetched;
+        if fetched < SCAN_BATCH as u64 || offset >= holders.total {
+            return Ok(false);
+        }
+    }
+}
+
+/// Sets the expiry and status on a fresh copy of the invoice, so the payment progress core wrote
+/// since `seen` was read isn't overwritten. Core has no conditional update: this refuses when
+/// the invoice moved on since `seen`, which leaves only the write itself unguarded
+async fn write_expiry(
+    state: &AppState,
+    seen: &Invoice,
+    status: InvoiceStatus,
+    expires_at: DateTime<Utc>,
+) -> Result<Invoice, ApiError> {
+    let mut invoice = state.db.get_invoice(&seen.id).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?
+        .ok_or_else(|| ApiError::NotFound("Invoice not found".into()))?;
+
+    if invoice.status != seen.status || invoice.paid_raw != seen.paid_raw || invoice.expires_at != seen.expires_at {
+        return Err(ApiError::Conflict("Invoice changed meanwhile, retry".into()));
+    }
+
+    invoice.status = status;
+    invoice.expires_at = expires_at;
+
+    state.db.update_invoice(&invoice).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+
+    Ok(invoice)
+}
+
+/// Puts the address back on the watch list, the janitor drops it when an invoice expires
+async fn watch_again(state: &AppState, invoice: &Invoice) -> Result<(), ApiError> {
+    state.db.add_watch_address(&invoice.network, &invoice.address).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+
+    Ok(())
+}
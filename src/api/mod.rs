@@ -4,16 +4,27 @@ mod auth;
 mod payment;
 mod webhook;
 mod public;
+mod api_key;
+mod state;
+mod idempotency;
+mod event;
+mod webhook_endpoint;
 
-use crate::model::{CreateInvoiceReq};
-use crate::model::core::{InvoiceSchema, ChainConfigSchema, TokenConfigSchema, WebhookSchema,
-                         PaymentSchema};
+use crate::model::{CreateInvoiceReq, EventFilterParams, EventLogEntry, EventLogPage, ExtendInvoiceReq, FiatQuote, InvoiceOptionModel, InvoiceOptionReq,
+                   PaymentTolerance, PendingInvoiceModel, RotateInvoiceSecretReq, SelectOptionReq};
+use crate::model::core::{InvoiceSchema, InvoiceStatusSchema, ChainConfigSchema, TokenConfigSchema,
+                         WebhookSchema, WebhookEventSchema, WebhookEventType, PaymentSchema};
+use crate::tolerance::InvoiceTolerance;
+use crate::delivery::{CircuitState, DeliveryAttempt, DestinationHealth, RetryPolicyOverrides, WebhookSource};
 use crate::model::public::{PublicInvoiceModel, PublicPaymentModel, PublicChainModel, 
-                           PublicTokenModel};
-use necko3_core::state::AppState;
-use axum::routing::{delete, get, patch, post};
+                           PublicTokenModel, PublicInvoiceOptionsModel, PublicCheckoutModel};
+use crate::model::api_key::{ApiKeyModel, ApiScope, CreateApiKeyReq, CreatedApiKeyModel};
+use crate::model::envelope::{ApiVersion, WebhookEnvelope};
+use crate::model::webhook_endpoint::{CreateWebhookEndpointReq, CreatedWebhookEndpointModel,
+                                     RotateWebhookSecretReq, UpdateWebhookEndpointReq, WebhookEndpointModel};
+use axum::routing::{delete, get, patch, post, put};
 use axum::{middleware, Router};
-use std::sync::Arc;
+use std::net::SocketAddr;
 use axum::http::{header, HeaderName, HeaderValue, Method};
 use tower_http::cors::{AllowOrigin, CorsLayer};
 use tower_http::trace::TraceLayer;
@@ -25,7 +36,13 @@ pub use chain::*;
 pub use invoice::*;
 pub use payment::*;
 pub use webhook::*;
-use crate::api::auth::{auth_middleware, SecurityAddon};
+pub use api_key::*;
+pub use event::*;
+pub use webhook_endpoint::*;
+pub use state::ApiState;
+pub use auth::{ApiKeyStore, FailedAuthTracker, SignatureVerifier};
+pub use idempotency::IdempotencyStore;
+use crate::api::auth::{auth_middleware, scope_middleware, SecurityAddon};
 
 #[derive(OpenApi)]
 #[openapi(
@@ -40,11 +57,17 @@ use crate::api::auth::{auth_middleware, SecurityAddon};
         get_tokens,
         get_token,
         delete_token,
+        get_token_tolerance,
+        set_token_tolerance,
+        delete_token_tolerance,
     
         create_invoice,
         get_invoices,
         get_invoice_by_id,
         cancel_invoice,
+        extend_invoice,
+        reopen_invoice,
+        rotate_invoice_secret,
 
         get_payment,
         get_payments,
@@ -53,9 +76,32 @@ use crate::api::auth::{auth_middleware, SecurityAddon};
         get_webhook,
         get_webhooks,
         cancel_webhook,
+        get_webhook_attempts,
+        retry_webhook,
+        replay_invoice_webhooks,
+
+        create_webhook_endpoint,
+        get_webhook_endpoints,
+        get_webhook_endpoint_health,
+        get_webhook_endpoint,
+        update_webhook_endpoint,
+        delete_webhook_endpoint,
+        rotate_webhook_endpoint_secret,
+
+        get_events,
+        event_feed,
+
+        create_api_key,
+        get_api_keys,
+        get_api_key,
+        revoke_api_key,
 
         public::get_invoice_data,
+        public::get_invoice_options,
+        public::select_invoice_option,
         public::get_invoice_payments,
+        public::get_invoice_events,
+        public::get_checkout,
         public::get_public_chain,
         public::get_public_token
     ),
@@ -63,58 +109,123 @@ use crate::api::auth::{auth_middleware, SecurityAddon};
         schemas(
             InvoiceSchema,
             CreateInvoiceReq,
+            ExtendInvoiceReq,
+            RotateInvoiceSecretReq,
+            FiatQuote,
+            PaymentTolerance,
+            InvoiceTolerance,
+            InvoiceStatusSchema,
+            InvoiceOptionReq,
+            InvoiceOptionModel,
+            PendingInvoiceModel,
+            SelectOptionReq,
             ChainConfigSchema,
             TokenConfigSchema,
             WebhookSchema,
             PaymentSchema,
+            EventFilterParams,
+            EventLogPage,
+            EventLogEntry,
+            WebhookEventSchema,
+            WebhookEventType,
+            WebhookEnvelope,
+            ApiVersion,
+            WebhookSource,
+            DeliveryAttempt,
+            WebhookEndpointModel,
+            CreateWebhookEndpointReq,
+            UpdateWebhookEndpointReq,
+            CreatedWebhookEndpointModel,
+            RotateWebhookSecretReq,
+            RetryPolicyOverrides,
+            DestinationHealth,
+            CircuitState,
             PublicInvoiceModel,
             PublicPaymentModel,
             PublicChainModel,
-            PublicTokenModel
+            PublicTokenModel,
+            PublicInvoiceOptionsModel,
+            PublicCheckoutModel,
+            ApiScope,
+            ApiKeyModel,
+            CreateApiKeyReq,
+            CreatedApiKeyModel
         )
     ),
     modifiers(&SecurityAddon),
     security(
-        ("api_key" = [])
+        ("api_key" = []),
+        ("signature_key_id" = [], "signature_timestamp" = [], "signature" = [])
     )
 )]
 struct ApiDoc;
 
 pub async fn serve(
-    state: Arc<AppState>,
+    state: ApiState,
     include_swagger: bool,
     cors_layer: CorsLayer,
     bind_address: &str,
 ) -> std::io::Result<()> {
+    let require = |scope: ApiScope| middleware::from_fn_with_state(scope, scope_middleware);
+
     let mut app = Router::new()
-        .route("/invoice", post(create_invoice))
-        .route("/invoice", get(get_invoices))
-        .route("/invoice/{id}", get(get_invoice_by_id))
-        .route("/invoice/{id}", delete(cancel_invoice))
-
-        .route("/chain", post(add_chain))
-        .route("/chain", get(get_chains))
-        .route("/chain/{name}", get(get_chain))
-        .route("/chain/{name}", delete(delete_chain))
-        .route("/chain/{name}", patch(update_chain))
-
-        .route("/chain/{name}/token", post(add_token))
-        .route("/chain/{name}/token", get(get_tokens))
-        .route("/chain/{name}/token/{symbol}", get(get_token))
-        .route("/chain/{name}/token/{symbol}", delete(delete_token))
-
-        .route("/payment", get(get_payments))
-        .route("/payment/{id}", get(get_payment))
-        .route("/payment/{id}", delete(cancel_payment))
-
-        .route("/webhook", get(get_webhooks))
-        .route("/webhook/{id}", get(get_webhook))
-        .route("/webhook/{id}", delete(cancel_webhook))
+        .route("/invoice", post(create_invoice).layer(require(ApiScope::InvoicesWrite)))
+        .route("/invoice", get(get_invoices).layer(require(ApiScope::InvoicesRead)))
+        .route("/invoice/{id}", get(get_invoice_by_id).layer(require(ApiScope::InvoicesRead)))
+        .route("/invoice/{id}", delete(cancel_invoice).layer(require(ApiScope::InvoicesWrite)))
+        .route("/invoice/{id}/extend", post(extend_invoice).layer(require(ApiScope::InvoicesWrite)))
+        .route("/invoice/{id}/reopen", post(reopen_invoice).layer(require(ApiScope::InvoicesWrite)))
+        .route("/invoice/{id}/rotate-secret", post(rotate_invoice_secret).layer(require(ApiScope::InvoicesWrite)))
+
+        .route("/chain", post(add_chain).layer(require(ApiScope::ChainsAdmin)))
+        .route("/chain", get(get_chains).layer(require(ApiScope::ChainsRead)))
+        .route("/chain/{name}", get(get_chain).layer(require(ApiScope::ChainsRead)))
+        .route("/chain/{name}", delete(delete_chain).layer(require(ApiScope::ChainsAdmin)))
+        .route("/chain/{name}", patch(update_chain).layer(require(ApiScope::ChainsAdmin)))
+
+        .route("/chain/{name}/token", post(add_token).layer(require(ApiScope::ChainsAdmin)))
+        .route("/chain/{name}/token", get(get_tokens).layer(require(ApiScope::ChainsRead)))
+        .route("/chain/{name}/token/{symbol}", get(get_token).layer(require(ApiScope::ChainsRead)))
+        .route("/chain/{name}/token/{symbol}", delete(delete_token).layer(require(ApiScope::ChainsAdmin)))
+        .route("/chain/{name}/token/{symbol}/tolerance", get(get_token_tolerance).layer(require(ApiScope::ChainsRead)))
+        .route("/chain/{name}/token/{symbol}/tolerance", put(set_token_tolerance).layer(require(ApiScope::ChainsAdmin)))
+        .route("/chain/{name}/token/{symbol}/tolerance", delete(delete_token_tolerance).layer(require(ApiScope::ChainsAdmin)))
+
+        .route("/payment", get(get_payments).layer(require(ApiScope::PaymentsRead)))
+        .route("/payment/{id}", get(get_payment).layer(require(ApiScope::PaymentsRead)))
+        .route("/payment/{id}", delete(cancel_payment).layer(require(ApiScope::PaymentsWrite)))
+
+        .route("/webhook", get(get_webhooks).layer(require(ApiScope::WebhooksRead)))
+        .route("/webhook/{id}", get(get_webhook).layer(require(ApiScope::WebhooksRead)))
+        .route("/webhook/{id}", delete(cancel_webhook).layer(require(ApiScope::WebhooksWrite)))
+        .route("/webhook/{id}/attempts", get(get_webhook_attempts).layer(require(ApiScope::WebhooksRead)))
+        .route("/webhook/{id}/retry", post(retry_webhook).layer(require(ApiScope::WebhooksWrite)))
+        .route("/invoice/{id}/webhooks/replay", post(replay_invoice_webhooks).layer(require(ApiScope::WebhooksWrite)))
+
+        .route("/webhook-endpoint", post(create_webhook_endpoint).layer(require(ApiScope::WebhooksWrite)))
+        .route("/webhook-endpoint", get(get_webhook_endpoints).layer(require(ApiScope::WebhooksRead)))
+        .route("/webhook-endpoint/health", get(get_webhook_endpoint_health).layer(require(ApiScope::WebhooksRead)))
+        .route("/webhook-endpoint/{id}", get(get_webhook_endpoint).layer(require(ApiScope::WebhooksRead)))
+        .route("/webhook-endpoint/{id}", patch(update_webhook_endpoint).layer(require(ApiScope::WebhooksWrite)))
+        .route("/webhook-endpoint/{id}", delete(delete_webhook_endpoint).layer(require(ApiScope::WebhooksWrite)))
+        .route("/webhook-endpoint/{id}/rotate-secret", post(rotate_webhook_endpoint_secret).layer(require(ApiScope::WebhooksWrite)))
+
+        .route("/event", get(get_events).layer(require(ApiScope::WebhooksRead)))
+        .route("/event/ws", get(event_feed).layer(require(ApiScope::InvoicesRead)))
+
+        .route("/api-key", post(create_api_key).layer(require(ApiScope::ApiKeysAdmin)))
+        .route("/api-key", get(get_api_keys).layer(require(ApiScope::ApiKeysAdmin)))
+        .route("/api-key/{id}", get(get_api_key).layer(require(ApiScope::ApiKeysAdmin)))
+        .route("/api-key/{id}", delete(revoke_api_key).layer(require(ApiScope::ApiKeysAdmin)))
 
         .layer(middleware::from_fn_with_state(state.clone(), auth_middleware))
 
         .route("/public/invoice/{id}", get(public::get_invoice_data))
+        .route("/public/invoice/{id}/options", get(public::get_invoice_options))
+        .route("/public/invoice/{id}/select", post(public::select_invoice_option))
         .route("/public/invoice/{id}/payments", get(public::get_invoice_payments))
+        .route("/public/invoice/{id}/events", get(public::get_invoice_events))
+        .route("/public/checkout/{id}", get(public::get_checkout))
         .route("/public/chain/{name}", get(public::get_public_chain))
         .route("/public/chain/{name}/token/{symbol}", get(public::get_public_token))
 
@@ -137,7 +248,8 @@ pub async fn serve(
         e
     })?;
     
-    axum::serve(listener, app.with_state(state)).await
+    axum::serve(listener, app.with_state(state)
+        .into_make_service_with_connect_info::<SocketAddr>()).await
 }
 
 pub fn cors_from_str(raw_str: &str) -> CorsLayer {
@@ -163,6 +275,11 @@ pub fn cors_from_str(raw_str: &str) -> CorsLayer {
             header::CONTENT_TYPE,
             header::ACCEPT,
             HeaderName::from_static("x-api-key"),
+            HeaderName::from_static("x-key-id"),
+            HeaderName::from_static("x-timestamp"),
+            HeaderName::from_static("x-signature"),
+            HeaderName::from_static("idempotency-key"),
+            HeaderName::from_static("last-event-id"),
         ])
         .allow_credentials(allow_credentials)
 }
\ No newline at end of file
@@ -4,7 +4,7 @@ use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
 use axum::Json;
 use necko3_core::db::DatabaseAdapter;
-use necko3_core::model::Payment;
+use necko3_core::model::{Payment, PaymentStatus};
 use necko3_core::AppState;
 use std::sync::Arc;
 
@@ -15,7 +15,9 @@ use std::sync::Arc;
         ("id" = String, Path, description = "Payment UUID")
     ),
     responses(
-        (status = 200, description = "Payment cancelled", body = ApiResponse<Empty>),
+        (status = 200, description = "Payment cancelled", body = ApiResponse<PaymentSchema>),
+        (status = 404, description = "Payment not found", body = ApiResponse<Empty>),
+        (status = 409, description = "Payment is already confirmed or cancelled", body = ApiResponse<Empty>),
         (status = 500, description = "Server error", body = ApiResponse<Empty>)
     ),
     tag = "Payments"
@@ -23,11 +25,25 @@ use std::sync::Arc;
 pub async fn cancel_payment(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
-) -> Result<(StatusCode, Json<ApiResponse<String>>), ApiError> {
+) -> Result<(StatusCode, Json<ApiResponse<Payment>>), ApiError> {
+    let payment = state.db.get_payment(&id).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?
+        .ok_or_else(|| ApiError::NotFound("Payment not found".into()))?;
+
+    match payment.status {
+        PaymentStatus::Confirming => {}
+        PaymentStatus::Confirmed => return Err(ApiError::Conflict("Payment is already confirmed".into())),
+        PaymentStatus::Cancelled => return Err(ApiError::Conflict("Payment is already cancelled".into())),
+    }
+
     state.db.cancel_payment(&id).await
         .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
 
-    Ok((StatusCode::OK, Json(ApiResponse::ok())))
+    let payment = state.db.get_payment(&id).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?
+        .ok_or_else(|| ApiError::NotFound("Payment not found".into()))?;
+
+    Ok((StatusCode::OK, Json(ApiResponse::success(payment))))
 }
 
 #[utoipa::path(
@@ -0,0 +1,112 @@
+use crate::api::public::invoice::not_selected;
+use crate::block_times::BlockTimes;
+use crate::chain_ids::ChainIds;
+use crate::extras::InvoiceExtrasStore;
+use crate::model::core::{InvoiceStatusSchema, PaymentSchema};
+use crate::model::public::{payment_uri, PaymentProgress, PublicChainModel, PublicCheckoutModel,
+                           PublicPaymentModel, PublicTokenModel};
+use crate::model::{ApiError, ApiResponse, Empty, InvoiceModel};
+use crate::pending::PendingInvoiceStore;
+use crate::watcher::processed_head;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use necko3_core::db::DatabaseAdapter;
+use necko3_core::deps::format_units;
+use necko3_core::model::{Pagination, PaymentFilter};
+use necko3_core::AppState;
+use std::sync::Arc;
+
+/// Payments shown on the checkout, an invoice rarely gets more than a handful
+const MAX_PAYMENTS: u32 = 100;
+
+#[utoipa::path(
+    get,
+    path = "/public/checkout/{id}",
+    params(
+        ("id" = String, Path, description = "Invoice UUID")
+    ),
+    responses(
+        (status = 200, description = "Invoice, payments, chain and token in one document, \
+            for rendering a payment page", body = ApiResponse<PublicCheckoutModel>),
+        (status = 404, description = "Invoice not found", body = ApiResponse<Empty>),
+        (status = 409, description = "Payer hasn't chosen a network/token yet (see /public/invoice/{id}/options), or the invoice was cancelled before", body = ApiResponse<Empty>),
+        (status = 500, description = "Server error", body = ApiResponse<Empty>)
+    ),
+    tag = "Public",
+    security(
+        ()
+    )
+)]
+pub async fn get_checkout(
+    State(state): State<Arc<AppState>>,
+    State(pending): State<Arc<PendingInvoiceStore>>,
+    State(extras): State<Arc<InvoiceExtrasStore>>,
+    State(block_times): State<Arc<BlockTimes>>,
+    State(chain_ids): State<Arc<ChainIds>>,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<ApiResponse<PublicCheckoutModel>>), ApiError> {
+    let invoice = match state.db.get_invoice(&id).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))? {
+        Some(invoice) => invoice,
+        None => return Err(not_selected(&pending, &id).await),
+    };
+
+    let invoice_extras = extras.get(&invoice.id).await;
+    let invoice = InvoiceModel::new(invoice, invoice_extras);
+
+    let chain: PublicChainModel = state.db.get_chain(&invoice.network).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?
+        .ok_or_else(|| ApiError::InternalServerError("Chain of the invoice not found".into()))?
+        .config().read().unwrap().clone().into();
+
+    let token: Option<PublicTokenModel> = match invoice.token == chain.native_symbol {
+        true => None,
+        false => Some(state.db.get_token(&invoice.network, &invoice.token).await
+            .map_err(|e| ApiError::InternalServerError(e.to_string()))?
+            .ok_or_else(|| ApiError::InternalServerError("Token of the invoice not found".into()))?
+            .into()),
+    };
+
+    let head = processed_head(&state, &block_times, &invoice.network).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+    let seconds_per_block = block_times.seconds_per_block(&invoice.network);
+
+    let mut payments = state.db.get_payments(PaymentFilter {
+        invoice_id: Some(invoice.id.clone()),
+        pagination: Pagination { limit: MAX_PAYMENTS, offset: 0 },
+        ..Default::default()
+    }).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?
+        .items
+        .into_iter()
+        .map(|payment| {
+            let payment = PaymentSchema::from(payment);
+            let progress = PaymentProgress::new(&payment, head, seconds_per_block);
+            PublicPaymentModel::new(payment, invoice.decimals, progress)
+        })
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+
+    payments.sort_by_key(|payment| payment.created_at);
+
+    let remaining_raw = invoice.amount_raw.saturating_sub(invoice.paid_raw);
+    let remaining = format_units(remaining_raw, invoice.decimals)
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+
+    // without the chain id a wallet would assume mainnet, so no link rather than a wrong one
+    let payment_uri = chain_ids.get(&invoice.network)
+        .filter(|_| matches!(invoice.status, InvoiceStatusSchema::Pending) && !remaining_raw.is_zero())
+        .map(|chain_id| payment_uri(&invoice.address, token.as_ref(), remaining_raw, chain_id));
+
+    let checkout = PublicCheckoutModel {
+        invoice: invoice.into(),
+        remaining,
+        chain,
+        token,
+        payments,
+        payment_uri,
+    };
+
+    Ok((StatusCode::OK, Json(ApiResponse::success(checkout))))
+}
@@ -1,12 +1,23 @@
-use crate::model::public::{PublicInvoiceModel, PublicPaymentModel};
-use crate::model::{ApiError, ApiResponse, Empty, PaginatedVecPage};
+use crate::api::invoice::{applied_tolerance, open_invoice};
+use crate::event_log::{decode_cursor, encode_cursor, EventLog};
+use crate::events::{Event, EventBus};
+use crate::extras::InvoiceExtrasStore;
+use crate::model::public::{PaymentProgress, PublicInvoiceModel, PublicInvoiceOptionsModel, PublicPaymentModel};
+use crate::model::{ApiError, ApiResponse, Empty, PaginatedVecPage, SelectOptionReq};
+use crate::pending::{PendingInvoiceStore, Selection};
+use crate::block_times::BlockTimes;
+use crate::watcher::{processed_head, WatchList};
 use axum::extract::{Path, Query, State};
-use axum::http::StatusCode;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
 use axum::Json;
 use necko3_core::db::DatabaseAdapter;
-use necko3_core::deps::format_units;
 use necko3_core::AppState;
+use std::collections::HashMap;
 use std::sync::Arc;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tracing::warn;
 use necko3_core::model::PaymentFilter;
 use crate::model::core::PaginationParams;
 
@@ -19,6 +30,7 @@ use crate::model::core::PaginationParams;
     responses(
         (status = 200, description = "Public invoice data", body = ApiResponse<PublicInvoiceModel>),
         (status = 404, description = "Invoice not found", body = ApiResponse<Empty>),
+        (status = 409, description = "Payer hasn't chosen a network/token yet (see /public/invoice/{id}/options), or the invoice was cancelled before", body = ApiResponse<Empty>),
         (status = 500, description = "Server error", body = ApiResponse<Empty>)
     ),
     tag = "Public",
@@ -28,13 +40,171 @@ use crate::model::core::PaginationParams;
 )]
 pub async fn get_invoice_data(
     State(state): State<Arc<AppState>>,
+    State(pending): State<Arc<PendingInvoiceStore>>,
+    State(extras): State<Arc<InvoiceExtrasStore>>,
     Path(id): Path<String>,
 ) -> Result<(StatusCode, Json<ApiResponse<PublicInvoiceModel>>), ApiError> {
     let invoice = state.db.get_invoice(&id).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+
+    match invoice {
+        Some(invoice) => {
+            let invoice_extras = extras.get(&invoice.id).await;
+            Ok((StatusCode::OK, Json(ApiResponse::success(
+                PublicInvoiceModel::new(invoice, &invoice_extras)))))
+        }
+        None => Err(not_selected(&pending, &id).await),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/public/invoice/{id}/options",
+    params(
+        ("id" = String, Path, description = "Invoice UUID")
+    ),
+    responses(
+        (status = 200, description = "Networks/tokens the payer can choose from", body = ApiResponse<PublicInvoiceOptionsModel>),
+        (status = 404, description = "Invoice not found", body = ApiResponse<Empty>),
+        (status = 409, description = "Option already chosen, or invoice cancelled", body = ApiResponse<Empty>),
+        (status = 500, description = "Server error", body = ApiResponse<Empty>)
+    ),
+    tag = "Public",
+    security(
+        ()
+    )
+)]
+pub async fn get_invoice_options(
+    State(state): State<Arc<AppState>>,
+    State(pending): State<Arc<PendingInvoiceStore>>,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<ApiResponse<PublicInvoiceOptionsModel>>), ApiError> {
+    if let Some(invoice) = pending.get(&id).await {
+        if invoice.is_cancelled() {
+            return Err(ApiError::Conflict("Invoice cancelled".into()));
+        }
+
+        return Ok((StatusCode::OK, Json(ApiResponse::success(invoice.into()))));
+    }
+
+    Err(already_selected_or_missing(&state, &id).await?)
+}
+
+#[utoipa::path(
+    post,
+    path = "/public/invoice/{id}/select",
+    params(
+        ("id" = String, Path, description = "Invoice UUID")
+    ),
+    request_body = SelectOptionReq,
+    responses(
+        (status = 201, description = "Option chosen, address derived", body = ApiResponse<PublicInvoiceModel>),
+        (status = 400, description = "Invoice doesn't offer this network/token", body = ApiResponse<Empty>),
+        (status = 404, description = "Invoice not found", body = ApiResponse<Empty>),
+        (status = 409, description = "Option already chosen or being chosen, or invoice expired or cancelled", body = ApiResponse<Empty>),
+        (status = 500, description = "Server error", body = ApiResponse<Empty>)
+    ),
+    tag = "Public",
+    security(
+        ()
+    )
+)]
+pub async fn select_invoice_option(
+    State(state): State<Arc<AppState>>,
+    State(pending): State<Arc<PendingInvoiceStore>>,
+    State(extras): State<Arc<InvoiceExtrasStore>>,
+    State(watch_list): State<Arc<WatchList>>,
+    Path(id): Path<String>,
+    Json(payload): Json<SelectOptionReq>,
+) -> Result<(StatusCode, Json<ApiResponse<PublicInvoiceModel>>), ApiError> {
+    // only one of several concurrent selections gets to start
+    let invoice = match pending.begin_selection(&id).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))? {
+        Some(Selection::Started(invoice)) => invoice,
+        Some(Selection::Refused(invoice)) if invoice.is_cancelled() => {
+            return Err(ApiError::Conflict("Invoice cancelled".into()));
+        }
+        Some(Selection::Refused(invoice)) if invoice.is_expired() => {
+            return Err(ApiError::Conflict("Invoice expired".into()));
+        }
+        Some(Selection::Refused(_)) => {
+            return Err(ApiError::Conflict("Network/token is being chosen right now".into()));
+        }
+        None => return Err(already_selected_or_missing(&state, &id).await?),
+    };
+
+    // a selection that died after creating the core invoice left the pending one behind
+    if state.db.get_invoice(&id).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?
+        .is_some() {
+        pending.finish_selection(&id).await
+            .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+        return Err(ApiError::Conflict("Network/token already chosen for this invoice".into()));
+    }
+
+    let Some(option) = invoice.option(&payload.network, &payload.token).cloned() else {
+        pending.abort_selection(&invoice).await
+            .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+        return Err(ApiError::BadRequest(format!("Invoice doesn't accept {} ({})",
+                                                payload.token, payload.network)));
+    };
+
+    let original_extras = extras.get(&id).await;
+    let mut invoice_extras = original_extras.clone();
+    invoice_extras.fiat = option.fiat.clone();
+    invoice_extras.webhook = invoice.webhook();
+    invoice_extras.tolerance = match applied_tolerance(&option) {
+        Ok(tolerance) => tolerance,
+        Err(e) => {
+            pending.abort_selection(&invoice).await
+                .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+            return Err(e);
+        }
+    };
+
+    extras.put(&id, invoice_extras.clone()).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+
+    match open_invoice(&state, &watch_list, invoice.clone(), option).await {
+        Ok(opened) => {
+            // the core invoice is what counts now, a leftover is cleaned up by the next selection attempt
+            if let Err(e) = pending.finish_selection(&id).await {
+                warn!(invoice_id = %id, error = %e, "Failed to drop the selected pending invoice");
+            }
+
+            Ok((StatusCode::CREATED, Json(ApiResponse::success(
+                PublicInvoiceModel::new(opened, &invoice_extras)))))
+        }
+        Err(e) => {
+            // back to awaiting a selection, as if this one never happened
+            extras.put(&id, original_extras).await
+                .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+            pending.abort_selection(&invoice).await
+                .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+            Err(e)
+        }
+    }
+}
+
+/// Error for an invoice core doesn't have (yet): it's still awaiting selection, was cancelled before it, or doesn't exist
+pub(crate) async fn not_selected(pending: &PendingInvoiceStore, id: &str) -> ApiError {
+    match pending.get(id).await {
+        Some(invoice) if invoice.is_cancelled() => ApiError::Conflict("Invoice cancelled".into()),
+        Some(_) => ApiError::Conflict("Invoice is waiting for a network/token to be chosen".into()),
+        None => ApiError::NotFound("Invoice not found".into()),
+    }
+}
+
+/// Error for an invoice that isn't awaiting selection: either it got its option already or doesn't exist
+async fn already_selected_or_missing(state: &AppState, id: &str) -> Result<ApiError, ApiError> {
+    let exists = state.db.get_invoice(id).await
         .map_err(|e| ApiError::InternalServerError(e.to_string()))?
-        .ok_or_else(|| ApiError::NotFound("Invoice not found".into()))?;
+        .is_some();
 
-    Ok((StatusCode::OK, Json(ApiResponse::success(invoice.into()))))
+    Ok(match exists {
+        true => ApiError::Conflict("Network/token already chosen for this invoice".into()),
+        false => ApiError::NotFound("Invoice not found".into()),
+    })
 }
 
 #[utoipa::path(
@@ -55,6 +225,7 @@ pub async fn get_invoice_data(
 )]
 pub async fn get_invoice_payments(
     State(state): State<Arc<AppState>>,
+    State(block_times): State<Arc<BlockTimes>>,
     Path(id): Path<String>,
     Query(pagination): Query<PaginationParams>,
 ) -> Result<(StatusCode, Json<ApiResponse<PaginatedVecPage<PublicPaymentModel>>>), ApiError> {
@@ -68,30 +239,28 @@ pub async fn get_invoice_payments(
         .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
 
     let mut public_payments = vec![];
+    let mut heads = HashMap::new();
 
     for p in payments.items {
-        let chain = p.network;
-        let token = p.token;
-
-        let decimals_opt = state.db.get_token_decimals(&chain, &token).await
+        let decimals_opt = state.db.get_token_decimals(&p.network, &p.token).await
             .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
 
+        let head = match heads.get(&p.network) {
+            Some(head) => *head,
+            None => {
+                let head = processed_head(&state, &block_times, &p.network).await
+                    .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+                heads.insert(p.network.clone(), head);
+                head
+            }
+        };
+
         if let Some(decimals) = decimals_opt {
-            let amount = format_units(p.amount_raw, decimals)
-                .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+            let payment = p.into();
+            let progress = PaymentProgress::new(&payment, head, block_times.seconds_per_block(&payment.network));
 
-            public_payments.push(PublicPaymentModel {
-                id: p.id,
-                invoice_id: p.invoice_id,
-                from: p.from,
-                to: p.to,
-                network: chain,
-                token,
-                tx_hash: p.tx_hash,
-                amount,
-                status: p.status.into(),
-                created_at: p.created_at,
-            })
+            public_payments.push(PublicPaymentModel::new(payment, decimals, progress)
+                .map_err(|e| ApiError::InternalServerError(e.to_string()))?);
         }
     }
 
@@ -103,4 +272,111 @@ pub async fn get_invoice_payments(
     };
 
     Ok((StatusCode::OK, Json(ApiResponse::success(payments_page))))
-}
\ No newline at end of file
+}
+
+#[utoipa::path(
+    get,
+    path = "/public/invoice/{id}/events",
+    params(
+        ("id" = String, Path, description = "Invoice UUID"),
+        ("Last-Event-ID" = Option<String>, Header, description = "Id of the last event received, \
+            the events logged after it are replayed from the event log instead of the `invoice` event")
+    ),
+    responses(
+        (status = 200, description = "Server-Sent Events. Starts with an `invoice` event \
+            (PublicInvoiceModel), or `reset` when Last-Event-ID is unknown or pruned from the event log: \
+            the state to start over from. Then `payment_detected`, `payment_confirmations`, \
+            `payment_confirmed` and `payment_cancelled` carry a PublicPaymentModel, \
+            `invoice_paid`, `invoice_expired` and `invoice_cancelled` a PublicInvoiceModel. \
+            Ids are event log cursors, `payment_confirmations` has none and isn't replayed",
+            content_type = "text/event-stream", body = String),
+        (status = 404, description = "Invoice not found", body = ApiResponse<Empty>),
+        (status = 409, description = "Payer hasn't chosen a network/token yet (see /public/invoice/{id}/options), or the invoice was cancelled before", body = ApiResponse<Empty>),
+        (status = 500, description = "Server error", body = ApiResponse<Empty>)
+    ),
+    tag = "Public",
+    security(
+        ()
+    )
+)]
+pub async fn get_invoice_events(
+    State(state): State<Arc<AppState>>,
+    State(pending): State<Arc<PendingInvoiceStore>>,
+    State(extras): State<Arc<InvoiceExtrasStore>>,
+    State(events): State<Arc<EventBus>>,
+    State(log): State<Arc<EventLog>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<SseEvent, axum::Error>>>, ApiError> {
+    let last_event_id = headers.get("last-event-id")
+        .map(|value| value.to_str().ok().and_then(decode_cursor));
+
+    // subscribe before looking at the log, so nothing happens unseen in between
+    let live = events.subscribe();
+    let newest = log.last_sequence();
+
+    let invoice = match state.db.get_invoice(&id).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))? {
+        Some(invoice) => invoice,
+        None => return Err(not_selected(&pending, &id).await),
+    };
+
+    let mut backlog = vec![];
+    let mut seen = newest;
+
+    match last_event_id {
+        // resumed: whatever was logged for the invoice since, then on live
+        Some(Some(last)) if last <= newest && !log.is_pruned(last).await => {
+            let replayed: Vec<Event> = log.for_invoice(&id).await.iter()
+                .filter(|entry| entry.sequence > last)
+                .map(Event::logged)
+                .collect();
+
+            seen = replayed.last().and_then(|event| event.sequence).unwrap_or(last).max(newest);
+            backlog.extend(replayed.iter().map(sse_event));
+        }
+        // an id the log doesn't have (anymore): start over from the current state
+        resumed => {
+            let invoice_extras = extras.get(&invoice.id).await;
+            let kind = match resumed {
+                Some(_) => "reset",
+                None => "invoice",
+            };
+
+            backlog.push(SseEvent::default()
+                .id(encode_cursor(newest))
+                .event(kind)
+                .json_data(PublicInvoiceModel::new(invoice, &invoice_extras)));
+        }
+    }
+
+    // a client that falls too far behind is cut off and resumes through Last-Event-ID
+    let live = BroadcastStream::new(live)
+        .take_while(|received| received.is_ok())
+        .filter_map(move |received| received.ok()
+            .filter(|event| event.sequence.is_none_or(|sequence| sequence > seen)
+                && event.invoice.id == id && !event.kind.is_webhook()))
+        .map(|event| sse_event(&event));
+
+    Ok(Sse::new(tokio_stream::iter(backlog).chain(live)).keep_alive(KeepAlive::default()))
+}
+
+/// Payment events carry the payment, the others the invoice
+fn sse_event(event: &Event) -> Result<SseEvent, axum::Error> {
+    let mut sse = SseEvent::default().event(event.kind.as_str());
+
+    // events that aren't logged leave the client's Last-Event-ID where it was
+    if let Some(sequence) = event.sequence {
+        sse = sse.id(encode_cursor(sequence));
+    }
+
+    match &event.payment {
+        Some(payment) => {
+            let payment = PublicPaymentModel::new(payment.clone(), event.invoice.decimals,
+                                                  event.progress.unwrap_or_default())
+                .map_err(|e| axum::Error::new(e.into_boxed_dyn_error()))?;
+            sse.json_data(payment)
+        }
+        None => sse.json_data(PublicInvoiceModel::from(event.invoice.clone())),
+    }
+}
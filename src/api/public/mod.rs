@@ -1,7 +1,9 @@
 pub mod invoice;
 pub mod chain;
 pub mod token;
+pub mod checkout;
 
 pub use invoice::*;
 pub use chain::*;
-pub use token::*;
\ No newline at end of file
+pub use token::*;
+pub use checkout::*;
\ No newline at end of file
@@ -0,0 +1,156 @@
+use crate::api::auth::{ApiKeyStore, FailedAuthTracker, SignatureVerifier};
+use crate::api::idempotency::IdempotencyStore;
+use crate::api::invoice::InvoiceStores;
+use crate::block_times::BlockTimes;
+use crate::chain_ids::ChainIds;
+use crate::delivery::{AttemptStore, CircuitBreaker, DeliveryStore, EndpointStore, UrlGuard};
+use crate::event_log::EventLog;
+use crate::events::EventBus;
+use crate::extras::InvoiceExtrasStore;
+use crate::pending::PendingInvoiceStore;
+use crate::rates::RateProvider;
+use crate::tolerance::ToleranceStore;
+use crate::watcher::WatchList;
+use axum::extract::FromRef;
+use necko3_core::state::AppState;
+use std::sync::Arc;
+
+/// Router state. Wraps the core `AppState` together with backend-only services;
+/// handlers extract just the part they need through `FromRef`.
+#[derive(Clone)]
+pub struct ApiState {
+    pub app: Arc<AppState>,
+    pub api_keys: Arc<ApiKeyStore>,
+    pub auth_failures: Arc<FailedAuthTracker>,
+    pub signatures: Arc<SignatureVerifier>,
+    pub idempotency: Arc<IdempotencyStore>,
+    pub extras: Arc<InvoiceExtrasStore>,
+    pub pending: Arc<PendingInvoiceStore>,
+    pub rates: Arc<dyn RateProvider>,
+    pub tolerances: Arc<ToleranceStore>,
+    pub events: Arc<EventBus>,
+    pub event_log: Arc<EventLog>,
+    pub deliveries: Arc<DeliveryStore>,
+    pub attempts: Arc<AttemptStore>,
+    pub endpoints: Arc<EndpointStore>,
+    pub url_guard: Arc<UrlGuard>,
+    pub breaker: Arc<CircuitBreaker>,
+    pub block_times: Arc<BlockTimes>,
+    pub chain_ids: Arc<ChainIds>,
+    pub watch_list: Arc<WatchList>,
+}
+
+impl FromRef<ApiState> for Arc<AppState> {
+    fn from_ref(state: &ApiState) -> Self {
+        state.app.clone()
+    }
+}
+
+impl FromRef<ApiState> for Arc<ApiKeyStore> {
+    fn from_ref(state: &ApiState) -> Self {
+        state.api_keys.clone()
+    }
+}
+
+impl FromRef<ApiState> for Arc<IdempotencyStore> {
+    fn from_ref(state: &ApiState) -> Self {
+        state.idempotency.clone()
+    }
+}
+
+impl FromRef<ApiState> for Arc<InvoiceExtrasStore> {
+    fn from_ref(state: &ApiState) -> Self {
+        state.extras.clone()
+    }
+}
+
+impl FromRef<ApiState> for Arc<PendingInvoiceStore> {
+    fn from_ref(state: &ApiState) -> Self {
+        state.pending.clone()
+    }
+}
+
+impl FromRef<ApiState> for Arc<dyn RateProvider> {
+    fn from_ref(state: &ApiState) -> Self {
+        state.rates.clone()
+    }
+}
+
+impl FromRef<ApiState> for Arc<ToleranceStore> {
+    fn from_ref(state: &ApiState) -> Self {
+        state.tolerances.clone()
+    }
+}
+
+impl FromRef<ApiState> for Arc<EventBus> {
+    fn from_ref(state: &ApiState) -> Self {
+        state.events.clone()
+    }
+}
+
+impl FromRef<ApiState> for Arc<EventLog> {
+    fn from_ref(state: &ApiState) -> Self {
+        state.event_log.clone()
+    }
+}
+
+impl FromRef<ApiState> for Arc<DeliveryStore> {
+    fn from_ref(state: &ApiState) -> Self {
+        state.deliveries.clone()
+    }
+}
+
+impl FromRef<ApiState> for Arc<AttemptStore> {
+    fn from_ref(state: &ApiState) -> Self {
+        state.attempts.clone()
+    }
+}
+
+impl FromRef<ApiState> for Arc<EndpointStore> {
+    fn from_ref(state: &ApiState) -> Self {
+        state.endpoints.clone()
+    }
+}
+
+impl FromRef<ApiState> for Arc<UrlGuard> {
+    fn from_ref(state: &ApiState) -> Self {
+        state.url_guard.clone()
+    }
+}
+
+impl FromRef<ApiState> for Arc<CircuitBreaker> {
+    fn from_ref(state: &ApiState) -> Self {
+        state.breaker.clone()
+    }
+}
+
+impl FromRef<ApiState> for Arc<BlockTimes> {
+    fn from_ref(state: &ApiState) -> Self {
+        state.block_times.clone()
+    }
+}
+
+impl FromRef<ApiState> for Arc<ChainIds> {
+    fn from_ref(state: &ApiState) -> Self {
+        state.chain_ids.clone()
+    }
+}
+
+impl FromRef<ApiState> for Arc<WatchList> {
+    fn from_ref(state: &ApiState) -> Self {
+        state.watch_list.clone()
+    }
+}
+
+impl FromRef<ApiState> for InvoiceStores {
+    fn from_ref(state: &ApiState) -> Self {
+        Self {
+            extras: state.extras.clone(),
+            pending: state.pending.clone(),
+            rates: state.rates.clone(),
+            tolerances: state.tolerances.clone(),
+            url_guard: state.url_guard.clone(),
+            watch_list: state.watch_list.clone(),
+        }
+    }
+}
@@ -1,11 +1,16 @@
-use crate::model::core::WebhookSchema;
+use crate::delivery::{route, AttemptStore, Delivery, DeliveryAttempt, DeliveryStore, EndpointStore};
+use crate::event_log::EventLog;
+use crate::model::core::{WebhookSchema, WebhookStatusSchema};
 use crate::model::core::{PaginationParams, WebhookFilterSchema};
-use crate::model::{ApiError, ApiResponse, Empty, PaginatedVecPage};
+use crate::extras::InvoiceExtrasStore;
+use crate::model::envelope::EventSnapshot;
+use crate::model::{ApiError, ApiResponse, Empty, InvoiceModel, PaginatedVecPage, WebhookModel};
 use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
 use axum::Json;
+use chrono::Utc;
 use necko3_core::db::DatabaseAdapter;
-use necko3_core::model::Webhook;
+use necko3_core::model::Pagination;
 use necko3_core::AppState;
 use std::sync::Arc;
 
@@ -16,19 +21,51 @@ use std::sync::Arc;
         ("id" = String, Path, description = "Webhook UUID")
     ),
     responses(
-        (status = 200, description = "Webhook cancelled", body = ApiResponse<Empty>),
+        (status = 200, description = "Webhook cancelled", body = ApiResponse<WebhookSchema>),
+        (status = 404, description = "Webhook not found", body = ApiResponse<Empty>),
+        (status = 409, description = "Webhook is being sent, already sent, failed or cancelled", body = ApiResponse<Empty>),
         (status = 500, description = "Server error", body = ApiResponse<Empty>)
     ),
     tag = "Webhooks"
 )]
 pub async fn cancel_webhook(
     State(state): State<Arc<AppState>>,
+    State(extras): State<Arc<InvoiceExtrasStore>>,
+    State(deliveries): State<Arc<DeliveryStore>>,
     Path(id): Path<String>,
-) -> Result<(StatusCode, Json<ApiResponse<String>>), ApiError> {
-    state.db.cancel_webhook(&id).await
+) -> Result<(StatusCode, Json<ApiResponse<WebhookModel>>), ApiError> {
+    let webhook = find_webhook(&state, &deliveries, &id).await?;
+
+    match webhook.status {
+        WebhookStatusSchema::Pending => {}
+        WebhookStatusSchema::Processing => return Err(ApiError::Conflict("Webhook is being sent right now".into())),
+        WebhookStatusSchema::Sent => return Err(ApiError::Conflict("Webhook is already sent".into())),
+        WebhookStatusSchema::Failed => return Err(ApiError::Conflict("Webhook has already failed".into())),
+        WebhookStatusSchema::Cancelled => return Err(ApiError::Conflict("Webhook is already cancelled".into())),
+    }
+
+    let owned_by_backend = deliveries.get(&id).await.is_some();
+
+    if owned_by_backend {
+        deliveries.update(&id, |delivery| {
+            if matches!(delivery.status, WebhookStatusSchema::Pending) {
+                delivery.status = WebhookStatusSchema::Cancelled;
+            }
+        }).await
+            .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+    } else {
+        state.db.cancel_webhook(&id).await
+            .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+    }
+
+    let webhook = find_webhook(&state, &deliveries, &id).await?;
+
+    let invoice = state.db.get_invoice(&webhook.invoice_id).await
         .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+    let invoice_extras = extras.get(&webhook.invoice_id).await;
 
-    Ok((StatusCode::OK, Json(ApiResponse::ok())))
+    Ok((StatusCode::OK, Json(ApiResponse::success(
+        WebhookModel::new(webhook, invoice.as_ref(), invoice_extras)))))
 }
 
 #[utoipa::path(
@@ -39,19 +76,61 @@ pub async fn cancel_webhook(
         PaginationParams
     ),
     responses(
-        (status = 200, description = "List all webhooks", body = ApiResponse<PaginatedVecPage<WebhookSchema>>),
+        (status = 200, description = "List all webhooks: the ones core queued, followed by the ones \
+            only the backend sends (replays, webhook endpoints, event types core doesn't know)", body = ApiResponse<PaginatedVecPage<WebhookSchema>>),
         (status = 500, description = "Server error", body = ApiResponse<Empty>)
     ),
     tag = "Webhooks"
 )]
 pub async fn get_webhooks(
     State(state): State<Arc<AppState>>,
+    State(extras): State<Arc<InvoiceExtrasStore>>,
+    State(deliveries): State<Arc<DeliveryStore>>,
     Query(filter): Query<WebhookFilterSchema>,
-) -> Result<(StatusCode, Json<ApiResponse<PaginatedVecPage<Webhook>>>), ApiError> {
-    let webhooks = state.db.get_webhooks(filter.into()).await
-        .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+) -> Result<(StatusCode, Json<ApiResponse<PaginatedVecPage<WebhookModel>>>), ApiError> {
+    let pagination = Pagination::from(filter.pagination);
+    let own = deliveries.list_own(&filter).await;
+
+    let mut webhooks = vec![];
+    let mut core_total = 0;
+
+    // core has none of the event types it doesn't know
+    if filter.event_type.is_none_or(|event_type| event_type.is_core()) {
+        let core = state.db.get_webhooks(filter.into()).await
+            .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+        core_total = core.total;
+
+        for webhook in core.items {
+            webhooks.push(deliveries.resolve(webhook).await
+                .map_err(|e| ApiError::InternalServerError(e.to_string()))?);
+        }
+    }
 
-    Ok((StatusCode::OK, Json(ApiResponse::success(webhooks.into()))))
+    // the backend's own ones are paged as if they came right after core's
+    let skip = pagination.offset.saturating_sub(core_total) as usize;
+    let room = (pagination.limit as usize).saturating_sub(webhooks.len());
+    webhooks.extend(own.iter().skip(skip).take(room).cloned());
+
+    let mut items = Vec::with_capacity(webhooks.len());
+
+    for webhook in webhooks {
+        let invoice = state.db.get_invoice(&webhook.invoice_id).await
+            .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+        let invoice_extras = extras.get(&webhook.invoice_id).await;
+        items.push(WebhookModel::new(webhook, invoice.as_ref(), invoice_extras));
+    }
+
+    let webhooks_page = PaginatedVecPage {
+        items,
+        total: core_total + own.len() as u64,
+        page_size: pagination.limit,
+        page: match pagination.limit {
+            0 => 1,
+            limit => pagination.offset / limit as u64 + 1,
+        },
+    };
+
+    Ok((StatusCode::OK, Json(ApiResponse::success(webhooks_page))))
 }
 
 #[utoipa::path(
@@ -69,11 +148,155 @@ pub async fn get_webhooks(
 )]
 pub async fn get_webhook(
     State(state): State<Arc<AppState>>,
+    State(extras): State<Arc<InvoiceExtrasStore>>,
+    State(deliveries): State<Arc<DeliveryStore>>,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<ApiResponse<WebhookModel>>), ApiError> {
+    let webhook = find_webhook(&state, &deliveries, &id).await?;
+
+    let invoice = state.db.get_invoice(&webhook.invoice_id).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+    let invoice_extras = extras.get(&webhook.invoice_id).await;
+
+    Ok((StatusCode::OK, Json(ApiResponse::success(
+        WebhookModel::new(webhook, invoice.as_ref(), invoice_extras)))))
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhook/{id}/attempts",
+    params(
+        ("id" = String, Path, description = "Webhook UUID")
+    ),
+    responses(
+        (status = 200, description = "Every attempt the backend made, oldest first. Only webhooks \
+            of invoices created before the backend took over delivery are sent by core, their \
+            attempts aren't recorded", body = ApiResponse<Vec<DeliveryAttempt>>),
+        (status = 404, description = "Webhook not found", body = ApiResponse<Empty>),
+        (status = 500, description = "Server error", body = ApiResponse<Empty>)
+    ),
+    tag = "Webhooks"
+)]
+pub async fn get_webhook_attempts(
+    State(state): State<Arc<AppState>>,
+    State(deliveries): State<Arc<DeliveryStore>>,
+    State(attempts): State<Arc<AttemptStore>>,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<ApiResponse<Vec<DeliveryAttempt>>>), ApiError> {
+    let webhook = find_webhook(&state, &deliveries, &id).await?;
+
+    Ok((StatusCode::OK, Json(ApiResponse::success(attempts.list(&webhook.id).await))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/webhook/{id}/retry",
+    params(
+        ("id" = String, Path, description = "Webhook UUID")
+    ),
+    responses(
+        (status = 200, description = "Attempts reset, delivery scheduled right away. The retry deadline counts from now", body = ApiResponse<WebhookSchema>),
+        (status = 404, description = "Webhook not found", body = ApiResponse<Empty>),
+        (status = 409, description = "Webhook is still pending or being sent", body = ApiResponse<Empty>),
+        (status = 500, description = "Server error", body = ApiResponse<Empty>)
+    ),
+    tag = "Webhooks"
+)]
+pub async fn retry_webhook(
+    State(state): State<Arc<AppState>>,
+    State(extras): State<Arc<InvoiceExtrasStore>>,
+    State(deliveries): State<Arc<DeliveryStore>>,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<ApiResponse<WebhookModel>>), ApiError> {
+    let webhook = find_webhook(&state, &deliveries, &id).await?;
+
+    match webhook.status {
+        WebhookStatusSchema::Pending => return Err(ApiError::Conflict("Webhook is still pending".into())),
+        WebhookStatusSchema::Processing => return Err(ApiError::Conflict("Webhook is being sent right now".into())),
+        WebhookStatusSchema::Sent | WebhookStatusSchema::Failed | WebhookStatusSchema::Cancelled => {}
+    }
+
+    // the backend takes the webhook over from core, under the same id
+    let webhook = webhook.retry(Utc::now());
+
+    deliveries.schedule(webhook.clone()).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+
+    let invoice = state.db.get_invoice(&webhook.invoice_id).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+    let invoice_extras = extras.get(&webhook.invoice_id).await;
+
+    Ok((StatusCode::OK, Json(ApiResponse::success(
+        WebhookModel::new(webhook, invoice.as_ref(), invoice_extras)))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/invoice/{id}/webhooks/replay",
+    params(
+        ("id" = String, Path, description = "Invoice UUID")
+    ),
+    responses(
+        (status = 201, description = "One new webhook per event of the invoice (see GET /event), \
+            oldest first, with the invoice and payment as they were when the event happened. Sent to the \
+            invoice's current webhook URL (as v1, like every webhook to an invoice's own URL). Invoices \
+            without one get theirs sent to the webhook endpoints subscribed to the event, in their api_version", body = ApiResponse<Vec<WebhookSchema>>),
+        (status = 404, description = "Invoice not found", body = ApiResponse<Empty>),
+        (status = 409, description = "Invoice has no webhook URL and no webhook endpoint is enabled", body = ApiResponse<Empty>),
+        (status = 500, description = "Server error", body = ApiResponse<Empty>)
+    ),
+    tag = "Webhooks"
+)]
+pub async fn replay_invoice_webhooks(
+    State(state): State<Arc<AppState>>,
+    State(extras): State<Arc<InvoiceExtrasStore>>,
+    State(deliveries): State<Arc<DeliveryStore>>,
+    State(endpoints): State<Arc<EndpointStore>>,
+    State(log): State<Arc<EventLog>>,
     Path(id): Path<String>,
-) -> Result<(StatusCode, Json<ApiResponse<Webhook>>), ApiError> {
-    let payment = state.db.get_webhook(&id).await
+) -> Result<(StatusCode, Json<ApiResponse<Vec<WebhookModel>>>), ApiError> {
+    let invoice = state.db.get_invoice(&id).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?
+        .ok_or_else(|| ApiError::NotFound("Invoice not found".into()))?;
+
+    let invoice_extras = extras.get(&id).await;
+
+    // sent where the invoice's webhooks go now
+    let current = InvoiceModel::new(invoice.clone(), invoice_extras.clone());
+
+    if current.webhook_url.is_none() && !endpoints.list().await.iter().any(|e| e.enabled) {
+        return Err(ApiError::Conflict("Invoice has no webhook URL and no webhook endpoint is enabled".into()));
+    }
+
+    let mut replayed = vec![];
+
+    for entry in log.for_invoice(&id).await {
+        // carrying the invoice and payment as they were when the event happened
+        let snapshot = EventSnapshot::new(&entry, entry.invoice.clone(), entry.payment.clone());
+
+        let webhooks = route(&endpoints, &current, &entry.event, &snapshot).await;
+
+        for webhook in webhooks {
+            deliveries.schedule(webhook.clone()).await
+                .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+
+            replayed.push(WebhookModel::new(webhook, Some(&invoice), invoice_extras.clone()));
+        }
+    }
+
+    Ok((StatusCode::CREATED, Json(ApiResponse::success(replayed))))
+}
+
+/// Looks in the backend's deliveries first, they shadow the core webhook they took over
+async fn find_webhook(state: &AppState, deliveries: &DeliveryStore, id: &str) -> Result<Delivery, ApiError> {
+    if let Some(delivery) = deliveries.get(id).await {
+        return Ok(delivery);
+    }
+
+    let webhook = state.db.get_webhook(id).await
         .map_err(|e| ApiError::InternalServerError(e.to_string()))?
         .ok_or_else(|| ApiError::NotFound("Webhook not found".into()))?;
 
-    Ok((StatusCode::OK, Json(ApiResponse::success(payment))))
-}
\ No newline at end of file
+    webhook.try_into()
+        .map_err(|e: anyhow::Error| ApiError::InternalServerError(e.to_string()))
+}
@@ -0,0 +1,258 @@
+use crate::delivery::{CircuitBreaker, DestinationHealth, EndpointStore, RetryPolicyOverrides, UrlGuard};
+use crate::model::core::WebhookEventType;
+use crate::model::envelope::ApiVersion;
+use crate::model::webhook_endpoint::{CreateWebhookEndpointReq, CreatedWebhookEndpointModel,
+                                     RotateWebhookSecretReq, UpdateWebhookEndpointReq, WebhookEndpointModel};
+use crate::model::{ApiError, ApiResponse, Empty};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::TimeDelta;
+use std::sync::Arc;
+
+/// How long the old secret keeps signing after a rotation, unless the request says otherwise
+pub(crate) const DEFAULT_SECRET_GRACE_PERIOD: u64 = 86400;
+pub(crate) const MAX_SECRET_GRACE_PERIOD: u64 = 30 * 86400;
+
+#[utoipa::path(
+    post,
+    path = "/webhook-endpoint",
+    request_body = CreateWebhookEndpointReq,
+    responses(
+        (status = 201, description = "Webhook endpoint created. The signing secret is returned only once", body = ApiResponse<CreatedWebhookEndpointModel>),
+        (status = 400, description = "Bad Request (e.g. URL not https or pointing at a private address)", body = ApiResponse<Empty>),
+        (status = 500, description = "Server Error", body = ApiResponse<Empty>)
+    ),
+    tag = "Webhook Endpoints"
+)]
+pub async fn create_webhook_endpoint(
+    State(endpoints): State<Arc<EndpointStore>>,
+    State(url_guard): State<Arc<UrlGuard>>,
+    Json(payload): Json<CreateWebhookEndpointReq>,
+) -> Result<(StatusCode, Json<ApiResponse<CreatedWebhookEndpointModel>>), ApiError> {
+    validate_url(&url_guard, &payload.url).await?;
+    let event_types = validate_event_types(payload.event_types)?;
+    let retry_policy = payload.retry_policy.unwrap_or_default();
+    validate_retry_policy(&retry_policy)?;
+
+    let api_version = payload.api_version.unwrap_or(ApiVersion::LATEST);
+
+    let endpoint = endpoints.create(payload.url, event_types, payload.description, retry_policy, api_version).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+
+    let created = CreatedWebhookEndpointModel {
+        secret: endpoint.secret.clone(),
+        info: endpoint.into(),
+    };
+
+    Ok((StatusCode::CREATED, Json(ApiResponse::success(created))))
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhook-endpoint",
+    responses(
+        (status = 200, description = "List all webhook endpoints", body = ApiResponse<Vec<WebhookEndpointModel>>),
+        (status = 500, description = "Server Error", body = ApiResponse<Empty>)
+    ),
+    tag = "Webhook Endpoints"
+)]
+pub async fn get_webhook_endpoints(
+    State(endpoints): State<Arc<EndpointStore>>,
+) -> Result<(StatusCode, Json<ApiResponse<Vec<WebhookEndpointModel>>>), ApiError> {
+    let endpoints = endpoints.list().await
+        .into_iter()
+        .map(WebhookEndpointModel::from)
+        .collect();
+
+    Ok((StatusCode::OK, Json(ApiResponse::success(endpoints))))
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhook-endpoint/health",
+    responses(
+        (status = 200, description = "Circuit state, failure rate and last error of every destination \
+            the backend sent webhooks to since it started, the worst first. Webhooks sent by core aren't counted", body = ApiResponse<Vec<DestinationHealth>>),
+        (status = 500, description = "Server Error", body = ApiResponse<Empty>)
+    ),
+    tag = "Webhook Endpoints"
+)]
+pub async fn get_webhook_endpoint_health(
+    State(breaker): State<Arc<CircuitBreaker>>,
+) -> Result<(StatusCode, Json<ApiResponse<Vec<DestinationHealth>>>), ApiError> {
+    Ok((StatusCode::OK, Json(ApiResponse::success(breaker.health()))))
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhook-endpoint/{id}",
+    params(
+        ("id" = String, Path, description = "Webhook endpoint UUID")
+    ),
+    responses(
+        (status = 200, description = "Webhook endpoint data", body = ApiResponse<WebhookEndpointModel>),
+        (status = 404, description = "Webhook endpoint not found", body = ApiResponse<Empty>),
+        (status = 500, description = "Server Error", body = ApiResponse<Empty>)
+    ),
+    tag = "Webhook Endpoints"
+)]
+pub async fn get_webhook_endpoint(
+    State(endpoints): State<Arc<EndpointStore>>,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<ApiResponse<WebhookEndpointModel>>), ApiError> {
+    let endpoint = endpoints.get(&id).await
+        .ok_or_else(|| ApiError::NotFound("Webhook endpoint not found".into()))?;
+
+    Ok((StatusCode::OK, Json(ApiResponse::success(endpoint.into()))))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/webhook-endpoint/{id}",
+    params(
+        ("id" = String, Path, description = "Webhook endpoint UUID")
+    ),
+    request_body = UpdateWebhookEndpointReq,
+    responses(
+        (status = 200, description = "Webhook endpoint updated", body = ApiResponse<WebhookEndpointModel>),
+        (status = 400, description = "Bad Request", body = ApiResponse<Empty>),
+        (status = 404, description = "Webhook endpoint not found", body = ApiResponse<Empty>),
+        (status = 500, description = "Server Error", body = ApiResponse<Empty>)
+    ),
+    tag = "Webhook Endpoints"
+)]
+pub async fn update_webhook_endpoint(
+    State(endpoints): State<Arc<EndpointStore>>,
+    State(url_guard): State<Arc<UrlGuard>>,
+    Path(id): Path<String>,
+    Json(payload): Json<UpdateWebhookEndpointReq>,
+) -> Result<(StatusCode, Json<ApiResponse<WebhookEndpointModel>>), ApiError> {
+    if let Some(url) = &payload.url {
+        validate_url(&url_guard, url).await?;
+    }
+
+    let event_types = payload.event_types
+        .map(validate_event_types)
+        .transpose()?;
+
+    if let Some(retry_policy) = &payload.retry_policy {
+        validate_retry_policy(retry_policy)?;
+    }
+
+    let endpoint = endpoints.update(&id, |endpoint| {
+        if let Some(url) = payload.url {
+            endpoint.url = url;
+        }
+        if let Some(event_types) = event_types {
+            endpoint.event_types = event_types;
+        }
+        if let Some(description) = payload.description {
+            endpoint.description = Some(description);
+        }
+        if let Some(enabled) = payload.enabled {
+            endpoint.enabled = enabled;
+        }
+        if let Some(retry_policy) = payload.retry_policy {
+            endpoint.retry_policy = retry_policy;
+        }
+        if let Some(api_version) = payload.api_version {
+            endpoint.api_version = api_version;
+        }
+    }).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?
+        .ok_or_else(|| ApiError::NotFound("Webhook endpoint not found".into()))?;
+
+    Ok((StatusCode::OK, Json(ApiResponse::success(endpoint.into()))))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/webhook-endpoint/{id}",
+    params(
+        ("id" = String, Path, description = "Webhook endpoint UUID")
+    ),
+    responses(
+        (status = 200, description = "Webhook endpoint deleted. Its pending webhooks are cancelled", body = ApiResponse<Empty>),
+        (status = 404, description = "Webhook endpoint not found", body = ApiResponse<Empty>),
+        (status = 500, description = "Server Error", body = ApiResponse<Empty>)
+    ),
+    tag = "Webhook Endpoints"
+)]
+pub async fn delete_webhook_endpoint(
+    State(endpoints): State<Arc<EndpointStore>>,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<ApiResponse<String>>), ApiError> {
+    endpoints.remove(&id).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?
+        .ok_or_else(|| ApiError::NotFound("Webhook endpoint not found".into()))?;
+
+    Ok((StatusCode::OK, Json(ApiResponse::ok())))
+}
+
+#[utoipa::path(
+    post,
+    path = "/webhook-endpoint/{id}/rotate-secret",
+    params(
+        ("id" = String, Path, description = "Webhook endpoint UUID")
+    ),
+    request_body = RotateWebhookSecretReq,
+    responses(
+        (status = 200, description = "New signing secret, returned only once. Until the grace period \
+            ends webhooks carry one signature per secret in X-Signature, comma-separated, newest first", body = ApiResponse<CreatedWebhookEndpointModel>),
+        (status = 400, description = "Bad Request", body = ApiResponse<Empty>),
+        (status = 404, description = "Webhook endpoint not found", body = ApiResponse<Empty>),
+        (status = 500, description = "Server Error", body = ApiResponse<Empty>)
+    ),
+    tag = "Webhook Endpoints"
+)]
+pub async fn rotate_webhook_endpoint_secret(
+    State(endpoints): State<Arc<EndpointStore>>,
+    Path(id): Path<String>,
+    payload: Option<Json<RotateWebhookSecretReq>>,
+) -> Result<(StatusCode, Json<ApiResponse<CreatedWebhookEndpointModel>>), ApiError> {
+    let Json(payload) = payload.unwrap_or_default();
+
+    let grace_period = payload.grace_period.unwrap_or(DEFAULT_SECRET_GRACE_PERIOD);
+    if grace_period > MAX_SECRET_GRACE_PERIOD {
+        return Err(ApiError::BadRequest(format!(
+            "grace_period must not exceed {} seconds", MAX_SECRET_GRACE_PERIOD)));
+    }
+
+    let endpoint = endpoints.rotate_secret(&id, TimeDelta::seconds(grace_period as i64)).await
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))?
+        .ok_or_else(|| ApiError::NotFound("Webhook endpoint not found".into()))?;
+
+    let rotated = CreatedWebhookEndpointModel {
+        secret: endpoint.secret.clone(),
+        info: endpoint.into(),
+    };
+
+    Ok((StatusCode::OK, Json(ApiResponse::success(rotated))))
+}
+
+async fn validate_url(url_guard: &UrlGuard, url: &str) -> Result<(), ApiError> {
+    url_guard.check(url).await
+        .map_err(|e| ApiError::BadRequest(format!("Invalid URL: {}", e)))
+}
+
+fn validate_retry_policy(retry_policy: &RetryPolicyOverrides) -> Result<(), ApiError> {
+    retry_policy.validate()
+        .map_err(|e| ApiError::BadRequest(format!("Invalid retry_policy: {}", e)))
+}
+
+/// Drops duplicates, keeping the order
+fn validate_event_types(event_types: Vec<WebhookEventType>) -> Result<Vec<WebhookEventType>, ApiError> {
+    let mut unique = Vec::with_capacity(event_types.len());
+    for event_type in event_types {
+        if !unique.contains(&event_type) {
+            unique.push(event_type);
+        }
+    }
+
+    if unique.is_empty() {
+        return Err(ApiError::BadRequest("At least one event type is required".into()));
+    }
+
+    Ok(unique)
+}
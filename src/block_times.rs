@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Weight of the newest measurement in the average
+const SMOOTHING: f64 = 0.2;
+
+struct Clock {
+    block: u64,
+    seen_at: Instant,
+    seconds_per_block: Option<f64>,
+}
+
+/// Time per block per network, as processed by the backend: measured from how fast core's last
+/// processed block moves, not from the chain itself. It tracks the chain's block time while
+/// processing keeps up, but core works in batches and may fall behind.
+pub struct BlockTimes {
+    networks: Mutex<HashMap<String, Clock>>,
+    /// configured block times, used until a measurement comes in and as its starting point
+    seeds: HashMap<String, f64>,
+}
+
+impl BlockTimes {
+    pub fn new() -> Self {
+        Self {
+            networks: Mutex::new(HashMap::new()),
+            seeds: HashMap::new(),
+        }
+    }
+
+    /// Seeds block times from `NETWORK=seconds` pairs separated by commas (e.g. `Polygon=2,Ethereum=12`)
+    pub fn parse(raw: &str) -> anyhow::Result<Self> {
+        let mut seeds = HashMap::new();
+
+        for pair in raw.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (network, seconds) = pair.split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Bad block time '{}', expected NETWORK=seconds", pair))?;
+            let seconds = seconds.trim().parse::<f64>()
+                .ok()
+                .filter(|seconds| seconds.is_finite() && *seconds > 0.0)
+                .ok_or_else(|| anyhow::anyhow!("Bad block time '{}', seconds must be a positive number", pair))?;
+
+            seeds.insert(network.trim().to_owned(), seconds);
+        }
+
+        Ok(Self {
+            seeds,
+            ..Self::new()
+        })
+    }
+
+    pub fn observe(&self, network: &str, block: u64) {
+        let now = Instant::now();
+        let mut networks = self.networks.lock().unwrap();
+
+        let Some(clock) = networks.get_mut(network) else {
+            networks.insert(network.to_owned(), Clock {
+                block,
+                seen_at: now,
+                seconds_per_block: self.seeds.get(network).copied(),
+            });
+            return;
+        };
+
+        // a chain reset to an older block starts over, without a measurement
+        if block <= clock.block {
+            if block < clock.block {
+                clock.block = block;
+                clock.seen_at = now;
+            }
+            return;
+        }
+
+        let measured = now.duration_since(clock.seen_at).as_secs_f64() / (block - clock.block) as f64;
+
+        clock.seconds_per_block = Some(match clock.seconds_per_block {
+            Some(average) => average + SMOOTHING * (measured - average),
+            None => measured,
+        });
+        clock.block = block;
+        clock.seen_at = now;
+    }
+
+    /// The configured block time until the network's processed block has moved at least once since startup,
+    /// `None` without one
+    pub fn seconds_per_block(&self, network: &str) -> Option<f64> {
+        self.networks.lock().unwrap()
+            .get(network)
+            .and_then(|clock| clock.seconds_per_block)
+            .or_else(|| self.seeds.get(network).copied())
+    }
+}
+
+impl Default for BlockTimes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeds_until_measured() {
+        let block_times = BlockTimes::parse("Polygon=2, Ethereum=12").unwrap();
+        assert_eq!(block_times.seconds_per_block("Polygon"), Some(2.0));
+        assert_eq!(block_times.seconds_per_block("Tron"), None);
+
+        // the first head only starts the clock
+        block_times.observe("Ethereum", 100);
+        assert_eq!(block_times.seconds_per_block("Ethereum"), Some(12.0));
+
+        assert!(BlockTimes::parse("Polygon").is_err());
+        assert!(BlockTimes::parse("Polygon=0").is_err());
+    }
+}
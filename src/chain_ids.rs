@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+
+/// EVM chain id per network. Core's chain configuration doesn't carry one, so it is set on the
+/// backend; payment links name the chain with it and aren't offered for networks without one.
+#[derive(Default)]
+pub struct ChainIds {
+    ids: HashMap<String, u64>,
+}
+
+impl ChainIds {
+    /// Reads `NETWORK=chain_id` pairs separated by commas (e.g. `Polygon=137,Ethereum=1`)
+    pub fn parse(raw: &str) -> anyhow::Result<Self> {
+        let mut ids = HashMap::new();
+
+        for pair in raw.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (network, id) = pair.split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Bad chain id '{}', expected NETWORK=chain_id", pair))?;
+            let id = id.trim().parse::<u64>()
+                .ok()
+                .filter(|id| *id > 0)
+                .ok_or_else(|| anyhow::anyhow!("Bad chain id '{}', chain_id must be a positive integer", pair))?;
+
+            ids.insert(network.trim().to_owned(), id);
+        }
+
+        Ok(Self { ids })
+    }
+
+    pub fn get(&self, network: &str) -> Option<u64> {
+        self.ids.get(network).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_pairs() {
+        let chain_ids = ChainIds::parse("Polygon=137, Ethereum=1").unwrap();
+        assert_eq!(chain_ids.get("Polygon"), Some(137));
+        assert_eq!(chain_ids.get("Tron"), None);
+
+        assert!(ChainIds::parse("Polygon").is_err());
+        assert!(ChainIds::parse("Polygon=0").is_err());
+        assert!(ChainIds::parse("Polygon=abc").is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use crate::store::JsonStore;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use utoipa::ToSchema;
+
+/// Attempts kept per webhook, older ones are dropped
+const MAX_ATTEMPTS_KEPT: usize = 50;
+
+/// Response bodies are cut to this many bytes
+pub const MAX_RESPONSE_BODY: usize = 1024;
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct DeliveryAttempt {
+    /// 1 for the first attempt
+    #[schema(example = 2)]
+    pub attempt: u32,
+    #[schema(example = "https://merchant.website/payment")]
+    pub url: String,
+    #[schema(example = "2026-02-27T21:26:02.537Z")]
+    pub started_at: DateTime<Utc>,
+    /// until the response headers arrived, or the request failed
+    #[schema(example = 184)]
+    pub latency_ms: u64,
+    /// `None` if no response came back
+    #[schema(example = 502)]
+    pub status_code: Option<u16>,
+    /// first 1 KiB of the response body
+    #[schema(example = "Bad Gateway")]
+    pub response_body: Option<String>,
+    /// connection, TLS or timeout error
+    #[schema(example = "error sending request for url (https://merchant.website/payment)")]
+    pub error: Option<String>,
+}
+
+impl DeliveryAttempt {
+    pub fn succeeded(&self) -> bool {
+        self.status_code.is_some_and(|code| (200..300).contains(&code))
+    }
+}
+
+/// Delivery attempts the backend made, per webhook id
+pub struct AttemptStore {
+    attempts: JsonStore<Vec<DeliveryAttempt>>,
+}
+
+impl AttemptStore {
+    pub async fn open(path: PathBuf) -> anyhow::Result<Self> {
+        Ok(Self {
+            attempts: JsonStore::open(path).await?,
+        })
+    }
+
+    pub async fn list(&self, webhook_id: &str) -> Vec<DeliveryAttempt> {
+        self.attempts.get(webhook_id).await.unwrap_or_default()
+    }
+
+    pub async fn record(&self, webhook_id: &str, attempt: DeliveryAttempt) -> anyhow::Result<()> {
+        let mut attempts = self.list(webhook_id).await;
+
+        attempts.push(attempt);
+        if attempts.len() > MAX_ATTEMPTS_KEPT {
+            attempts.drain(..attempts.len() - MAX_ATTEMPTS_KEPT);
+        }
+
+        self.attempts.insert(webhook_id.to_owned(), attempts).await
+    }
+}
+
+/// Cuts `body` to `MAX_RESPONSE_BODY` bytes without splitting a character
+pub fn truncate_body(mut body: String) -> String {
+    if body.len() > MAX_RESPONSE_BODY {
+        let mut end = MAX_RESPONSE_BODY;
+        while !body.is_char_boundary(end) {
+            end -= 1;
+        }
+        body.truncate(end);
+    }
+
+    body
+}
@@ -0,0 +1,295 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use utoipa::ToSchema;
+
+/// Outcomes kept per destination for the failure rate
+const WINDOW: usize = 20;
+
+/// How long other deliveries wait while a half-open circuit is being probed
+const PROBE_WAIT: TimeDelta = TimeDelta::seconds(5);
+
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// webhooks go out as usual
+    Closed,
+    /// too many failures in a row, webhooks wait until the cooldown ends
+    Open,
+    /// cooldown over, one webhook goes out to see if the destination is back
+    HalfOpen,
+}
+
+struct Destination {
+    state: CircuitState,
+    consecutive_failures: u32,
+    /// newest last, true for a success
+    outcomes: VecDeque<bool>,
+    open_until: Option<DateTime<Utc>>,
+    probing: bool,
+    last_error: Option<String>,
+    last_failure_at: Option<DateTime<Utc>>,
+    last_success_at: Option<DateTime<Utc>>,
+}
+
+impl Destination {
+    fn new() -> Self {
+        Self {
+            state: CircuitState::Closed,
+            consecutive_failures: 0,
+            outcomes: VecDeque::with_capacity(WINDOW),
+            open_until: None,
+            probing: false,
+            last_error: None,
+            last_failure_at: None,
+            last_success_at: None,
+        }
+    }
+
+    fn push(&mut self, success: bool) {
+        if self.outcomes.len() == WINDOW {
+            self.outcomes.pop_front();
+        }
+        self.outcomes.push_back(success);
+    }
+
+    fn failure_rate(&self) -> f64 {
+        match self.outcomes.is_empty() {
+            true => 0.0,
+            false => self.outcomes.iter().filter(|success| !**success).count() as f64
+                / self.outcomes.len() as f64,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DestinationHealth {
+    /// scheme, host and port webhooks are sent to
+    #[schema(example = "https://merchant.website:443")]
+    pub destination: String,
+    pub state: CircuitState,
+    /// failed share of the last 20 attempts, 0 to 1
+    #[schema(example = 0.25)]
+    pub failure_rate: f64,
+    #[schema(example = 20)]
+    pub recent_attempts: usize,
+    #[schema(example = 0)]
+    pub consecutive_failures: u32,
+    #[schema(example = "HTTP 503")]
+    pub last_error: Option<String>,
+    #[schema(example = "2026-02-27T21:20:02.537Z")]
+    pub last_failure_at: Option<DateTime<Utc>>,
+    #[schema(example = "2026-02-27T21:25:02.537Z")]
+    pub last_success_at: Option<DateTime<Utc>>,
+    /// set while the circuit is open
+    #[schema(example = "2026-02-27T21:21:02.537Z")]
+    pub open_until: Option<DateTime<Utc>>,
+}
+
+/// Stops hammering a destination that keeps failing: after `threshold` failed attempts in a row
+/// its webhooks are held back for `cooldown`, then a single one is let through to probe it.
+/// Destinations are URL origins, so all invoices of a merchant share one circuit. Kept in memory,
+/// a restart closes every circuit.
+pub struct CircuitBreaker {
+    /// 0 turns the breaker off, failures are still tracked for the health view
+    threshold: u32,
+    cooldown: TimeDelta,
+    destinations: Mutex<HashMap<String, Destination>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: TimeDelta) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            destinations: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// `Err(until)` when webhooks to the URL have to wait. A half-open circuit lets the first
+    /// caller through as its probe
+    pub fn admit(&self, url: &str, now: DateTime<Utc>) -> Result<(), DateTime<Utc>> {
+        let Some(destination) = destination_of(url) else {
+            return Ok(());
+        };
+
+        let mut destinations = self.destinations.lock().unwrap();
+        let Some(entry) = destinations.get_mut(&destination) else {
+            return Ok(());
+        };
+
+        match entry.state {
+            CircuitState::Closed => Ok(()),
+            CircuitState::Open => {
+                let until = entry.open_until.unwrap_or(now);
+                if until > now {
+                    return Err(until);
+                }
+
+                entry.state = CircuitState::HalfOpen;
+                entry.open_until = None;
+                entry.probing = true;
+                Ok(())
+            }
+            CircuitState::HalfOpen if entry.probing => Err(now + PROBE_WAIT),
+            CircuitState::HalfOpen => {
+                entry.probing = true;
+                Ok(())
+            }
+        }
+    }
+
+    /// Outcome of a request that was actually made. Returns true when it opened the circuit
+    pub fn record(&self, url: &str, error: Option<String>, now: DateTime<Utc>) -> bool {
+        let Some(destination) = destination_of(url) else {
+            return false;
+        };
+
+        let mut destinations = self.destinations.lock().unwrap();
+        let entry = destinations.entry(destination).or_insert_with(Destination::new);
+
+        entry.push(error.is_none());
+        entry.probing = false;
+
+        let Some(error) = error else {
+            entry.state = CircuitState::Closed;
+            entry.consecutive_failures = 0;
+            entry.open_until = None;
+            entry.last_success_at = Some(now);
+            return false;
+        };
+
+        entry.consecutive_failures += 1;
+        entry.last_error = Some(error);
+        entry.last_failure_at = Some(now);
+
+        // a failed probe opens it again, no need to wait for the threshold
+        let opens = self.threshold > 0 && match entry.state {
+            CircuitState::Closed => entry.consecutive_failures >= self.threshold,
+            CircuitState::HalfOpen => true,
+            CircuitState::Open => false,
+        };
+
+        if opens {
+            entry.state = CircuitState::Open;
+            entry.open_until = Some(now + self.cooldown);
+        }
+
+        opens
+    }
+
+    /// Every destination that has been sent to since startup, the worst first
+    pub fn health(&self) -> Vec<DestinationHealth> {
+        let destinations = self.destinations.lock().unwrap();
+
+        let mut health: Vec<DestinationHealth> = destinations.iter()
+            .map(|(destination, entry)| DestinationHealth {
+                destination: destination.clone(),
+                state: entry.state,
+                failure_rate: entry.failure_rate(),
+                recent_attempts: entry.outcomes.len(),
+                consecutive_failures: entry.consecutive_failures,
+                last_error: entry.last_error.clone(),
+                last_failure_at: entry.last_failure_at,
+                last_success_at: entry.last_success_at,
+                open_until: entry.open_until,
+            })
+            .collect();
+
+        health.sort_by(|a, b| b.failure_rate.total_cmp(&a.failure_rate)
+            .then_with(|| a.destination.cmp(&b.destination)));
+        health
+    }
+}
+
+/// scheme://host:port of the URL
+pub fn destination_of(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let host = url.host_str()?;
+    let port = url.port_or_known_default()?;
+
+    Some(format!("{}://{}:{}", url.scheme(), host.to_lowercase(), port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://merchant.website/hook";
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + seconds, 0).unwrap()
+    }
+
+    fn fail(breaker: &CircuitBreaker, now: DateTime<Utc>) -> bool {
+        breaker.record(URL, Some("HTTP 503".into()), now)
+    }
+
+    #[test]
+    fn opens_after_threshold() {
+        let breaker = CircuitBreaker::new(3, TimeDelta::seconds(60));
+
+        assert!(!fail(&breaker, at(0)));
+        assert!(!fail(&breaker, at(1)));
+        assert_eq!(breaker.admit(URL, at(2)), Ok(()));
+        assert!(fail(&breaker, at(2)));
+
+        assert_eq!(breaker.admit(URL, at(3)), Err(at(62)));
+        // the whole origin shares the circuit
+        assert_eq!(breaker.admit("https://merchant.website/other", at(3)), Err(at(62)));
+        assert_eq!(breaker.admit("https://other.website/hook", at(3)), Ok(()));
+    }
+
+    #[test]
+    fn success_resets_the_count() {
+        let breaker = CircuitBreaker::new(2, TimeDelta::seconds(60));
+
+        fail(&breaker, at(0));
+        breaker.record(URL, None, at(1));
+        assert!(!fail(&breaker, at(2)));
+        assert_eq!(breaker.admit(URL, at(3)), Ok(()));
+    }
+
+    #[test]
+    fn half_open_probe() {
+        let breaker = CircuitBreaker::new(1, TimeDelta::seconds(60));
+        assert!(fail(&breaker, at(0)));
+
+        // cooldown over: one probe goes through, the rest wait for it
+        assert_eq!(breaker.admit(URL, at(60)), Ok(()));
+        assert_eq!(breaker.admit(URL, at(61)), Err(at(61) + PROBE_WAIT));
+
+        // a failed probe opens it again right away
+        assert!(fail(&breaker, at(62)));
+        assert_eq!(breaker.admit(URL, at(63)), Err(at(122)));
+
+        assert_eq!(breaker.admit(URL, at(122)), Ok(()));
+        assert!(!breaker.record(URL, None, at(123)));
+        assert_eq!(breaker.admit(URL, at(124)), Ok(()));
+        assert_eq!(breaker.admit(URL, at(125)), Ok(()));
+    }
+
+    #[test]
+    fn threshold_zero_only_tracks() {
+        let breaker = CircuitBreaker::new(0, TimeDelta::seconds(60));
+
+        for second in 0..10 {
+            assert!(!fail(&breaker, at(second)));
+        }
+        assert_eq!(breaker.admit(URL, at(10)), Ok(()));
+
+        let health = breaker.health();
+        assert_eq!(health.len(), 1);
+        assert_eq!(health[0].destination, "https://merchant.website:443");
+        assert_eq!(health[0].consecutive_failures, 10);
+        assert_eq!(health[0].failure_rate, 1.0);
+    }
+
+    #[test]
+    fn destinations() {
+        assert_eq!(destination_of("https://Merchant.Website/a?b").as_deref(), Some("https://merchant.website:443"));
+        assert_eq!(destination_of("http://merchant.website:8080/").as_deref(), Some("http://merchant.website:8080"));
+        assert_eq!(destination_of("not a url"), None);
+    }
+}
@@ -0,0 +1,307 @@
+use crate::delivery::attempts::{truncate_body, AttemptStore, DeliveryAttempt, MAX_RESPONSE_BODY};
+use crate::delivery::breaker::{destination_of, CircuitBreaker};
+use crate::delivery::guard::{GuardedResolver, UrlGuard};
+use crate::delivery::{Delivery, DeliveryStore, EndpointStore, RetryPolicy};
+use crate::extras::InvoiceExtrasStore;
+use crate::model::core::WebhookStatusSchema;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use necko3_core::db::DatabaseAdapter;
+use necko3_core::AppState;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, warn};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Webhooks being sent at once, across all destinations
+const MAX_IN_FLIGHT: usize = 64;
+
+/// Webhooks being sent at once to one destination (see `destination_of`)
+const MAX_IN_FLIGHT_PER_DESTINATION: usize = 4;
+
+/// Sends the webhooks the backend delivers itself (see `Delivery`). Each one goes out in its own
+/// task, so a slow destination only holds up its own webhooks
+pub struct Dispatcher {
+    state: Arc<AppState>,
+    deliveries: Arc<DeliveryStore>,
+    attempts: Arc<AttemptStore>,
+    endpoints: Arc<EndpointStore>,
+    extras: Arc<InvoiceExtrasStore>,
+    guard: Arc<UrlGuard>,
+    /// for deliveries to webhook endpoints with their overrides on top
+    policy: RetryPolicy,
+    breaker: Arc<CircuitBreaker>,
+    client: reqwest::Client,
+}
+
+impl Dispatcher {
+    pub fn new(state: Arc<AppState>, deliveries: Arc<DeliveryStore>, attempts: Arc<AttemptStore>,
+               endpoints: Arc<EndpointStore>, extras: Arc<InvoiceExtrasStore>, guard: Arc<UrlGuard>,
+               breaker: Arc<CircuitBreaker>) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .redirect(reqwest::redirect::Policy::none())
+            .dns_resolver(Arc::new(GuardedResolver(guard.clone())))
+            .build()?;
+
+        Ok(Self {
+            state,
+            deliveries,
+            attempts,
+            endpoints,
+            extras,
+            guard,
+            policy: RetryPolicy::default(),
+            breaker,
+            client,
+        })
+    }
+
+    pub fn with_policy(self, policy: RetryPolicy) -> Self {
+        Self { policy, ..self }
+    }
+
+    pub fn spawn(self, interval: Duration) {
+        let dispatcher = Arc::new(self);
+
+        tokio::spawn(async move {
+            info!(interval_sec = interval.as_secs(), "Webhook dispatcher started");
+
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            let in_flight = Arc::new(Semaphore::new(MAX_IN_FLIGHT));
+            let mut destinations: HashMap<String, Arc<Semaphore>> = HashMap::new();
+            let mut tasks = JoinSet::new();
+
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = dispatcher.deliveries.wait_scheduled() => {}
+                }
+
+                while let Some(result) = tasks.try_join_next() {
+                    if let Err(e) = result {
+                        warn!(error = %e, "Webhook delivery task failed");
+                    }
+                }
+                // destinations nothing is being sent to right now
+                destinations.retain(|_, limit| Arc::strong_count(limit) > 1);
+
+                for delivery in dispatcher.deliveries.due(Utc::now()).await {
+                    let delivery = match dispatcher.claim(&delivery.id).await {
+                        Ok(Some(delivery)) => delivery,
+                        Ok(None) => continue,
+                        Err(e) => {
+                            warn!(webhook_id = %delivery.id, error = %e, "Failed to claim webhook");
+                            continue;
+                        }
+                    };
+
+                    let destination = destinations
+                        .entry(destination_of(&delivery.url).unwrap_or_else(|| delivery.url.clone()))
+                        .or_insert_with(|| Arc::new(Semaphore::new(MAX_IN_FLIGHT_PER_DESTINATION)))
+                        .clone();
+                    let in_flight = in_flight.clone();
+                    let dispatcher = dispatcher.clone();
+
+                    tasks.spawn(async move {
+                        // the destination's turn first, so waiting for it takes no slot from the others
+                        let _destination = destination.acquire_owned().await;
+                        let _in_flight = in_flight.acquire_owned().await;
+
+                        if let Err(e) = dispatcher.deliver(delivery).await {
+                            warn!(error = %e, "Webhook delivery failed");
+                        }
+                    });
+                }
+            }
+        });
+    }
+
+    /// Marks a due delivery as being sent, so the next round doesn't pick it up again.
+    /// `None` if it isn't pending anymore, e.g. got cancelled meanwhile
+    async fn claim(&self, id: &str) -> anyhow::Result<Option<Delivery>> {
+        let mut claimed = false;
+
+        let delivery = self.deliveries.update(id, |d| {
+            if matches!(d.status, WebhookStatusSchema::Pending) {
+                d.status = WebhookStatusSchema::Processing;
+                claimed = true;
+            }
+        }).await?;
+
+        Ok(delivery.filter(|_| claimed))
+    }
+
+    async fn deliver(&self, delivery: Delivery) -> anyhow::Result<()> {
+        let (secrets, policy): (Vec<String>, _) = match &delivery.endpoint_id {
+            Some(endpoint_id) => match self.endpoints.get(endpoint_id).await {
+                Some(endpoint) => {
+                    let secrets = endpoint.signing_secrets(Utc::now()).into_iter()
+                        .map(str::to_owned)
+                        .collect();
+                    (secrets, self.policy.with(&endpoint.retry_policy))
+                }
+                None => {
+                    // nobody to sign for (or to send to) anymore
+                    self.deliveries.update(&delivery.id, |d| d.status = WebhookStatusSchema::Cancelled).await?;
+                    debug!(webhook_id = %delivery.id, endpoint_id, "Webhook endpoint is gone, delivery cancelled");
+                    return Ok(());
+                }
+            },
+            None => {
+                let secrets = match self.extras.get(&delivery.invoice_id).await.webhook {
+                    Some(webhook) => webhook.signing_secrets(Utc::now()).into_iter()
+                        .map(str::to_owned)
+                        .collect(),
+                    // invoices from before the backend took over delivery keep theirs in core
+                    None => self.state.db.get_invoice(&delivery.invoice_id).await?
+                        .and_then(|invoice| invoice.webhook_secret)
+                        .into_iter()
+                        .collect(),
+                };
+                (secrets, self.policy.clone())
+            }
+        };
+
+        let body = delivery.body()?;
+        let timestamp = Utc::now().timestamp().to_string();
+
+        let mut request = self.client.post(&delivery.url)
+            .header("content-type", "application/json")
+            .header("x-webhook-id", &delivery.id)
+            .header("x-timestamp", &timestamp);
+
+        // one signature per secret during a rotation, so receivers can verify with either
+        if !secrets.is_empty() {
+            let signatures = secrets.iter()
+                .map(|secret| sign(secret, &timestamp, &body))
+                .collect::<anyhow::Result<Vec<String>>>()?;
+
+            request = request.header("x-signature", signatures.join(","));
+        }
+
+        let mut attempt = DeliveryAttempt {
+            attempt: delivery.attempts + 1,
+            url: delivery.url.clone(),
+            started_at: Utc::now(),
+            latency_ms: 0,
+            status_code: None,
+            response_body: None,
+            error: None,
+        };
+
+        // checked again, the URL may predate the rules or its host may resolve elsewhere now
+        let mut refused = false;
+
+        match self.guard.check(&delivery.url).await {
+            Ok(()) => {
+                // held back without using up an attempt while the destination's circuit is open
+                if let Err(until) = self.breaker.admit(&delivery.url, Utc::now()) {
+                    self.deliveries.update(&delivery.id, |d| {
+                        d.status = WebhookStatusSchema::Pending;
+                        d.next_retry = until;
+                    }).await?;
+
+                    debug!(webhook_id = %delivery.id, url = %delivery.url, %until, "Circuit open, webhook deferred");
+                    return Ok(());
+                }
+
+                // timed from here, the checks above may wait on DNS
+                attempt.started_at = Utc::now();
+                let started = Instant::now();
+
+                match request.body(body).send().await {
+                    Ok(response) => {
+                        attempt.latency_ms = started.elapsed().as_millis() as u64;
+                        attempt.status_code = Some(response.status().as_u16());
+                        attempt.response_body = read_body(response).await;
+                    }
+                    Err(e) => {
+                        attempt.latency_ms = started.elapsed().as_millis() as u64;
+                        attempt.error = Some(e.to_string());
+                    }
+                }
+
+                let error = match (attempt.succeeded(), attempt.status_code) {
+                    (true, _) => None,
+                    (false, Some(code)) => Some(format!("HTTP {}", code)),
+                    (false, None) => attempt.error.clone(),
+                };
+
+                if self.breaker.record(&delivery.url, error, Utc::now()) {
+                    warn!(url = %delivery.url, "Webhook destination keeps failing, circuit opened");
+                }
+            }
+            Err(rejection) => {
+                refused = rejection.is_permanent();
+                attempt.error = Some(format!("Refused: {}", rejection));
+            }
+        }
+
+        let delivered = attempt.succeeded();
+        let permanent = refused || attempt.status_code.is_some_and(|code| policy.is_permanent(code));
+
+        self.deliveries.update(&delivery.id, |d| {
+            d.attempts += 1;
+
+            let next_retry = match delivered || permanent || d.attempts > d.max_retries {
+                true => None,
+                false => policy.next_retry(d.attempts, d.deadline_from(), Utc::now()),
+            };
+
+            d.status = if delivered {
+                WebhookStatusSchema::Sent
+            } else if let Some(next_retry) = next_retry {
+                d.next_retry = next_retry;
+                WebhookStatusSchema::Pending
+            } else {
+                WebhookStatusSchema::Failed
+            };
+        }).await?;
+
+        match delivered {
+            true => debug!(webhook_id = %delivery.id, url = %delivery.url, "Webhook delivered"),
+            false => warn!(webhook_id = %delivery.id, url = %delivery.url, status = attempt.status_code,
+                           error = attempt.error, "Webhook attempt failed"),
+        }
+
+        self.attempts.record(&delivery.id, attempt).await
+    }
+}
+
+/// Start of the response body, reading no further than `MAX_RESPONSE_BODY` bytes of it
+async fn read_body(mut response: reqwest::Response) -> Option<String> {
+    let mut body = Vec::new();
+
+    while body.len() < MAX_RESPONSE_BODY {
+        match response.chunk().await {
+            Ok(Some(chunk)) => body.extend_from_slice(&chunk),
+            Ok(None) => break,
+            Err(_) if body.is_empty() => return None,
+            Err(_) => break,
+        }
+    }
+
+    body.truncate(MAX_RESPONSE_BODY);
+    Some(truncate_body(String::from_utf8_lossy(&body).into_owned()))
+}
+
+/// hex(HMAC-SHA256(secret, timestamp + "." + body))
+fn sign(secret: &str, timestamp: &str, body: &str) -> anyhow::Result<String> {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes())?;
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+
+    Ok(hex::encode(mac.finalize().into_bytes()))
+}
@@ -0,0 +1,96 @@
+use crate::delivery::RetryPolicyOverrides;
+use crate::model::core::WebhookEventType;
+use crate::model::envelope::ApiVersion;
+use crate::model::webhook_endpoint::{PreviousSecret, WebhookEndpoint};
+use crate::store::JsonStore;
+use chrono::TimeDelta;
+use rand::distr::{Alphanumeric, SampleString};
+use std::path::PathBuf;
+
+const SECRET_PREFIX: &str = "nk3w_";
+const SECRET_LENGTH: usize = 48;
+
+pub struct EndpointStore {
+    endpoints: JsonStore<WebhookEndpoint>,
+}
+
+impl EndpointStore {
+    pub async fn open(path: PathBuf) -> anyhow::Result<Self> {
+        Ok(Self {
+            endpoints: JsonStore::open(path).await?,
+        })
+    }
+
+    /// The signing secret is generated here, callers hand it out only on creation and rotation
+    pub async fn create(&self, url: String, event_types: Vec<WebhookEventType>,
+                        description: Option<String>, retry_policy: RetryPolicyOverrides,
+                        api_version: ApiVersion) -> anyhow::Result<WebhookEndpoint> {
+        let now = chrono::Utc::now();
+
+        let endpoint = WebhookEndpoint {
+            id: uuid::Uuid::new_v4().to_string(),
+            url,
+            event_types,
+            description,
+            secret: new_secret(),
+            previous_secret: None,
+            enabled: true,
+            retry_policy,
+            api_version,
+            created_at: now,
+            updated_at: now,
+        };
+
+        self.endpoints.insert(endpoint.id.clone(), endpoint.clone()).await?;
+
+        Ok(endpoint)
+    }
+
+    pub async fn list(&self) -> Vec<WebhookEndpoint> {
+        let mut endpoints = self.endpoints.list().await;
+        endpoints.sort_by_key(|e| e.created_at);
+        endpoints
+    }
+
+    pub async fn get(&self, id: &str) -> Option<WebhookEndpoint> {
+        self.endpoints.get(id).await
+    }
+
+    pub async fn update<F>(&self, id: &str, f: F) -> anyhow::Result<Option<WebhookEndpoint>>
+    where
+        F: FnOnce(&mut WebhookEndpoint),
+    {
+        self.endpoints.update(id, |endpoint| {
+            f(endpoint);
+            endpoint.updated_at = chrono::Utc::now();
+        }).await
+    }
+
+    /// The current secret keeps signing for `grace` next to the new one. A rotation during
+    /// the grace period of another drops the older secret right away
+    pub async fn rotate_secret(&self, id: &str, grace: TimeDelta) -> anyhow::Result<Option<WebhookEndpoint>> {
+        self.update(id, |endpoint| {
+            let previous = std::mem::replace(&mut endpoint.secret, new_secret());
+
+            endpoint.previous_secret = (!grace.is_zero()).then(|| PreviousSecret {
+                secret: previous,
+                expires_at: chrono::Utc::now() + grace,
+            });
+        }).await
+    }
+
+    pub async fn remove(&self, id: &str) -> anyhow::Result<Option<WebhookEndpoint>> {
+        self.endpoints.remove(id).await
+    }
+
+    /// Enabled endpoints that want `event_type`, oldest first
+    pub async fn subscribed(&self, event_type: WebhookEventType) -> Vec<WebhookEndpoint> {
+        self.list().await.into_iter()
+            .filter(|endpoint| endpoint.subscribes_to(event_type))
+            .collect()
+    }
+}
+
+fn new_secret() -> String {
+    format!("{}{}", SECRET_PREFIX, Alphanumeric.sample_string(&mut rand::rng(), SECRET_LENGTH))
+}
@@ -0,0 +1,321 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+
+/// Why a webhook URL was refused
+#[derive(Debug)]
+pub enum UrlRejection {
+    Invalid(String),
+    Scheme(String),
+    /// may work later, DNS can be flaky
+    Unresolvable(String),
+    Blocked { host: String, ip: IpAddr },
+}
+
+impl UrlRejection {
+    pub fn is_permanent(&self) -> bool {
+        !matches!(self, UrlRejection::Unresolvable(_))
+    }
+}
+
+impl fmt::Display for UrlRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UrlRejection::Invalid(reason) => write!(f, "invalid URL: {}", reason),
+            UrlRejection::Scheme(scheme) => write!(f, "scheme '{}' is not allowed, use https", scheme),
+            UrlRejection::Unresolvable(reason) => write!(f, "host does not resolve: {}", reason),
+            UrlRejection::Blocked { host, ip } => {
+                write!(f, "{} resolves to {}, which is a private, loopback or link-local address", host, ip)
+            }
+        }
+    }
+}
+
+impl std::error::Error for UrlRejection {}
+
+/// Host or network (CIDR) the operator lets webhooks reach even though it's not public
+enum Allowed {
+    Host(String),
+    Network(IpAddr, u8),
+}
+
+impl Allowed {
+    fn parse(raw: &str) -> anyhow::Result<Self> {
+        let Some((ip, prefix)) = raw.split_once('/') else {
+            return Ok(match raw.parse::<IpAddr>() {
+                Ok(IpAddr::V4(ip)) => Allowed::Network(IpAddr::V4(ip), 32),
+                Ok(IpAddr::V6(ip)) => Allowed::Network(IpAddr::V6(ip), 128),
+                Err(_) => Allowed::Host(raw.to_lowercase()),
+            });
+        };
+
+        let ip: IpAddr = ip.parse()?;
+        let prefix: u8 = prefix.parse()?;
+        let max = if ip.is_ipv4() { 32 } else { 128 };
+        anyhow::ensure!(prefix <= max, "prefix /{} is too long for {}", prefix, ip);
+
+        Ok(Allowed::Network(ip, prefix))
+    }
+
+    fn matches_host(&self, host: &str) -> bool {
+        matches!(self, Allowed::Host(allowed) if allowed.eq_ignore_ascii_case(host))
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self, ip) {
+            (Allowed::Network(IpAddr::V4(net), prefix), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - *prefix as u32).unwrap_or(0);
+                u32::from(*net) & mask == u32::from(ip) & mask
+            }
+            (Allowed::Network(IpAddr::V6(net), prefix), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - *prefix as u32).unwrap_or(0);
+                u128::from(*net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Keeps webhooks away from the backend's own network (SSRF): URLs must be https (unless
+/// `WEBHOOK_ALLOW_HTTP`) and every address their host resolves to must be public, except
+/// for hosts and networks in `WEBHOOK_ALLOWED_HOSTS`.
+pub struct UrlGuard {
+    allow_http: bool,
+    allowed: Vec<Allowed>,
+}
+
+impl UrlGuard {
+    /// `allowlist` is comma-separated hostnames, IPs and CIDRs
+    pub fn new(allow_http: bool, allowlist: &str) -> anyhow::Result<Self> {
+        let allowed = allowlist.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| Allowed::parse(entry)
+                .map_err(|e| anyhow::anyhow!("Bad WEBHOOK_ALLOWED_HOSTS entry '{}': {}", entry, e)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self { allow_http, allowed })
+    }
+
+    pub async fn check(&self, url: &str) -> Result<(), UrlRejection> {
+        let parsed = reqwest::Url::parse(url)
+            .map_err(|e| UrlRejection::Invalid(e.to_string()))?;
+
+        match parsed.scheme() {
+            "https" => {}
+            "http" if self.allow_http => {}
+            other => return Err(UrlRejection::Scheme(other.to_owned())),
+        }
+
+        let host = parsed.host_str()
+            .ok_or_else(|| UrlRejection::Invalid("no host".into()))?;
+        let port = parsed.port_or_known_default().unwrap_or(443);
+
+        self.resolve(host, port).await.map(|_| ())
+    }
+
+    /// Addresses of `host`, refused if any of them isn't public and allowed neither
+    pub async fn resolve(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, UrlRejection> {
+        // IPv6 hosts come bracketed out of URLs
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+
+        let addrs: Vec<SocketAddr> = match host.parse::<IpAddr>() {
+            Ok(ip) => vec![SocketAddr::new(ip, port)],
+            Err(_) => tokio::net::lookup_host((host, port)).await
+                .map_err(|e| UrlRejection::Unresolvable(format!("{}: {}", host, e)))?
+                .collect(),
+        };
+
+        if addrs.is_empty() {
+            return Err(UrlRejection::Unresolvable(format!("{}: no addresses", host)));
+        }
+
+        if self.allowed.iter().any(|allowed| allowed.matches_host(host)) {
+            return Ok(addrs);
+        }
+
+        // all of them, the client may connect to any
+        for addr in &addrs {
+            let ip = addr.ip();
+            if !is_public(ip) && !self.allowed.iter().any(|allowed| allowed.contains(ip)) {
+                return Err(UrlRejection::Blocked { host: host.to_owned(), ip });
+            }
+        }
+
+        Ok(addrs)
+    }
+}
+
+/// DNS for the webhook client goes through the guard as well, so a host can't pass the check
+/// and resolve to something else by the time the request is made (DNS rebinding)
+pub struct GuardedResolver(pub Arc<UrlGuard>);
+
+impl reqwest::dns::Resolve for GuardedResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let guard = self.0.clone();
+
+        Box::pin(async move {
+            // the client puts in the port of the URL itself
+            let addrs = guard.resolve(name.as_str(), 0).await?;
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+
+    !(ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified()
+        || ip.is_broadcast() || ip.is_multicast() || ip.is_documentation()
+        // "this network", carrier-grade NAT, benchmarking, reserved
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+        || (a == 198 && (18..20).contains(&b))
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    if let Some(v4) = ip.to_ipv4_mapped() {
+        return is_public_v4(v4);
+    }
+
+    let segments = ip.segments();
+
+    // NAT64 (64:ff9b::/96) and 6to4 (2002::/16) carry an IPv4 address
+    let embedded = match segments {
+        [0x64, 0xff9b, 0, 0, 0, 0, high, low] => Some((high, low)),
+        [0x2002, high, low, ..] => Some((high, low)),
+        _ => None,
+    };
+
+    if let Some((high, low)) = embedded {
+        return is_public_v4(Ipv4Addr::from(((high as u32) << 16) | low as u32));
+    }
+
+    !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast()
+        || ip.is_unique_local() || ip.is_unicast_link_local()
+        // documentation
+        || (segments[0] == 0x2001 && segments[1] == 0xdb8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v4(ip: &str) -> Ipv4Addr {
+        ip.parse().unwrap()
+    }
+
+    fn v6(ip: &str) -> Ipv6Addr {
+        ip.parse().unwrap()
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn public_v4() {
+        assert!(is_public_v4(v4("1.1.1.1")));
+        assert!(is_public_v4(v4("93.184.216.34")));
+
+        for blocked in ["10.0.0.1", "172.16.0.1", "192.168.1.1", "127.0.0.1", "169.254.169.254",
+                        "0.0.0.0", "0.1.2.3", "255.255.255.255", "224.0.0.1", "192.0.2.1",
+                        "100.64.0.1", "100.127.255.255", "198.18.0.1", "240.0.0.1"] {
+            assert!(!is_public_v4(v4(blocked)), "{} must not be public", blocked);
+        }
+
+        // right outside carrier-grade NAT and benchmarking
+        assert!(is_public_v4(v4("100.63.255.255")));
+        assert!(is_public_v4(v4("100.128.0.0")));
+        assert!(is_public_v4(v4("198.20.0.0")));
+    }
+
+    #[test]
+    fn public_v6() {
+        assert!(is_public_v6(v6("2606:4700:4700::1111")));
+
+        for blocked in ["::1", "::", "fc00::1", "fd12:3456::1", "fe80::1", "ff02::1", "2001:db8::1"] {
+            assert!(!is_public_v6(v6(blocked)), "{} must not be public", blocked);
+        }
+    }
+
+    #[test]
+    fn embedded_v4_in_v6() {
+        // IPv4-mapped
+        assert!(!is_public_v6(v6("::ffff:127.0.0.1")));
+        assert!(!is_public_v6(v6("::ffff:169.254.169.254")));
+        assert!(is_public_v6(v6("::ffff:1.1.1.1")));
+
+        // NAT64
+        assert!(!is_public_v6(v6("64:ff9b::10.0.0.1")));
+        assert!(!is_public_v6(v6("64:ff9b::a9fe:a9fe")));
+        assert!(is_public_v6(v6("64:ff9b::1.1.1.1")));
+
+        // 6to4
+        assert!(!is_public_v6(v6("2002:7f00:1::")));
+        assert!(!is_public_v6(v6("2002:c0a8:101::1")));
+        assert!(is_public_v6(v6("2002:101:101::1")));
+    }
+
+    #[test]
+    fn allowed_networks() {
+        let zero = Allowed::parse("0.0.0.0/0").unwrap();
+        assert!(zero.contains(ip("10.0.0.1")));
+        assert!(zero.contains(ip("255.255.255.255")));
+        assert!(!zero.contains(ip("::1")));
+
+        let host = Allowed::parse("10.1.2.3/32").unwrap();
+        assert!(host.contains(ip("10.1.2.3")));
+        assert!(!host.contains(ip("10.1.2.4")));
+
+        let bare = Allowed::parse("10.1.2.3").unwrap();
+        assert!(bare.contains(ip("10.1.2.3")));
+        assert!(!bare.contains(ip("10.1.2.2")));
+
+        let net = Allowed::parse("10.1.2.0/24").unwrap();
+        assert!(net.contains(ip("10.1.2.255")));
+        assert!(!net.contains(ip("10.1.3.0")));
+
+        let zero_v6 = Allowed::parse("::/0").unwrap();
+        assert!(zero_v6.contains(ip("fd00::1")));
+        assert!(!zero_v6.contains(ip("10.0.0.1")));
+
+        let host_v6 = Allowed::parse("fd00::1/128").unwrap();
+        assert!(host_v6.contains(ip("fd00::1")));
+        assert!(!host_v6.contains(ip("fd00::2")));
+
+        let net_v6 = Allowed::parse("fd00:1::/32").unwrap();
+        assert!(net_v6.contains(ip("fd00:1:ffff::1")));
+        assert!(!net_v6.contains(ip("fd00:2::1")));
+
+        assert!(Allowed::parse("10.0.0.0/33").is_err());
+        assert!(Allowed::parse("::/129").is_err());
+        assert!(Allowed::parse("hooks.internal").unwrap().matches_host("HOOKS.internal"));
+    }
+
+    #[tokio::test]
+    async fn check() {
+        let guard = UrlGuard::new(false, "10.1.2.0/24, hooks.internal").unwrap();
+
+        assert!(guard.check("https://1.1.1.1/hook").await.is_ok());
+        assert!(guard.check("https://10.1.2.7/hook").await.is_ok());
+        assert!(matches!(guard.check("http://1.1.1.1/hook").await, Err(UrlRejection::Scheme(_))));
+        assert!(matches!(guard.check("not a url").await, Err(UrlRejection::Invalid(_))));
+        assert!(matches!(guard.check("https://169.254.169.254/").await,
+                         Err(UrlRejection::Blocked { .. })));
+        assert!(matches!(guard.check("https://[::ffff:127.0.0.1]/").await,
+                         Err(UrlRejection::Blocked { .. })));
+
+        let http = UrlGuard::new(true, "").unwrap();
+        assert!(http.check("http://1.1.1.1/hook").await.is_ok());
+        assert!(http.check("http://10.1.2.7/hook").await.is_err());
+    }
+}
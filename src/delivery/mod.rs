@@ -0,0 +1,323 @@
+mod attempts;
+mod breaker;
+mod dispatcher;
+mod endpoints;
+mod guard;
+mod policy;
+
+pub use attempts::{AttemptStore, DeliveryAttempt};
+pub use breaker::{CircuitBreaker, CircuitState, DestinationHealth};
+pub use dispatcher::Dispatcher;
+pub use endpoints::EndpointStore;
+pub use guard::UrlGuard;
+pub use policy::{RetryPolicy, RetryPolicyOverrides};
+
+use crate::model::core::{WebhookEventSchema, WebhookFilterSchema, WebhookStatusSchema};
+use crate::model::envelope::{ApiVersion, EventSnapshot};
+use crate::model::webhook_endpoint::WebhookEndpoint;
+use crate::model::InvoiceModel;
+use crate::store::JsonStore;
+use chrono::{DateTime, Utc};
+use necko3_core::model::Webhook;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use tokio::sync::{broadcast, Notify};
+use utoipa::ToSchema;
+
+/// Retries for webhooks whose invoice doesn't set `webhook_max_retries`
+pub const DEFAULT_MAX_RETRIES: u32 = 5;
+
+/// Changes buffered for the watcher, which turns them into webhook_* events
+const CHANGES_BUFFER: usize = 256;
+
+const FINGERPRINT_LENGTH: usize = 12;
+
+/// Tells webhook secrets apart without giving them away: the start of their SHA-256
+pub fn fingerprint(secret: &str) -> String {
+    let digest = hex::encode(Sha256::digest(secret.as_bytes()));
+    format!("sha256:{}", &digest[..FINGERPRINT_LENGTH])
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookSource {
+    /// queued by core for invoices from before the backend took over delivery,
+    /// and sent by the backend after a manual retry
+    Core,
+    /// everything else: invoice webhook URLs, webhook endpoints, replays
+    #[default]
+    Backend,
+}
+
+/// A webhook as the backend sees it. The backend delivers every webhook of the invoices it
+/// creates, to their `webhook_url` or to webhook endpoints, and replays. Core webhooks of older
+/// invoices are mapped onto it, a manual retry takes one over from core under the same id.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Delivery {
+    pub id: String,
+    pub invoice_id: String,
+    /// set when sent to a webhook endpoint, which then signs it with its own secret
+    #[serde(default)]
+    pub endpoint_id: Option<String>,
+    #[serde(default)]
+    pub source: WebhookSource,
+    pub url: String,
+    pub payload: WebhookEventSchema,
+    /// v1 unless the webhook endpoint is pinned to a later version
+    #[serde(default)]
+    pub api_version: ApiVersion,
+    /// what the payload carries besides the event. `None` for webhooks core queued
+    #[serde(default)]
+    pub snapshot: Option<EventSnapshot>,
+    pub status: WebhookStatusSchema,
+    pub attempts: u32,
+    pub max_retries: u32,
+    pub next_retry: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    /// set by a manual retry, the retry deadline counts from then instead of `created_at`
+    #[serde(default)]
+    pub retried_at: Option<DateTime<Utc>>,
+}
+
+impl Delivery {
+    pub fn new(url: String, payload: WebhookEventSchema, snapshot: &EventSnapshot, max_retries: u32) -> Self {
+        let now = Utc::now();
+
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            invoice_id: snapshot.invoice.id.clone(),
+            endpoint_id: None,
+            source: WebhookSource::Backend,
+            url,
+            payload,
+            api_version: ApiVersion::V1,
+            snapshot: Some(snapshot.clone()),
+            status: WebhookStatusSchema::Pending,
+            attempts: 0,
+            max_retries,
+            next_retry: now,
+            created_at: now,
+            retried_at: None,
+        }
+    }
+
+    pub fn for_endpoint(endpoint: &WebhookEndpoint, payload: WebhookEventSchema, snapshot: &EventSnapshot) -> Self {
+        Self {
+            endpoint_id: Some(endpoint.id.clone()),
+            api_version: endpoint.api_version,
+            ..Self::new(endpoint.url.clone(), payload, snapshot,
+                        endpoint.retry_policy.max_retries.unwrap_or(DEFAULT_MAX_RETRIES))
+        }
+    }
+
+    /// Due right away with no attempts made, for a manual retry. The retry deadline starts over
+    pub fn retry(self, now: DateTime<Utc>) -> Self {
+        Self {
+            status: WebhookStatusSchema::Pending,
+            attempts: 0,
+            next_retry: now,
+            retried_at: Some(now),
+            ..self
+        }
+    }
+
+    /// Where the retry deadline counts from
+    pub fn deadline_from(&self) -> DateTime<Utc> {
+        self.retried_at.unwrap_or(self.created_at)
+    }
+
+    /// Request body in the delivery's API version
+    pub fn body(&self) -> serde_json::Result<String> {
+        match &self.snapshot {
+            Some(snapshot) => snapshot.encode(&self.payload, self.api_version),
+            None => serde_json::to_string(&self.payload),
+        }
+    }
+}
+
+/// Where an event of `invoice` goes: its own `webhook_url` (as v1), or else every enabled
+/// endpoint subscribed to the event. The deliveries carry `snapshot`, which may be older than `invoice`
+pub async fn route(endpoints: &EndpointStore, invoice: &InvoiceModel, event: &WebhookEventSchema,
+                   snapshot: &EventSnapshot) -> Vec<Delivery> {
+
+    match &invoice.webhook_url {
+        Some(url) => vec![Delivery::new(url.clone(), event.clone(), snapshot,
+                                        invoice.webhook_max_retries.unwrap_or(DEFAULT_MAX_RETRIES))],
+        None => endpoints.subscribed(event.event_type()).await.iter()
+            .map(|endpoint| Delivery::for_endpoint(endpoint, event.clone(), snapshot))
+            .collect(),
+    }
+}
+
+impl TryFrom<Webhook> for Delivery {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Webhook) -> Result<Self, Self::Error> {
+        // core payloads serialize exactly like WebhookEventSchema
+        let payload = serde_json::from_value(serde_json::to_value(&value.payload)?)?;
+
+        Ok(Self {
+            id: value.id,
+            invoice_id: value.invoice_id,
+            endpoint_id: None,
+            source: WebhookSource::Core,
+            url: value.url,
+            payload,
+            api_version: ApiVersion::V1,
+            snapshot: None,
+            status: value.status.into(),
+            attempts: value.attempts,
+            max_retries: value.max_retries,
+            next_retry: value.next_retry,
+            created_at: value.created_at,
+            retried_at: None,
+        })
+    }
+}
+
+/// A delivery right after it was queued or changed
+#[derive(Clone)]
+pub struct DeliveryChange {
+    /// status and attempts before the change, `None` for a newly queued delivery
+    pub before: Option<(WebhookStatusSchema, u32)>,
+    pub delivery: Delivery,
+}
+
+pub struct DeliveryStore {
+    deliveries: JsonStore<Delivery>,
+    /// wakes the dispatcher for deliveries that are due right away
+    scheduled: Notify,
+    changes: broadcast::Sender<DeliveryChange>,
+}
+
+impl DeliveryStore {
+    pub async fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let deliveries: JsonStore<Delivery> = JsonStore::open(path).await?;
+
+        // attempts cut short by a restart are made again
+        for delivery in deliveries.list().await {
+            if matches!(delivery.status, WebhookStatusSchema::Processing) {
+                deliveries.update(&delivery.id, |d| d.status = WebhookStatusSchema::Pending).await?;
+            }
+        }
+
+        Ok(Self {
+            deliveries,
+            scheduled: Notify::new(),
+            changes: broadcast::Sender::new(CHANGES_BUFFER),
+        })
+    }
+
+    pub async fn get(&self, id: &str) -> Option<Delivery> {
+        self.deliveries.get(id).await
+    }
+
+    /// The backend's own delivery if it has one under the webhook's id, the core webhook otherwise
+    pub async fn resolve(&self, webhook: Webhook) -> anyhow::Result<Delivery> {
+        match self.deliveries.get(&webhook.id).await {
+            Some(delivery) => Ok(delivery),
+            None => webhook.try_into(),
+        }
+    }
+
+    pub async fn schedule(&self, delivery: Delivery) -> anyhow::Result<()> {
+        let before = self.deliveries.get(&delivery.id).await
+            .map(|before| (before.status, before.attempts));
+
+        self.deliveries.insert(delivery.id.clone(), delivery.clone()).await?;
+        self.scheduled.notify_one();
+
+        // nobody listening is fine
+        let _ = self.changes.send(DeliveryChange { before, delivery });
+        Ok(())
+    }
+
+    pub async fn update<F>(&self, id: &str, f: F) -> anyhow::Result<Option<Delivery>>
+    where
+        F: FnOnce(&mut Delivery),
+    {
+        let mut before = None;
+
+        let updated = self.deliveries.update(id, |delivery| {
+            before = Some((delivery.status, delivery.attempts));
+            f(delivery);
+        }).await?;
+
+        if let Some(delivery) = &updated
+            && before != Some((delivery.status, delivery.attempts)) {
+            let _ = self.changes.send(DeliveryChange { before, delivery: delivery.clone() });
+        }
+
+        Ok(updated)
+    }
+
+    /// Every delivery queued or changed from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<DeliveryChange> {
+        self.changes.subscribe()
+    }
+
+    /// Deliveries core doesn't know about, oldest first
+    pub async fn list_own(&self, filter: &WebhookFilterSchema) -> Vec<Delivery> {
+        let mut own: Vec<Delivery> = self.deliveries.list().await.into_iter()
+            .filter(|delivery| delivery.source == WebhookSource::Backend)
+            .filter(|delivery| filter.invoice_id.as_ref().is_none_or(|id| *id == delivery.invoice_id))
+            .filter(|delivery| filter.event_type.is_none_or(|t| t == delivery.payload.event_type()))
+            .filter(|delivery| filter.url.as_ref().is_none_or(|url| *url == delivery.url))
+            .filter(|delivery| filter.status.is_none_or(|status| status == delivery.status))
+            .collect();
+
+        own.sort_by_key(|delivery| delivery.created_at);
+        own
+    }
+
+    /// Pending deliveries whose time has come, oldest first
+    pub async fn due(&self, now: DateTime<Utc>) -> Vec<Delivery> {
+        let mut due: Vec<Delivery> = self.deliveries.list().await.into_iter()
+            .filter(|delivery| matches!(delivery.status, WebhookStatusSchema::Pending)
+                && delivery.next_retry <= now)
+            .collect();
+
+        due.sort_by_key(|delivery| delivery.next_retry);
+        due
+    }
+
+    pub async fn wait_scheduled(&self) {
+        self.scheduled.notified().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    #[test]
+    fn manual_retry_restarts_the_deadline() {
+        let now = Utc::now();
+        let policy = RetryPolicy { jitter: 0.0, ..RetryPolicy::default() };
+
+        let delivery = Delivery {
+            id: "webhook".into(),
+            invoice_id: "invoice".into(),
+            endpoint_id: None,
+            source: WebhookSource::Backend,
+            url: "https://example.com/hook".into(),
+            payload: WebhookEventSchema::InvoiceExpired { invoice_id: "invoice".into() },
+            api_version: ApiVersion::V1,
+            snapshot: None,
+            status: WebhookStatusSchema::Failed,
+            attempts: 6,
+            max_retries: 5,
+            next_retry: now - TimeDelta::days(10),
+            created_at: now - TimeDelta::days(10),
+            retried_at: None,
+        };
+        assert!(policy.next_retry(1, delivery.deadline_from(), now).is_none());
+
+        let delivery = delivery.retry(now);
+        assert_eq!(delivery.attempts, 0);
+        assert_eq!(delivery.created_at, now - TimeDelta::days(10));
+        assert!(policy.next_retry(1, delivery.deadline_from(), now).is_some());
+    }
+}
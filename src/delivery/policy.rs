@@ -0,0 +1,230 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// When the backend retries a webhook. It sends every webhook of invoices created since it took
+/// over delivery, only those of older invoices are still sent (and retried) by core.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    /// seconds before the first retry
+    pub initial_interval: u64,
+    /// each retry waits this many times longer than the one before
+    pub multiplier: f64,
+    /// seconds, the wait never grows past it
+    pub max_interval: u64,
+    /// share of the wait added or taken off at random, so retries don't come in waves
+    pub jitter: f64,
+    /// seconds after the webhook was queued when it's given up on, 0 for never
+    pub deadline: u64,
+    /// responses that fail the webhook right away instead of retrying
+    pub permanent_status_codes: Vec<u16>,
+}
+
+/// Same as the `WEBHOOK_RETRY_*` defaults
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_interval: 10,
+            multiplier: 2.0,
+            max_interval: 3600,
+            jitter: 0.1,
+            deadline: 259200,
+            permanent_status_codes: vec![410],
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        RetryPolicyOverrides {
+            max_retries: None,
+            initial_interval: Some(self.initial_interval),
+            multiplier: Some(self.multiplier),
+            max_interval: Some(self.max_interval),
+            jitter: Some(self.jitter),
+            deadline: Some(self.deadline),
+            permanent_status_codes: Some(self.permanent_status_codes.clone()),
+        }.validate()?;
+
+        if self.max_interval < self.initial_interval {
+            return Err("max_interval must not be below initial_interval".into());
+        }
+
+        Ok(())
+    }
+
+    /// The policy with whatever `overrides` sets
+    pub fn with(&self, overrides: &RetryPolicyOverrides) -> Self {
+        Self {
+            initial_interval: overrides.initial_interval.unwrap_or(self.initial_interval),
+            multiplier: overrides.multiplier.unwrap_or(self.multiplier),
+            max_interval: overrides.max_interval.unwrap_or(self.max_interval),
+            jitter: overrides.jitter.unwrap_or(self.jitter),
+            deadline: overrides.deadline.unwrap_or(self.deadline),
+            permanent_status_codes: overrides.permanent_status_codes.clone()
+                .unwrap_or_else(|| self.permanent_status_codes.clone()),
+        }
+    }
+
+    pub fn is_permanent(&self, status_code: u16) -> bool {
+        self.permanent_status_codes.contains(&status_code)
+    }
+
+    /// When to make the next attempt after `attempts` failed ones, `None` past the deadline
+    pub fn next_retry(&self, attempts: u32, created_at: DateTime<Utc>, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let exponent = attempts.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let wait = (self.initial_interval as f64 * self.multiplier.powi(exponent))
+            .min(self.max_interval as f64);
+
+        let jitter = match self.jitter > 0.0 {
+            true => rand::random_range(-self.jitter..=self.jitter),
+            false => 0.0,
+        };
+
+        let wait = TimeDelta::milliseconds((wait * (1.0 + jitter) * 1000.0) as i64);
+        let at = now + wait;
+
+        if self.deadline > 0 && at > created_at + TimeDelta::seconds(self.deadline as i64) {
+            return None;
+        }
+
+        Some(at)
+    }
+}
+
+/// Per webhook endpoint, fields left out follow the global policy (`WEBHOOK_RETRY_*`)
+#[derive(Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct RetryPolicyOverrides {
+    /// retries after the first attempt
+    #[schema(example = 8)]
+    pub max_retries: Option<u32>,
+    /// seconds before the first retry
+    #[schema(example = 10)]
+    pub initial_interval: Option<u64>,
+    /// each retry waits this many times longer than the one before
+    #[schema(example = 2.0)]
+    pub multiplier: Option<f64>,
+    /// seconds, the wait never grows past it
+    #[schema(example = 3600)]
+    pub max_interval: Option<u64>,
+    /// share of the wait added or taken off at random, 0 to 1
+    #[schema(example = 0.1)]
+    pub jitter: Option<f64>,
+    /// seconds after the webhook was queued when it's given up on, 0 for never
+    #[schema(example = 259200)]
+    pub deadline: Option<u64>,
+    /// responses that fail the webhook right away instead of retrying
+    #[schema(example = json!([410]))]
+    pub permanent_status_codes: Option<Vec<u16>>,
+}
+
+impl RetryPolicyOverrides {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.initial_interval == Some(0) {
+            return Err("initial_interval must be at least 1 second".into());
+        }
+
+        if self.multiplier.is_some_and(|multiplier| !(1.0..=100.0).contains(&multiplier)) {
+            return Err("multiplier must be between 1 and 100".into());
+        }
+
+        if self.jitter.is_some_and(|jitter| !(0.0..=1.0).contains(&jitter)) {
+            return Err("jitter must be between 0 and 1".into());
+        }
+
+        if let (Some(initial), Some(max)) = (self.initial_interval, self.max_interval)
+            && max < initial {
+            return Err("max_interval must not be below initial_interval".into());
+        }
+
+        if self.permanent_status_codes.as_ref()
+            .is_some_and(|codes| codes.iter().any(|code| !(100..600).contains(code) || (200..300).contains(code))) {
+            return Err("permanent_status_codes must be non-2xx HTTP status codes".into());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + seconds, 0).unwrap()
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            jitter: 0.0,
+            ..RetryPolicy::default()
+        }
+    }
+
+    #[test]
+    fn schedule() {
+        let policy = policy();
+
+        let waits: Vec<i64> = (1..=9)
+            .map(|attempts| (policy.next_retry(attempts, at(0), at(0)).unwrap() - at(0)).num_seconds())
+            .collect();
+
+        // doubles from 10s, capped at max_interval
+        assert_eq!(waits, vec![10, 20, 40, 80, 160, 320, 640, 1280, 2560]);
+        assert_eq!((policy.next_retry(10, at(0), at(0)).unwrap() - at(0)).num_seconds(), 3600);
+        assert_eq!((policy.next_retry(u32::MAX, at(0), at(0)).unwrap() - at(0)).num_seconds(), 3600);
+    }
+
+    #[test]
+    fn jitter_stays_in_range() {
+        let policy = RetryPolicy { jitter: 0.5, ..policy() };
+
+        for _ in 0..100 {
+            let wait = (policy.next_retry(1, at(0), at(0)).unwrap() - at(0)).num_milliseconds();
+            assert!((5_000..=15_000).contains(&wait), "{} out of range", wait);
+        }
+    }
+
+    #[test]
+    fn deadline() {
+        let limited = RetryPolicy { deadline: 100, ..policy() };
+
+        assert_eq!(limited.next_retry(1, at(0), at(90)), Some(at(100)));
+        assert_eq!(limited.next_retry(1, at(0), at(91)), None);
+
+        let forever = RetryPolicy { deadline: 0, ..policy() };
+        assert_eq!(forever.next_retry(1, at(0), at(1_000_000)), Some(at(1_000_010)));
+    }
+
+    #[test]
+    fn overrides() {
+        let policy = policy().with(&RetryPolicyOverrides {
+            initial_interval: Some(1),
+            permanent_status_codes: Some(vec![404, 410]),
+            ..Default::default()
+        });
+
+        assert_eq!(policy.initial_interval, 1);
+        assert_eq!(policy.max_interval, 3600);
+        assert!(policy.is_permanent(404));
+        assert!(!policy.is_permanent(500));
+        assert!(!RetryPolicy::default().is_permanent(404));
+    }
+
+    #[test]
+    fn validation() {
+        assert!(RetryPolicy::default().validate().is_ok());
+        assert!(RetryPolicy { max_interval: 5, ..policy() }.validate().is_err());
+
+        for invalid in [
+            RetryPolicyOverrides { initial_interval: Some(0), ..Default::default() },
+            RetryPolicyOverrides { multiplier: Some(0.5), ..Default::default() },
+            RetryPolicyOverrides { jitter: Some(1.5), ..Default::default() },
+            RetryPolicyOverrides { initial_interval: Some(10), max_interval: Some(5), ..Default::default() },
+            RetryPolicyOverrides { permanent_status_codes: Some(vec![200]), ..Default::default() },
+            RetryPolicyOverrides { permanent_status_codes: Some(vec![999]), ..Default::default() },
+        ] {
+            assert!(invalid.validate().is_err());
+        }
+    }
+}
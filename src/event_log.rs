@@ -0,0 +1,261 @@
+use crate::model::core::{PaymentSchema, WebhookEventSchema};
+use crate::model::InvoiceModel;
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{watch, Mutex, RwLock};
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+
+/// How often entries past the retention are dropped while running
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LoggedEvent {
+    pub sequence: u64,
+    pub created_at: DateTime<Utc>,
+    pub event: WebhookEventSchema,
+    /// the invoice right after the event
+    pub invoice: InvoiceModel,
+    /// the payment of tx_* and payment_* events
+    pub payment: Option<PaymentSchema>,
+}
+
+#[derive(Default)]
+struct Entries {
+    list: Vec<LoggedEvent>,
+    /// sequences of each invoice's entries, oldest first
+    by_invoice: HashMap<String, VecDeque<u64>>,
+}
+
+impl Entries {
+    fn push(&mut self, entry: LoggedEvent) {
+        self.by_invoice.entry(entry.event.invoice_id().to_owned())
+            .or_default()
+            .push_back(entry.sequence);
+        self.list.push(entry);
+    }
+
+    /// Drops entries created before `cutoff`, the newest one always stays
+    fn prune(&mut self, cutoff: DateTime<Utc>) -> usize {
+        let expired = self.list.iter()
+            .position(|entry| entry.created_at > cutoff)
+            .unwrap_or(self.list.len().saturating_sub(1));
+
+        for entry in self.list.drain(..expired) {
+            let invoice_id = entry.event.invoice_id();
+            if let Some(sequences) = self.by_invoice.get_mut(invoice_id) {
+                sequences.pop_front();
+                if sequences.is_empty() {
+                    self.by_invoice.remove(invoice_id);
+                }
+            }
+        }
+
+        expired
+    }
+
+    fn get(&self, sequence: u64) -> Option<&LoggedEvent> {
+        self.list.binary_search_by_key(&sequence, |entry| entry.sequence).ok()
+            .map(|index| &self.list[index])
+    }
+}
+
+/// Ordered, append-only log of the events webhooks carry, for consumers that pull instead.
+/// Kept in memory and in a JSON Lines file. Entries past the retention are dropped on startup
+/// and every `PRUNE_INTERVAL` after that.
+pub struct EventLog {
+    path: PathBuf,
+    retention: TimeDelta,
+    entries: RwLock<Entries>,
+    file: Mutex<File>,
+    /// sequence of the newest entry, wakes up long-polling readers
+    last: watch::Sender<u64>,
+}
+
+impl EventLog {
+    pub async fn open(path: PathBuf, retention: TimeDelta) -> anyhow::Result<Self> {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let mut entries = Entries::default();
+
+        match tokio::fs::read_to_string(&path).await {
+            Ok(raw) => {
+                let lines: Vec<&str> = raw.lines().filter(|l| !l.trim().is_empty()).collect();
+
+                for (n, line) in lines.iter().enumerate() {
+                    match serde_json::from_str::<LoggedEvent>(line) {
+                        Ok(entry) => entries.push(entry),
+                        // an append cut short by a crash, compacting below drops it
+                        Err(e) if n + 1 == lines.len() => {
+                            warn!(path = %path.display(), error = %e, "Skipping torn last line of the event log");
+                        }
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        // the newest entry always stays, so sequences carry on across restarts
+        entries.prune(Utc::now() - retention);
+
+        let last = entries.list.last().map(|entry| entry.sequence).unwrap_or_default();
+        let file = compact(&path, &entries.list).await?;
+
+        info!(path = %path.display(), count = entries.list.len(), "Event log loaded");
+
+        Ok(Self {
+            path,
+            retention,
+            entries: RwLock::new(entries),
+            file: Mutex::new(file),
+            last: watch::Sender::new(last),
+        })
+    }
+
+    pub fn spawn_pruning(self: Arc<Self>) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(PRUNE_INTERVAL);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // the first tick completes right away, `open` just pruned
+            ticker.tick().await;
+
+            loop {
+                ticker.tick().await;
+
+                if let Err(e) = self.prune().await {
+                    warn!(error = %e, "Failed to prune event log");
+                }
+            }
+        });
+    }
+
+    /// Drops entries past the retention and rewrites the file without them
+    async fn prune(&self) -> anyhow::Result<()> {
+        let mut entries = self.entries.write().await;
+
+        let pruned = entries.prune(Utc::now() - self.retention);
+        if pruned == 0 {
+            return Ok(());
+        }
+
+        let mut file = self.file.lock().await;
+        *file = compact(&self.path, &entries.list).await?;
+
+        info!(pruned, count = entries.list.len(), "Event log pruned");
+        Ok(())
+    }
+
+    pub async fn append(&self, event: WebhookEventSchema, invoice: InvoiceModel,
+                        payment: Option<PaymentSchema>) -> anyhow::Result<LoggedEvent> {
+        let mut entries = self.entries.write().await;
+
+        let entry = LoggedEvent {
+            sequence: *self.last.borrow() + 1,
+            created_at: Utc::now(),
+            event,
+            invoice,
+            payment,
+        };
+
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+
+        let mut file = self.file.lock().await;
+        file.write_all(&line).await?;
+        file.flush().await?;
+
+        let sequence = entry.sequence;
+        entries.push(entry.clone());
+        self.last.send_replace(sequence);
+
+        Ok(entry)
+    }
+
+    /// Up to `limit` entries after `sequence`, and whether more follow them.
+    /// With nothing to return yet, waits up to `wait` for the next append.
+    pub async fn after(&self, sequence: u64, limit: usize, wait: Duration) -> (Vec<LoggedEvent>, bool) {
+        // subscribed before looking, so an append in between still wakes us up
+        let mut appended = self.last.subscribe();
+
+        let page = self.page(sequence, limit).await;
+        if !page.0.is_empty() || wait.is_zero() {
+            return page;
+        }
+
+        let _ = tokio::time::timeout(wait, appended.wait_for(|last| *last > sequence)).await;
+
+        self.page(sequence, limit).await
+    }
+
+    /// Every entry kept for the invoice, oldest first
+    pub async fn for_invoice(&self, invoice_id: &str) -> Vec<LoggedEvent> {
+        let entries = self.entries.read().await;
+
+        entries.by_invoice.get(invoice_id).into_iter()
+            .flatten()
+            .filter_map(|sequence| entries.get(*sequence).cloned())
+            .collect()
+    }
+
+    /// Whether entries right after `sequence` were pruned already, so reading on from it
+    /// would silently skip them
+    pub async fn is_pruned(&self, sequence: u64) -> bool {
+        let entries = self.entries.read().await;
+
+        entries.list.first().is_some_and(|oldest| oldest.sequence.saturating_sub(1) > sequence)
+    }
+
+    /// Sequence of the newest entry, 0 with nothing logged yet
+    pub fn last_sequence(&self) -> u64 {
+        *self.last.borrow()
+    }
+
+    /// When the newest entry was logged, `None` with nothing logged yet
+    pub async fn newest(&self) -> Option<DateTime<Utc>> {
+        self.entries.read().await.list.last().map(|entry| entry.created_at)
+    }
+
+    async fn page(&self, sequence: u64, limit: usize) -> (Vec<LoggedEvent>, bool) {
+        let entries = self.entries.read().await;
+        let list = &entries.list;
+
+        let start = list.partition_point(|entry| entry.sequence <= sequence);
+        let end = list.len().min(start + limit);
+
+        (list[start..end].to_vec(), end < list.len())
+    }
+}
+
+/// Rewrites the file with just `entries` and opens it for appending
+async fn compact(path: &Path, entries: &[LoggedEvent]) -> anyhow::Result<File> {
+    let tmp = path.with_extension("jsonl.tmp");
+    let mut compacted = String::new();
+    for entry in entries {
+        compacted.push_str(&serde_json::to_string(entry)?);
+        compacted.push('\n');
+    }
+    tokio::fs::write(&tmp, compacted).await?;
+    tokio::fs::rename(&tmp, path).await?;
+
+    Ok(OpenOptions::new().append(true).open(path).await?)
+}
+
+/// Cursors are opaque to consumers, they just hand back what they got
+pub fn encode_cursor(sequence: u64) -> String {
+    hex::encode(sequence.to_be_bytes())
+}
+
+pub fn decode_cursor(cursor: &str) -> Option<u64> {
+    let bytes: [u8; 8] = hex::decode(cursor).ok()?.try_into().ok()?;
+    Some(u64::from_be_bytes(bytes))
+}
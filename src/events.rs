@@ -0,0 +1,310 @@
+use crate::delivery::Delivery;
+use crate::event_log::LoggedEvent;
+use crate::model::core::{PaymentSchema, WebhookEventSchema, WebhookStatusSchema};
+use crate::model::public::PaymentProgress;
+use crate::model::InvoiceModel;
+use chrono::{DateTime, Utc};
+use necko3_core::deps::format_units;
+use necko3_core::model::Webhook;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+/// Events a subscriber may fall behind by before it's cut off. It catches up from the event log
+const CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    PaymentDetected,
+    /// confirmation count of a payment went up
+    PaymentConfirmations,
+    PaymentConfirmed,
+    PaymentCancelled,
+    /// a payment the watcher saw is gone from core, e.g. after a reorg
+    PaymentReverted,
+    /// first time the watcher sees the invoice
+    InvoiceCreated,
+    /// paid amount went up, the invoice is still pending
+    InvoicePartiallyPaid,
+    /// the invoice is about to expire unpaid
+    InvoiceExpiringSoon,
+    InvoicePaid,
+    InvoiceExpired,
+    InvoiceCancelled,
+    /// a webhook was queued for the invoice
+    WebhookQueued,
+    /// a delivery attempt failed, another one is scheduled
+    WebhookRetrying,
+    WebhookSent,
+    WebhookFailed,
+    WebhookCancelled,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::PaymentDetected => "payment_detected",
+            EventKind::PaymentConfirmations => "payment_confirmations",
+            EventKind::PaymentConfirmed => "payment_confirmed",
+            EventKind::PaymentCancelled => "payment_cancelled",
+            EventKind::PaymentReverted => "payment_reverted",
+            EventKind::InvoiceCreated => "invoice_created",
+            EventKind::InvoicePartiallyPaid => "invoice_partially_paid",
+            EventKind::InvoiceExpiringSoon => "invoice_expiring_soon",
+            EventKind::InvoicePaid => "invoice_paid",
+            EventKind::InvoiceExpired => "invoice_expired",
+            EventKind::InvoiceCancelled => "invoice_cancelled",
+            EventKind::WebhookQueued => "webhook_queued",
+            EventKind::WebhookRetrying => "webhook_retrying",
+            EventKind::WebhookSent => "webhook_sent",
+            EventKind::WebhookFailed => "webhook_failed",
+            EventKind::WebhookCancelled => "webhook_cancelled",
+        }
+    }
+
+    /// Webhook events are about merchant delivery and stay off public streams
+    pub fn is_webhook(&self) -> bool {
+        matches!(self, EventKind::WebhookQueued | EventKind::WebhookRetrying | EventKind::WebhookSent
+            | EventKind::WebhookFailed | EventKind::WebhookCancelled)
+    }
+
+    /// What a webhook going from `before` (status, attempts) to `status` after `attempts` means,
+    /// `before` is `None` the first time it's seen
+    pub fn of_webhook(before: Option<(WebhookStatusSchema, u32)>, status: WebhookStatusSchema,
+                      attempts: u32) -> Option<EventKind> {
+        match before {
+            Some((before_status, before_attempts)) if before_status == status => {
+                // back to Pending after a failed attempt
+                (before_attempts < attempts && matches!(status, WebhookStatusSchema::Pending))
+                    .then_some(EventKind::WebhookRetrying)
+            }
+            _ => match status {
+                WebhookStatusSchema::Pending if before.is_none() => Some(EventKind::WebhookQueued),
+                WebhookStatusSchema::Pending => Some(EventKind::WebhookRetrying),
+                WebhookStatusSchema::Processing => None,
+                WebhookStatusSchema::Sent => Some(EventKind::WebhookSent),
+                WebhookStatusSchema::Failed => Some(EventKind::WebhookFailed),
+                WebhookStatusSchema::Cancelled => Some(EventKind::WebhookCancelled),
+            },
+        }
+    }
+}
+
+/// Delivery state of a webhook at the time of the event
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WebhookState {
+    pub id: String,
+    pub url: String,
+    pub event_type: String,
+    pub status: WebhookStatusSchema,
+    pub attempts: u32,
+    pub max_retries: u32,
+    pub next_retry: DateTime<Utc>,
+}
+
+impl From<Webhook> for WebhookState {
+    fn from(value: Webhook) -> Self {
+        // the payload serializes like WebhookEventSchema, {"event_type": ..., "data": ...}
+        let event_type = serde_json::to_value(&value.payload).ok()
+            .and_then(|payload| payload.get("event_type")?.as_str().map(str::to_owned))
+            .unwrap_or_default();
+
+        Self {
+            id: value.id,
+            url: value.url,
+            event_type,
+            status: value.status.into(),
+            attempts: value.attempts,
+            max_retries: value.max_retries,
+            next_retry: value.next_retry,
+        }
+    }
+}
+
+impl From<&Delivery> for WebhookState {
+    fn from(value: &Delivery) -> Self {
+        Self {
+            id: value.id.clone(),
+            url: value.url.clone(),
+            event_type: value.payload.event_type().as_str().to_owned(),
+            status: value.status,
+            attempts: value.attempts,
+            max_retries: value.max_retries,
+            next_retry: value.next_retry,
+        }
+    }
+}
+
+/// Something that happened to an invoice, with the state right after it
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Event {
+    pub id: u64,
+    /// its entry in the event log, set for the events webhooks carry
+    pub sequence: Option<u64>,
+    pub kind: EventKind,
+    pub invoice: InvoiceModel,
+    /// set for payment_* events
+    pub payment: Option<PaymentSchema>,
+    /// set for payment_* events: confirmations, required_confirmations, estimated_seconds_to_finality
+    #[serde(flatten)]
+    pub progress: Option<PaymentProgress>,
+    /// set for webhook_* events
+    pub webhook: Option<WebhookState>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Event {
+    pub fn invoice(kind: EventKind, invoice: InvoiceModel) -> Self {
+        Self {
+            id: 0,
+            sequence: None,
+            kind,
+            invoice,
+            payment: None,
+            progress: None,
+            webhook: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    pub fn payment(kind: EventKind, invoice: InvoiceModel, payment: PaymentSchema, progress: PaymentProgress) -> Self {
+        Self {
+            payment: Some(payment),
+            progress: Some(progress),
+            ..Self::invoice(kind, invoice)
+        }
+    }
+
+    pub fn webhook(kind: EventKind, invoice: InvoiceModel, webhook: WebhookState) -> Self {
+        Self {
+            webhook: Some(webhook),
+            ..Self::invoice(kind, invoice)
+        }
+    }
+
+    /// The event an entry of the event log was made for, with the state it had back then.
+    /// Payment progress isn't logged, only the confirmations of tx_confirmed
+    pub fn logged(entry: &LoggedEvent) -> Self {
+        let kind = match &entry.event {
+            WebhookEventSchema::TxDetected { .. } => EventKind::PaymentDetected,
+            WebhookEventSchema::TxConfirmed { .. } => EventKind::PaymentConfirmed,
+            WebhookEventSchema::PaymentCancelled { .. } => EventKind::PaymentCancelled,
+            WebhookEventSchema::PaymentReverted { .. } => EventKind::PaymentReverted,
+            WebhookEventSchema::InvoiceCreated { .. } => EventKind::InvoiceCreated,
+            WebhookEventSchema::InvoicePartiallyPaid { .. } => EventKind::InvoicePartiallyPaid,
+            WebhookEventSchema::InvoiceExpiringSoon { .. } => EventKind::InvoiceExpiringSoon,
+            WebhookEventSchema::InvoicePaid { .. } => EventKind::InvoicePaid,
+            WebhookEventSchema::InvoiceExpired { .. } => EventKind::InvoiceExpired,
+            WebhookEventSchema::InvoiceCancelled { .. } => EventKind::InvoiceCancelled,
+        };
+
+        let progress = match &entry.event {
+            WebhookEventSchema::TxConfirmed { confirmations, .. } => Some(PaymentProgress {
+                confirmations: *confirmations,
+                ..Default::default()
+            }),
+            _ => entry.payment.as_ref().map(|_| PaymentProgress::default()),
+        };
+
+        Self {
+            sequence: Some(entry.sequence),
+            payment: entry.payment.clone(),
+            progress,
+            created_at: entry.created_at,
+            ..Self::invoice(kind, entry.invoice.clone())
+        }
+    }
+
+    /// The event as webhooks (and the event log) carry it, if it's one of theirs
+    pub fn webhook_event(&self) -> Option<WebhookEventSchema> {
+        let invoice_id = self.invoice.id.clone();
+
+        match (self.kind, &self.payment) {
+            (EventKind::PaymentDetected, Some(payment)) => Some(WebhookEventSchema::TxDetected {
+                invoice_id,
+                tx_hash: payment.tx_hash.clone(),
+                amount: format_units(payment.amount_raw, self.invoice.decimals).ok()?,
+                currency: payment.token.clone(),
+            }),
+            (EventKind::PaymentConfirmed, Some(payment)) => Some(WebhookEventSchema::TxConfirmed {
+                invoice_id,
+                tx_hash: payment.tx_hash.clone(),
+                confirmations: self.progress.map(|progress| progress.confirmations).unwrap_or_default(),
+            }),
+            (EventKind::PaymentCancelled, Some(payment)) => Some(WebhookEventSchema::PaymentCancelled {
+                invoice_id,
+                tx_hash: payment.tx_hash.clone(),
+            }),
+            (EventKind::PaymentReverted, Some(payment)) => Some(WebhookEventSchema::PaymentReverted {
+                invoice_id,
+                tx_hash: payment.tx_hash.clone(),
+            }),
+            (EventKind::InvoiceCreated, _) => Some(WebhookEventSchema::InvoiceCreated {
+                invoice_id,
+                amount: self.invoice.amount.clone(),
+                currency: self.invoice.token.clone(),
+                network: self.invoice.network.clone(),
+                address: self.invoice.address.clone(),
+                expires_at: self.invoice.expires_at,
+            }),
+            (EventKind::InvoicePartiallyPaid, _) => Some(WebhookEventSchema::InvoicePartiallyPaid {
+                invoice_id,
+                paid_amount: self.invoice.paid.clone(),
+                remaining_amount: format_units(
+                    self.invoice.amount_raw.saturating_sub(self.invoice.paid_raw), self.invoice.decimals).ok()?,
+            }),
+            (EventKind::InvoiceExpiringSoon, _) => Some(WebhookEventSchema::InvoiceExpiringSoon {
+                invoice_id,
+                expires_at: self.invoice.expires_at,
+            }),
+            (EventKind::InvoicePaid, _) => Some(WebhookEventSchema::InvoicePaid {
+                invoice_id,
+                paid_amount: self.invoice.paid.clone(),
+            }),
+            (EventKind::InvoiceExpired, _) => Some(WebhookEventSchema::InvoiceExpired { invoice_id }),
+            (EventKind::InvoiceCancelled, _) => Some(WebhookEventSchema::InvoiceCancelled { invoice_id }),
+            _ => None,
+        }
+    }
+}
+
+/// In-process fan-out of invoice, payment and webhook events, fed by the watcher
+pub struct EventBus {
+    sender: broadcast::Sender<Arc<Event>>,
+    next_id: AtomicU64,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+
+        Self {
+            sender,
+            next_id: AtomicU64::new(Utc::now().timestamp_micros() as u64),
+        }
+    }
+
+    /// Assigns the event its id and hands it to every subscriber
+    pub fn publish(&self, mut event: Event) -> Arc<Event> {
+        event.id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        event.created_at = Utc::now();
+        let event = Arc::new(event);
+
+        // no receivers is fine
+        let _ = self.sender.send(event.clone());
+
+        event
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Event>> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,239 @@
+use crate::model::core::InvoiceStatusSchema;
+use crate::model::webhook_endpoint::PreviousSecret;
+use crate::model::FiatQuote;
+use crate::store::JsonStore;
+use crate::tolerance::InvoiceTolerance;
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tokio::sync::Mutex;
+
+/// Merchant-side data attached to an invoice that the core `Invoice` has no room for
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct InvoiceExtras {
+    pub order_id: Option<String>,
+    pub metadata: Option<serde_json::Value>,
+    #[serde(default)]
+    pub fiat: Option<FiatQuote>,
+    #[serde(default)]
+    pub tolerance: Option<InvoiceTolerance>,
+    /// never serialized along with the rest, it holds the secret. The store keeps it separately
+    #[serde(skip)]
+    pub webhook: Option<InvoiceWebhook>,
+}
+
+impl InvoiceExtras {
+    pub fn is_empty(&self) -> bool {
+        self.order_id.is_none() && self.metadata.is_none() && self.fiat.is_none()
+            && self.tolerance.is_none() && self.webhook.is_none()
+    }
+}
+
+/// Where the invoice's webhooks go. The backend delivers them itself, so core gets an invoice
+/// without a `webhook_url` and never sends any
+#[derive(Clone, Serialize, Deserialize)]
+pub struct InvoiceWebhook {
+    pub url: String,
+    pub secret: Option<String>,
+    pub max_retries: Option<u32>,
+    /// the secret before the last rotation, it signs next to the new one until it expires
+    #[serde(default)]
+    pub previous_secret: Option<PreviousSecret>,
+}
+
+impl InvoiceWebhook {
+    /// Secrets to sign with right now, newest first
+    pub fn signing_secrets(&self, now: DateTime<Utc>) -> Vec<&str> {
+        let previous = self.previous_secret.as_ref()
+            .filter(|previous| previous.expires_at > now)
+            .map(|previous| previous.secret.as_str());
+
+        self.secret.as_deref().into_iter().chain(previous).collect()
+    }
+}
+
+/// What a paid invoice settled as, with what the invoice list filters and sorts by, so
+/// `Underpaid`/`Overpaid` invoices can be listed without going through every paid one in core
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settlement {
+    pub status: InvoiceStatusSchema,
+    pub address: String,
+    pub network: String,
+    pub token: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredExtras {
+    #[serde(flatten)]
+    extras: InvoiceExtras,
+    #[serde(default)]
+    webhook: Option<InvoiceWebhook>,
+    /// the invoice was cancelled, its order_id may be taken by a new one
+    #[serde(default)]
+    order_released: bool,
+    #[serde(default)]
+    settlement: Option<Settlement>,
+}
+
+pub struct InvoiceExtrasStore {
+    extras: JsonStore<StoredExtras>,
+    /// order_id -> invoice_id
+    orders: Mutex<HashMap<String, String>>,
+}
+
+impl InvoiceExtrasStore {
+    pub async fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let extras: JsonStore<StoredExtras> = JsonStore::open(path).await?;
+
+        let orders = extras.entries().await
+            .into_iter()
+            .filter(|(_, e)| !e.order_released)
+            .filter_map(|(invoice_id, e)| e.extras.order_id.map(|order_id| (order_id, invoice_id)))
+            .collect();
+
+        Ok(Self {
+            extras,
+            orders: Mutex::new(orders),
+        })
+    }
+
+    /// Claims `order_id` for the invoice. Returns `false` if another invoice already has it
+    pub async fn reserve_order(&self, order_id: &str, invoice_id: &str) -> bool {
+        let mut orders = self.orders.lock().await;
+
+        if orders.contains_key(order_id) {
+            return false;
+        }
+
+        orders.insert(order_id.to_owned(), invoice_id.to_owned());
+        true
+    }
+
+    /// Lets a new invoice take the order_id of a cancelled one. The cancelled invoice keeps
+    /// showing it, but lookups by order_id find the new one
+    pub async fn release_order(&self, invoice_id: &str) -> anyhow::Result<()> {
+        let mut orders = self.orders.lock().await;
+
+        let Some(stored) = self.extras.update(invoice_id, |stored| stored.order_released = true).await? else {
+            return Ok(());
+        };
+
+        if let Some(order_id) = &stored.extras.order_id
+            && orders.get(order_id).is_some_and(|holder| holder == invoice_id) {
+            orders.remove(order_id);
+        }
+
+        Ok(())
+    }
+
+    /// Drops everything kept for an invoice that was never created, its order_id reservation too
+    pub async fn remove(&self, invoice_id: &str) -> anyhow::Result<()> {
+        let mut orders = self.orders.lock().await;
+
+        self.extras.remove(invoice_id).await?;
+        orders.retain(|_, holder| holder != invoice_id);
+
+        Ok(())
+    }
+
+    /// Replaces the secret of the invoice's webhook, the current one keeps signing for `grace`.
+    /// `None` when the backend doesn't deliver the invoice's webhooks
+    pub async fn rotate_secret(&self, invoice_id: &str, secret: String,
+                               grace: TimeDelta) -> anyhow::Result<Option<InvoiceWebhook>> {
+        let _orders = self.orders.lock().await;
+
+        let Some(mut stored) = self.extras.get(invoice_id).await else {
+            return Ok(None);
+        };
+        let Some(webhook) = &mut stored.webhook else {
+            return Ok(None);
+        };
+
+        let previous = webhook.secret.replace(secret);
+        webhook.previous_secret = previous.filter(|_| !grace.is_zero()).map(|previous| PreviousSecret {
+            secret: previous,
+            expires_at: Utc::now() + grace,
+        });
+
+        let rotated = webhook.clone();
+        self.extras.insert(invoice_id.to_owned(), stored).await?;
+
+        Ok(Some(rotated))
+    }
+
+    /// Records what the invoice settled as. Nothing is written when it's unchanged
+    pub async fn settle(&self, invoice_id: &str, settlement: Settlement) -> anyhow::Result<()> {
+        let _orders = self.orders.lock().await;
+
+        let mut stored = match self.extras.get(invoice_id).await {
+            Some(stored) if stored.settlement.as_ref() == Some(&settlement) => return Ok(()),
+            Some(stored) => stored,
+            None => StoredExtras {
+                extras: InvoiceExtras::default(),
+                webhook: None,
+                order_released: false,
+                settlement: None,
+            },
+        };
+        stored.settlement = Some(settlement);
+
+        self.extras.insert(invoice_id.to_owned(), stored).await
+    }
+
+    /// Invoices recorded as settled with `status`
+    pub async fn settled_as(&self, status: InvoiceStatusSchema) -> Vec<(String, Settlement)> {
+        self.extras.entries().await
+            .into_iter()
+            .filter_map(|(invoice_id, stored)| stored.settlement.map(|settlement| (invoice_id, settlement)))
+            .filter(|(_, settlement)| settlement.status == status)
+            .collect()
+    }
+
+    /// Invoices with a backend-delivered webhook that weren't paid, expired and cancelled ones included
+    pub async fn unsettled_webhooks(&self) -> usize {
+        self.extras.list().await
+            .into_iter()
+            .filter(|stored| stored.webhook.is_some() && stored.settlement.is_none())
+            .count()
+    }
+
+    /// The invoice currently holding `order_id`
+    pub async fn find_by_order(&self, order_id: &str) -> Option<String> {
+        self.orders.lock().await.get(order_id).cloned()
+    }
+
+    pub async fn get(&self, invoice_id: &str) -> InvoiceExtras {
+        match self.extras.get(invoice_id).await {
+            Some(stored) => InvoiceExtras {
+                webhook: stored.webhook,
+                ..stored.extras
+            },
+            None => InvoiceExtras::default(),
+        }
+    }
+
+    /// Replaces what's kept for the invoice, empty `extras` clear it (its settlement stays)
+    pub async fn put(&self, invoice_id: &str, extras: InvoiceExtras) -> anyhow::Result<()> {
+        let _orders = self.orders.lock().await;
+        let existing = self.extras.get(invoice_id).await;
+        let settlement = existing.as_ref().and_then(|existing| existing.settlement.clone());
+
+        if extras.is_empty() && settlement.is_none() {
+            if existing.is_some() {
+                self.extras.remove(invoice_id).await?;
+            }
+            return Ok(());
+        }
+
+        let stored = StoredExtras {
+            webhook: extras.webhook.clone(),
+            extras,
+            order_released: existing.is_some_and(|existing| existing.order_released),
+            settlement,
+        };
+
+        self.extras.insert(invoice_id.to_owned(), stored).await
+    }
+}
@@ -1,7 +1,31 @@
 mod api;
+mod block_times;
+mod chain_ids;
+mod delivery;
+mod event_log;
+mod events;
+mod extras;
 mod model;
+mod pending;
+mod rates;
+mod sink;
+mod store;
+mod tolerance;
+mod watcher;
 
 use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+use api::{ApiKeyStore, ApiState, FailedAuthTracker, IdempotencyStore, SignatureVerifier};
+use block_times::BlockTimes;
+use chain_ids::ChainIds;
+use delivery::{AttemptStore, CircuitBreaker, DeliveryStore, Dispatcher, EndpointStore, RetryPolicy, UrlGuard};
+use event_log::EventLog;
+use events::EventBus;
+use extras::InvoiceExtrasStore;
+use pending::PendingInvoiceStore;
+use tolerance::ToleranceStore;
+use watcher::{WatchList, Watcher};
 use necko3_core::db::Database;
 use necko3_core::state::AppState;
 use std::time::Duration;
@@ -74,12 +98,29 @@ async fn main() -> anyhow::Result<()> {
         .parse::<u64>()
         .expect("Failed to parse CONFIRMATOR_INTERVAL as number u64");
 
+    let watcher_interval: u64 = env::var("WATCHER_INTERVAL")
+        .unwrap_or_else(|_| "3".into())
+        .parse::<u64>()
+        .expect("Failed to parse WATCHER_INTERVAL as number u64");
+
+    let invoice_expiring_soon: i64 = env::var("INVOICE_EXPIRING_SOON")
+        .unwrap_or_else(|_| "300".into())
+        .parse::<i64>()
+        .expect("Failed to parse INVOICE_EXPIRING_SOON as number i64");
+
+    let webhook_dispatch_interval: u64 = env::var("WEBHOOK_DISPATCH_INTERVAL")
+        .unwrap_or_else(|_| "5".into())
+        .parse::<u64>()
+        .expect("Failed to parse WEBHOOK_DISPATCH_INTERVAL as number u64");
+
     let cors_origins = env::var("CORS_ALLOWED_ORIGINS")
         .expect("CORS_ALLOWED_ORIGINS must be set");
 
     info!(
         janitor_sec = janitor_interval,
         confirmator_sec = confirmator_interval,
+        watcher_sec = watcher_interval,
+        webhook_dispatch_sec = webhook_dispatch_interval,
         swagger = include_swagger,
         "Configuration loaded"
     );
@@ -96,6 +137,170 @@ async fn main() -> anyhow::Result<()> {
         },
     };
 
+    let data_dir = PathBuf::from(env::var("DATA_DIR")
+        .unwrap_or_else(|_| "data".into()));
+
+    let api_keys = ApiKeyStore::open(&api_key, data_dir.join("api_keys.jsonl")).await?;
+
+    let auth_max_failures = env::var("AUTH_MAX_FAILURES")
+        .unwrap_or_else(|_| "10".into())
+        .parse::<u32>()
+        .expect("Failed to parse AUTH_MAX_FAILURES as number u32");
+
+    let auth_failure_window: u64 = env::var("AUTH_FAILURE_WINDOW")
+        .unwrap_or_else(|_| "60".into())
+        .parse::<u64>()
+        .expect("Failed to parse AUTH_FAILURE_WINDOW as number u64");
+
+    let auth_lockout: u64 = env::var("AUTH_LOCKOUT")
+        .unwrap_or_else(|_| "300".into())
+        .parse::<u64>()
+        .expect("Failed to parse AUTH_LOCKOUT as number u64");
+
+    let trust_forwarded_for = env::var("TRUST_FORWARDED_FOR")
+        .unwrap_or_else(|_| "false".into())
+        .parse::<bool>()
+        .expect("Failed to parse TRUST_FORWARDED_FOR as boolean");
+
+    let auth_failures = Arc::new(FailedAuthTracker::new(
+        auth_max_failures,
+        Duration::from_secs(auth_failure_window),
+        Duration::from_secs(auth_lockout),
+        trust_forwarded_for,
+    ));
+    auth_failures.clone().spawn_sweeping();
+
+    let signature_max_skew: u64 = env::var("SIGNATURE_MAX_SKEW")
+        .unwrap_or_else(|_| "300".into())
+        .parse::<u64>()
+        .expect("Failed to parse SIGNATURE_MAX_SKEW as number u64");
+
+    let idempotency = Arc::new(IdempotencyStore::open(data_dir.join("idempotency_keys.jsonl"),
+                                                      chrono::TimeDelta::hours(24)).await?);
+    idempotency.clone().spawn_pruning();
+
+    let extras = Arc::new(InvoiceExtrasStore::open(data_dir.join("invoice_extras.jsonl")).await?);
+    let pending = PendingInvoiceStore::open(data_dir.join("pending_invoices.jsonl")).await?;
+    let tolerances = ToleranceStore::open(data_dir.join("tolerances.jsonl")).await?;
+
+    let rates = rates::rate_provider_from_env()?;
+    info!(oracle = rates.name(), "Price oracle configured");
+
+    let event_log_retention: i64 = env::var("EVENT_LOG_RETENTION")
+        .unwrap_or_else(|_| "30".into())
+        .parse::<i64>()
+        .expect("Failed to parse EVENT_LOG_RETENTION as number i64");
+
+    let event_log = Arc::new(EventLog::open(data_dir.join("events.jsonl"),
+                                            chrono::TimeDelta::days(event_log_retention)).await?);
+    event_log.clone().spawn_pruning();
+
+    let deliveries = Arc::new(DeliveryStore::open(data_dir.join("webhook_deliveries.jsonl")).await?);
+    let attempts = Arc::new(AttemptStore::open(data_dir.join("webhook_attempts.jsonl")).await?);
+    let endpoints = Arc::new(EndpointStore::open(data_dir.join("webhook_endpoints.jsonl")).await?);
+
+    let webhook_allow_http = env::var("WEBHOOK_ALLOW_HTTP")
+        .unwrap_or_else(|_| "false".into())
+        .parse::<bool>()
+        .expect("Failed to parse WEBHOOK_ALLOW_HTTP as boolean");
+
+    let webhook_allowed_hosts = env::var("WEBHOOK_ALLOWED_HOSTS")
+        .unwrap_or_default();
+
+    let url_guard = Arc::new(UrlGuard::new(webhook_allow_http, &webhook_allowed_hosts)?);
+
+    let retry_policy = RetryPolicy {
+        initial_interval: env::var("WEBHOOK_RETRY_INITIAL_INTERVAL")
+            .unwrap_or_else(|_| "10".into())
+            .parse::<u64>()
+            .expect("Failed to parse WEBHOOK_RETRY_INITIAL_INTERVAL as number u64"),
+        multiplier: env::var("WEBHOOK_RETRY_MULTIPLIER")
+            .unwrap_or_else(|_| "2".into())
+            .parse::<f64>()
+            .expect("Failed to parse WEBHOOK_RETRY_MULTIPLIER as number f64"),
+        max_interval: env::var("WEBHOOK_RETRY_MAX_INTERVAL")
+            .unwrap_or_else(|_| "3600".into())
+            .parse::<u64>()
+            .expect("Failed to parse WEBHOOK_RETRY_MAX_INTERVAL as number u64"),
+        jitter: env::var("WEBHOOK_RETRY_JITTER")
+            .unwrap_or_else(|_| "0.1".into())
+            .parse::<f64>()
+            .expect("Failed to parse WEBHOOK_RETRY_JITTER as number f64"),
+        deadline: env::var("WEBHOOK_RETRY_DEADLINE")
+            .unwrap_or_else(|_| "259200".into())
+            .parse::<u64>()
+            .expect("Failed to parse WEBHOOK_RETRY_DEADLINE as number u64"),
+        permanent_status_codes: env::var("WEBHOOK_PERMANENT_STATUS_CODES")
+            .unwrap_or_else(|_| "410".into())
+            .split(',')
+            .map(str::trim)
+            .filter(|code| !code.is_empty())
+            .map(|code| code.parse::<u16>()
+                .expect("Failed to parse WEBHOOK_PERMANENT_STATUS_CODES as numbers u16"))
+            .collect(),
+    };
+
+    retry_policy.validate()
+        .map_err(|e| anyhow::anyhow!("Invalid webhook retry policy: {}", e))?;
+
+    let breaker_threshold: u32 = env::var("WEBHOOK_BREAKER_THRESHOLD")
+        .unwrap_or_else(|_| "5".into())
+        .parse::<u32>()
+        .expect("Failed to parse WEBHOOK_BREAKER_THRESHOLD as number u32");
+
+    let breaker_cooldown: i64 = env::var("WEBHOOK_BREAKER_COOLDOWN")
+        .unwrap_or_else(|_| "60".into())
+        .parse::<i64>()
+        .expect("Failed to parse WEBHOOK_BREAKER_COOLDOWN as number i64");
+
+    let breaker = Arc::new(CircuitBreaker::new(breaker_threshold,
+                                               chrono::TimeDelta::seconds(breaker_cooldown)));
+
+    Dispatcher::new(state.clone(), deliveries.clone(), attempts.clone(), endpoints.clone(),
+                    extras.clone(), url_guard.clone(), breaker.clone())?
+        .with_policy(retry_policy)
+        .spawn(Duration::from_secs(webhook_dispatch_interval));
+
+    let events = Arc::new(EventBus::new());
+
+    let sinks = sink::sinks_from_env(deliveries.clone(), endpoints.clone(), extras.clone()).await?;
+    info!(sinks = ?sinks.names(), "Event sinks configured");
+    sinks.spawn(event_log.clone(), sink::SinkCursorStore::open(data_dir.join("sink_cursors.jsonl")).await?).await?;
+
+    let block_times = Arc::new(BlockTimes::parse(&env::var("BLOCK_TIMES").unwrap_or_default())?);
+    let chain_ids = Arc::new(ChainIds::parse(&env::var("CHAIN_IDS").unwrap_or_default())?);
+
+    let watch_list = Arc::new(WatchList::default());
+
+    Watcher::new(state.clone(), extras.clone(), events.clone(), event_log.clone(),
+                 block_times.clone(), watch_list.clone())
+        .with_expiring_soon(chrono::TimeDelta::seconds(invoice_expiring_soon))
+        .spawn(Duration::from_secs(watcher_interval));
+
+    watcher::watch_deliveries(state.clone(), extras.clone(), deliveries.clone(), events.clone());
+
+    let state = ApiState {
+        app: state,
+        api_keys: Arc::new(api_keys),
+        auth_failures,
+        signatures: Arc::new(SignatureVerifier::new(Duration::from_secs(signature_max_skew))),
+        idempotency,
+        extras,
+        pending: Arc::new(pending),
+        rates: Arc::from(rates),
+        tolerances: Arc::new(tolerances),
+        events,
+        event_log,
+        deliveries,
+        attempts,
+        endpoints,
+        url_guard,
+        breaker,
+        block_times,
+        chain_ids,
+        watch_list,
+    };
+
     let bind_address = std::env::var("BIND_ADDRESS")
         .unwrap_or_else(|_| "127.0.0.1:3000".into());
 
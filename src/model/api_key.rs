@@ -0,0 +1,115 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub enum ApiScope {
+    #[serde(rename = "invoices:read")]
+    InvoicesRead,
+    #[serde(rename = "invoices:write")]
+    InvoicesWrite,
+    #[serde(rename = "payments:read")]
+    PaymentsRead,
+    #[serde(rename = "payments:write")]
+    PaymentsWrite,
+    #[serde(rename = "webhooks:read")]
+    WebhooksRead,
+    #[serde(rename = "webhooks:write")]
+    WebhooksWrite,
+    #[serde(rename = "chains:read")]
+    ChainsRead,
+    #[serde(rename = "chains:admin")]
+    ChainsAdmin,
+    #[serde(rename = "api-keys:admin")]
+    ApiKeysAdmin,
+}
+
+impl ApiScope {
+    pub const ALL: [ApiScope; 9] = [
+        ApiScope::InvoicesRead,
+        ApiScope::InvoicesWrite,
+        ApiScope::PaymentsRead,
+        ApiScope::PaymentsWrite,
+        ApiScope::WebhooksRead,
+        ApiScope::WebhooksWrite,
+        ApiScope::ChainsRead,
+        ApiScope::ChainsAdmin,
+        ApiScope::ApiKeysAdmin,
+    ];
+
+    /// write/admin scopes also grant read access to the same resource
+    pub fn grants(&self, required: ApiScope) -> bool {
+        *self == required || matches!(
+            (self, required),
+            (ApiScope::InvoicesWrite, ApiScope::InvoicesRead)
+                | (ApiScope::PaymentsWrite, ApiScope::PaymentsRead)
+                | (ApiScope::WebhooksWrite, ApiScope::WebhooksRead)
+                | (ApiScope::ChainsAdmin, ApiScope::ChainsRead)
+        )
+    }
+}
+
+/// Stored representation. The plaintext key is never persisted, only its SHA-256 hash.
+/// The signing secret has to be kept as is, since HMAC verification needs it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub prefix: String,
+    pub hash: String,
+    #[serde(default)]
+    pub signing_secret: Option<String>,
+    pub scopes: Vec<ApiScope>,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct CreateApiKeyReq {
+    #[schema(example = "storefront")]
+    pub name: String,
+    #[schema(example = json!(["invoices:write", "payments:read"]))]
+    pub scopes: Vec<ApiScope>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ApiKeyModel {
+    #[schema(example = "abcef000-abcd-4bcd-8bcd-abcdef000000")]
+    pub id: String,
+    #[schema(example = "storefront")]
+    pub name: String,
+    /// first characters of the key, to tell keys apart
+    #[schema(example = "nk3_Xa1b")]
+    pub prefix: String,
+    #[schema(example = json!(["invoices:write", "payments:read"]))]
+    pub scopes: Vec<ApiScope>,
+    #[schema(example = "2026-02-27T21:20:02.537Z")]
+    pub created_at: DateTime<Utc>,
+    #[schema(example = "2026-02-28T10:00:00.000Z")]
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl From<ApiKey> for ApiKeyModel {
+    fn from(value: ApiKey) -> Self {
+        Self {
+            id: value.id,
+            name: value.name,
+            prefix: value.prefix,
+            scopes: value.scopes,
+            created_at: value.created_at,
+            revoked_at: value.revoked_at,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CreatedApiKeyModel {
+    /// plaintext key, shown only once
+    #[schema(example = "nk3_Xa1b...")]
+    pub key: String,
+    /// HMAC secret for X-Signature requests, shown only once
+    #[schema(example = "nk3s_Yb2c...")]
+    pub signing_secret: String,
+    #[serde(flatten)]
+    pub info: ApiKeyModel,
+}
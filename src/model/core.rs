@@ -8,7 +8,10 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::sync::{Arc, RwLock};
 use chrono::{DateTime, Utc};
-use necko3_core::deps::U256;
+use necko3_core::deps::{parse_units, U256};
+use crate::delivery::WebhookSource;
+use crate::model::FiatQuote;
+use crate::tolerance::InvoiceTolerance;
 use utoipa::r#gen::serde_json::json;
 use utoipa::{IntoParams, ToSchema};
 
@@ -159,8 +162,9 @@ pub struct InvoiceSchema {
     pub decimals: u8,
     #[schema(example = "https://merchant.website/payment")]
     pub webhook_url: Option<String>,
-    #[schema(example = "mega-secret-random-generated-string")]
-    pub webhook_secret: Option<String>,
+    /// the secret itself is write-only, this only tells secrets apart
+    #[schema(example = "sha256:3f1c9a0b7e2d")]
+    pub webhook_secret_fingerprint: Option<String>,
     #[schema(example = 5)]
     pub webhook_max_retries: Option<u32>,
     #[schema(example = "2026-02-27T21:20:02.537Z")]
@@ -168,6 +172,14 @@ pub struct InvoiceSchema {
     #[schema(example = "2026-02-27T21:35:02.537Z")]
     pub expires_at: DateTime<Utc>,
     pub status: InvoiceStatusSchema,
+    #[schema(example = "order-10025")]
+    pub order_id: Option<String>,
+    #[schema(value_type = Option<Object>, example = json!({ "customer_id": 37 }))]
+    pub metadata: Option<serde_json::Value>,
+    /// set when the invoice was priced in fiat
+    pub fiat: Option<FiatQuote>,
+    /// set when the invoice accepts payments off its amount
+    pub tolerance: Option<InvoiceTolerance>,
 }
 
 impl From<InvoiceSchema> for Invoice {
@@ -184,7 +196,7 @@ impl From<InvoiceSchema> for Invoice {
             network: value.network,
             decimals: value.decimals,
             webhook_url: value.webhook_url,
-            webhook_secret: value.webhook_secret,
+            webhook_secret: None,
             webhook_max_retries: value.webhook_max_retries,
             created_at: value.created_at,
             expires_at: value.expires_at,
@@ -193,19 +205,61 @@ impl From<InvoiceSchema> for Invoice {
     }
 }
 
-#[derive(ToSchema, Serialize, Deserialize)]
+#[derive(Copy, Clone, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
 pub enum InvoiceStatusSchema {
     Pending,
     Paid,
+    /// paid short of the amount, but within the underpayment tolerance
+    Underpaid,
+    /// paid more than the amount plus the overpayment tolerance
+    Overpaid,
     Expired,
     Cancelled,
 }
 
+impl InvoiceStatusSchema {
+    /// Core only knows Paid, whether the payment fell short or went over is worked out here
+    pub fn of(invoice: &Invoice, tolerance: Option<&InvoiceTolerance>) -> Self {
+        match invoice.status {
+            InvoiceStatus::Pending => return InvoiceStatusSchema::Pending,
+            InvoiceStatus::Expired => return InvoiceStatusSchema::Expired,
+            InvoiceStatus::Cancelled => return InvoiceStatusSchema::Cancelled,
+            InvoiceStatus::Paid => {}
+        }
+
+        let raw = |amount: &str| parse_units(amount, invoice.decimals)
+            .map(|units| units.get_absolute())
+            .unwrap_or(invoice.amount_raw);
+
+        let (expected, max) = match tolerance {
+            Some(tolerance) => (raw(&tolerance.expected_amount), raw(&tolerance.max_amount)),
+            None => (invoice.amount_raw, invoice.amount_raw),
+        };
+
+        if invoice.paid_raw < expected {
+            InvoiceStatusSchema::Underpaid
+        } else if invoice.paid_raw > max {
+            InvoiceStatusSchema::Overpaid
+        } else {
+            InvoiceStatusSchema::Paid
+        }
+    }
+
+    /// Underpaid and Overpaid are kinds of Paid
+    pub fn matches(&self, status: InvoiceStatusSchema) -> bool {
+        *self == status || (*self == InvoiceStatusSchema::Paid && matches!(
+            status, InvoiceStatusSchema::Underpaid | InvoiceStatusSchema::Overpaid))
+    }
+}
+
+/// Underpaid and Overpaid are Paid to core
 impl From<InvoiceStatusSchema> for InvoiceStatus {
     fn from(value: InvoiceStatusSchema) -> Self {
         match value {
             InvoiceStatusSchema::Pending => InvoiceStatus::Pending,
-            InvoiceStatusSchema::Paid => InvoiceStatus::Paid,
+            InvoiceStatusSchema::Paid
+            | InvoiceStatusSchema::Underpaid
+            | InvoiceStatusSchema::Overpaid => InvoiceStatus::Paid,
             InvoiceStatusSchema::Expired => InvoiceStatus::Expired,
             InvoiceStatusSchema::Cancelled => InvoiceStatus::Cancelled,
         }
@@ -229,6 +283,10 @@ pub struct WebhookSchema {
     pub id: String,
     #[schema(example = "abcef000-abcd-4bcd-8bcd-abcdef000000")]
     pub invoice_id: String,
+    /// webhook endpoint it's sent to, `None` for the invoice's own `webhook_url`
+    #[schema(example = "abcef000-abcd-4bcd-8bcd-abcdef000000")]
+    pub endpoint_id: Option<String>,
+    pub source: WebhookSource,
     #[schema(example = "https://merchant.website/payment")]
     pub url: String,
     pub payload: WebhookEventSchema,
@@ -241,11 +299,22 @@ pub struct WebhookSchema {
     pub next_retry: DateTime<Utc>,
     #[schema(example = "2026-02-27T21:25:02.537Z")]
     pub created_at: DateTime<Utc>,
+    /// current status of the invoice
+    pub invoice_status: Option<InvoiceStatusSchema>,
+    /// order_id of the invoice
+    #[schema(example = "order-10025")]
+    pub order_id: Option<String>,
+    /// metadata of the invoice
+    #[schema(value_type = Option<Object>, example = json!({ "customer_id": 37 }))]
+    pub metadata: Option<serde_json::Value>,
+    /// fiat price of the invoice, if it was priced in fiat
+    pub fiat: Option<FiatQuote>,
 }
 
-#[derive(Serialize, ToSchema)]
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
 #[serde(tag = "event_type", content = "data", rename_all = "snake_case")]
 pub enum WebhookEventSchema {
+    /// a payment to the invoice address showed up on chain
     TxDetected {
         #[schema(example = "abcef000-abcd-4bcd-8bcd-abcdef000000")]
         invoice_id: String,
@@ -274,9 +343,139 @@ pub enum WebhookEventSchema {
         #[schema(example = "abcef000-abcd-4bcd-8bcd-abcdef000000")]
         invoice_id: String,
     },
+    // core only knows the ones above, the backend sends these
+    InvoiceCreated {
+        #[schema(example = "abcef000-abcd-4bcd-8bcd-abcdef000000")]
+        invoice_id: String,
+        #[schema(example = "25.37")]
+        amount: String,
+        #[schema(example = "USDC")]
+        currency: String,
+        #[schema(example = "Polygon")]
+        network: String,
+        #[schema(example = "0xabc123...")]
+        address: String,
+        #[schema(example = "2026-02-27T21:35:02.537Z")]
+        expires_at: DateTime<Utc>,
+    },
+    InvoiceCancelled {
+        #[schema(example = "abcef000-abcd-4bcd-8bcd-abcdef000000")]
+        invoice_id: String,
+    },
+    /// confirmed payments cover part of the amount, the invoice is still pending
+    InvoicePartiallyPaid {
+        #[schema(example = "abcef000-abcd-4bcd-8bcd-abcdef000000")]
+        invoice_id: String,
+        #[schema(example = "10.00")]
+        paid_amount: String,
+        #[schema(example = "15.37")]
+        remaining_amount: String,
+    },
+    /// the invoice expires within `INVOICE_EXPIRING_SOON` seconds
+    InvoiceExpiringSoon {
+        #[schema(example = "abcef000-abcd-4bcd-8bcd-abcdef000000")]
+        invoice_id: String,
+        #[schema(example = "2026-02-27T21:35:02.537Z")]
+        expires_at: DateTime<Utc>,
+    },
+    PaymentCancelled {
+        #[schema(example = "abcef000-abcd-4bcd-8bcd-abcdef000000")]
+        invoice_id: String,
+        #[schema(example = "0xabcdef123456...")]
+        tx_hash: String,
+    },
+    /// the payment is gone from the chain, e.g. after a reorg
+    PaymentReverted {
+        #[schema(example = "abcef000-abcd-4bcd-8bcd-abcdef000000")]
+        invoice_id: String,
+        #[schema(example = "0xabcdef123456...")]
+        tx_hash: String,
+    },
+}
+
+impl WebhookEventSchema {
+    pub fn invoice_id(&self) -> &str {
+        match self {
+            WebhookEventSchema::TxDetected { invoice_id, .. }
+            | WebhookEventSchema::TxConfirmed { invoice_id, .. }
+            | WebhookEventSchema::InvoicePaid { invoice_id, .. }
+            | WebhookEventSchema::InvoiceExpired { invoice_id }
+            | WebhookEventSchema::InvoiceCreated { invoice_id, .. }
+            | WebhookEventSchema::InvoiceCancelled { invoice_id }
+            | WebhookEventSchema::InvoicePartiallyPaid { invoice_id, .. }
+            | WebhookEventSchema::InvoiceExpiringSoon { invoice_id, .. }
+            | WebhookEventSchema::PaymentCancelled { invoice_id, .. }
+            | WebhookEventSchema::PaymentReverted { invoice_id, .. } => invoice_id,
+        }
+    }
+
+    /// Transaction of tx_* and payment_* events
+    pub fn tx_hash(&self) -> Option<&str> {
+        match self {
+            WebhookEventSchema::TxDetected { tx_hash, .. }
+            | WebhookEventSchema::TxConfirmed { tx_hash, .. }
+            | WebhookEventSchema::PaymentCancelled { tx_hash, .. }
+            | WebhookEventSchema::PaymentReverted { tx_hash, .. } => Some(tx_hash),
+            _ => None,
+        }
+    }
+
+    pub fn event_type(&self) -> WebhookEventType {
+        match self {
+            WebhookEventSchema::TxDetected { .. } => WebhookEventType::TxDetected,
+            WebhookEventSchema::TxConfirmed { .. } => WebhookEventType::TxConfirmed,
+            WebhookEventSchema::InvoicePaid { .. } => WebhookEventType::InvoicePaid,
+            WebhookEventSchema::InvoiceExpired { .. } => WebhookEventType::InvoiceExpired,
+            WebhookEventSchema::InvoiceCreated { .. } => WebhookEventType::InvoiceCreated,
+            WebhookEventSchema::InvoiceCancelled { .. } => WebhookEventType::InvoiceCancelled,
+            WebhookEventSchema::InvoicePartiallyPaid { .. } => WebhookEventType::InvoicePartiallyPaid,
+            WebhookEventSchema::InvoiceExpiringSoon { .. } => WebhookEventType::InvoiceExpiringSoon,
+            WebhookEventSchema::PaymentCancelled { .. } => WebhookEventType::PaymentCancelled,
+            WebhookEventSchema::PaymentReverted { .. } => WebhookEventType::PaymentReverted,
+        }
+    }
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize, ToSchema)]
+/// `event_type` of a `WebhookEventSchema`, without the data
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEventType {
+    TxDetected,
+    TxConfirmed,
+    InvoicePaid,
+    InvoiceExpired,
+    InvoiceCreated,
+    InvoiceCancelled,
+    InvoicePartiallyPaid,
+    InvoiceExpiringSoon,
+    PaymentCancelled,
+    PaymentReverted,
+}
+
+impl WebhookEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEventType::TxDetected => "tx_detected",
+            WebhookEventType::TxConfirmed => "tx_confirmed",
+            WebhookEventType::InvoicePaid => "invoice_paid",
+            WebhookEventType::InvoiceExpired => "invoice_expired",
+            WebhookEventType::InvoiceCreated => "invoice_created",
+            WebhookEventType::InvoiceCancelled => "invoice_cancelled",
+            WebhookEventType::InvoicePartiallyPaid => "invoice_partially_paid",
+            WebhookEventType::InvoiceExpiringSoon => "invoice_expiring_soon",
+            WebhookEventType::PaymentCancelled => "payment_cancelled",
+            WebhookEventType::PaymentReverted => "payment_reverted",
+        }
+    }
+
+    /// Core queues these itself for invoices with a `webhook_url`, the backend sends the rest
+    pub fn is_core(&self) -> bool {
+        matches!(self, WebhookEventType::TxDetected | WebhookEventType::TxConfirmed
+            | WebhookEventType::InvoicePaid | WebhookEventType::InvoiceExpired)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 pub enum WebhookStatusSchema {
     Pending,
     Processing,
@@ -297,7 +496,19 @@ impl From<WebhookStatusSchema> for WebhookStatus {
     }
 }
 
-#[derive(ToSchema, Serialize)]
+impl From<WebhookStatus> for WebhookStatusSchema {
+    fn from(value: WebhookStatus) -> Self {
+        match value {
+            WebhookStatus::Pending => WebhookStatusSchema::Pending,
+            WebhookStatus::Processing => WebhookStatusSchema::Processing,
+            WebhookStatus::Sent => WebhookStatusSchema::Sent,
+            WebhookStatus::Failed => WebhookStatusSchema::Failed,
+            WebhookStatus::Cancelled => WebhookStatusSchema::Cancelled,
+        }
+    }
+}
+
+#[derive(Clone, ToSchema, Serialize, Deserialize)]
 pub struct PaymentSchema {
     #[schema(example = "abcef000-abcd-4bcd-8bcd-abcdef000000")]
     pub id: String,
@@ -344,7 +555,26 @@ impl From<PaymentSchema> for Payment {
     }
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize, ToSchema)]
+impl From<Payment> for PaymentSchema {
+    fn from(value: Payment) -> Self {
+        Self {
+            id: value.id,
+            invoice_id: value.invoice_id,
+            from: value.from,
+            to: value.to,
+            network: value.network,
+            token: value.token,
+            tx_hash: value.tx_hash,
+            amount_raw: value.amount_raw,
+            block_number: value.block_number,
+            log_index: value.log_index,
+            status: value.status.into(),
+            created_at: value.created_at,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 pub enum PaymentStatusSchema {
     Confirming,
     Confirmed,
@@ -406,6 +636,7 @@ impl From<PaginationParams> for Pagination {
 #[derive(Serialize, Deserialize, ToSchema, IntoParams)]
 #[into_params(parameter_in = Query)]
 pub struct InvoiceFilterSchema {
+    pub order_id: Option<String>,
     pub address: Option<String>,
     pub network: Option<String>,
     pub token: Option<String>,
@@ -417,6 +648,7 @@ pub struct InvoiceFilterSchema {
     pub pagination: PaginationParams,
 }
 
+/// `order_id` isn't known to core and is resolved by the handler
 impl From<InvoiceFilterSchema> for InvoiceFilter {
     fn from(value: InvoiceFilterSchema) -> Self {
         InvoiceFilter {
@@ -466,7 +698,7 @@ impl From<PaymentFilterSchema> for PaymentFilter {
 #[into_params(parameter_in = Query)]
 pub struct WebhookFilterSchema {
     pub invoice_id: Option<String>,
-    pub event_type: Option<String>,
+    pub event_type: Option<WebhookEventType>,
     pub url: Option<String>,
     pub status: Option<WebhookStatusSchema>,
 
@@ -480,7 +712,7 @@ impl From<WebhookFilterSchema> for WebhookFilter {
     fn from(value: WebhookFilterSchema) -> Self {
         WebhookFilter {
             invoice_id: value.invoice_id,
-            event_type: value.event_type,
+            event_type: value.event_type.map(|event_type| event_type.as_str().to_owned()),
             url: value.url,
             status: value.status.map(|status| status.into()),
             pagination: value.pagination.into(),
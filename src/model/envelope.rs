@@ -0,0 +1,98 @@
+use crate::event_log::{encode_cursor, LoggedEvent};
+use crate::model::core::{InvoiceStatusSchema, PaymentSchema, WebhookEventSchema};
+use crate::model::InvoiceModel;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Shape of the events the backend sends. Pinned per webhook endpoint, so payloads can change
+/// in a new version without breaking receivers of the old one. An invoice's own `webhook_url`
+/// always gets v1
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug, Serialize, Deserialize, ToSchema)]
+pub enum ApiVersion {
+    /// the bare event, `{"event_type": ..., "data": ...}`, the way core sends webhooks, with the
+    /// `invoice_status` core doesn't know (`Underpaid`, `Overpaid`) and the invoice's `order_id`
+    /// and `metadata` next to it when it has them
+    #[default]
+    #[serde(rename = "v1")]
+    V1,
+    /// the event in a `WebhookEnvelope`
+    #[serde(rename = "v2")]
+    V2,
+}
+
+impl ApiVersion {
+    pub const LATEST: ApiVersion = ApiVersion::V2;
+}
+
+/// What the envelope carries besides the event, taken when the event happened
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EventSnapshot {
+    pub event_id: String,
+    pub created_at: DateTime<Utc>,
+    pub invoice: InvoiceModel,
+    pub payment: Option<PaymentSchema>,
+}
+
+impl EventSnapshot {
+    pub fn new(logged: &LoggedEvent, invoice: InvoiceModel, payment: Option<PaymentSchema>) -> Self {
+        Self {
+            event_id: encode_cursor(logged.sequence),
+            created_at: logged.created_at,
+            invoice,
+            payment,
+        }
+    }
+
+    /// The payload in `version`
+    pub fn encode(&self, event: &WebhookEventSchema, version: ApiVersion) -> serde_json::Result<String> {
+        match version {
+            ApiVersion::V1 => serde_json::to_string(&BareEvent {
+                event,
+                invoice_status: self.invoice.status,
+                order_id: self.invoice.extras.order_id.as_ref(),
+                metadata: self.invoice.extras.metadata.as_ref(),
+            }),
+            ApiVersion::V2 => serde_json::to_string(&WebhookEnvelope {
+                event_id: self.event_id.clone(),
+                api_version: ApiVersion::V2,
+                created_at: self.created_at,
+                event: event.clone(),
+                invoice: self.invoice.clone(),
+                payment: self.payment.clone(),
+            }),
+        }
+    }
+}
+
+/// Webhook payload of `api_version` v1
+#[derive(Serialize)]
+struct BareEvent<'a> {
+    #[serde(flatten)]
+    event: &'a WebhookEventSchema,
+    /// status of the invoice right after the event, `data` only has core's
+    invoice_status: InvoiceStatusSchema,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    order_id: Option<&'a String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<&'a serde_json::Value>,
+}
+
+/// Webhook payload of `api_version` v2
+#[derive(Serialize, ToSchema)]
+pub struct WebhookEnvelope {
+    /// unique per event and the same on every delivery of it: the event's cursor in `GET /event`
+    #[schema(example = "00000000000004d2")]
+    pub event_id: String,
+    pub api_version: ApiVersion,
+    /// when the event happened
+    #[schema(example = "2026-02-27T21:25:02.537Z")]
+    pub created_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: WebhookEventSchema,
+    /// the invoice right after the event, replays too
+    #[schema(value_type = crate::model::core::InvoiceSchema)]
+    pub invoice: InvoiceModel,
+    /// the payment of tx_* and payment_* events
+    pub payment: Option<PaymentSchema>,
+}
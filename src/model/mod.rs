@@ -1,30 +1,356 @@
 pub mod core;
 pub mod public;
+pub mod api_key;
+pub mod webhook_endpoint;
+pub mod envelope;
 
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use axum::Json;
-use necko3_core::model::PaginatedVec;
+use crate::delivery::{fingerprint, Delivery};
+use crate::event_log::{encode_cursor, LoggedEvent};
+use crate::events::Event;
+use crate::extras::InvoiceExtras;
+use crate::model::core::{InvoiceStatusSchema, WebhookEventSchema};
+use crate::pending::{PendingInvoice, QuotedOption};
+use chrono::{DateTime, Utc};
+use necko3_core::deps::{parse_units, U256};
+use necko3_core::model::{Invoice, PaginatedVec};
 use serde::{Deserialize, Serialize};
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 
 #[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 pub struct CreateInvoiceReq {
+    /// amount in token units. Either this or `fiat_amount` + `fiat_currency`
     #[schema(example = "25.37")]
-    pub amount: String,
+    pub amount: Option<String>,
+    /// amount in fiat, converted into token units at the current rate
+    #[schema(example = "23.50")]
+    pub fiat_amount: Option<String>,
+    #[schema(example = "EUR")]
+    pub fiat_currency: Option<String>,
+    /// required unless `options` is used
     #[schema(example = "USDC")]
-    pub token: String,
+    pub token: Option<String>,
+    /// required unless `options` is used
     #[schema(example = "Polygon")]
-    pub network: String,
+    pub network: Option<String>,
+    /// lets the payer choose between several (network, token) pairs instead of
+    /// fixing `network` + `token` up front. The address is derived once they pick.
+    pub options: Option<Vec<InvoiceOptionReq>>,
+    /// receives every event of the invoice (as v1). Webhook endpoints get none of them then,
+    /// they only hear about invoices without a URL of their own
     #[schema(example = "https://merchant.website/payment")]
     pub webhook_url: Option<String>,
-    #[schema(example = "mega-secret-random-generated-string")]
+    /// write-only, responses only carry its fingerprint
+    #[schema(example = "mega-secret-random-generated-string", write_only)]
     pub webhook_secret: Option<String>,
     #[schema(example = 5)]
     pub webhook_max_retries: Option<u32>,
-    /// seconds
+    /// seconds, at most 30 days
     #[schema(example = 900)]
     pub expire_after: Option<u64>, 
+    /// merchant's own order reference, unique across invoices
+    #[schema(example = "order-10025")]
+    pub order_id: Option<String>,
+    /// free-form JSON object, echoed back as is
+    #[schema(value_type = Option<Object>, example = json!({ "customer_id": 37 }))]
+    pub metadata: Option<serde_json::Value>,
+    /// overrides the default tolerance of the network/token
+    pub tolerance: Option<PaymentTolerance>,
+}
+
+/// How far a payment may be off the invoice amount. Each side is either token units
+/// (`"0.01"`) or a percentage of the amount (`"1%"`)
+#[derive(Debug, Clone, Default, Deserialize, Serialize, ToSchema)]
+pub struct PaymentTolerance {
+    /// shortfall still accepted as paid, the invoice becomes `Underpaid`
+    #[schema(example = "0.01")]
+    pub underpayment: Option<String>,
+    /// excess still reported as `Paid`. Anything above it makes the invoice `Overpaid`
+    #[schema(example = "5%")]
+    pub overpayment: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct InvoiceOptionReq {
+    #[schema(example = "Polygon")]
+    pub network: String,
+    #[schema(example = "USDC")]
+    pub token: String,
+    /// amount in this token. Falls back to the invoice `amount`, not allowed with fiat pricing
+    #[schema(example = "25.37")]
+    pub amount: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct ExtendInvoiceReq {
+    /// seconds from now, 900 by default and at most 30 days
+    #[schema(example = 900)]
+    pub expire_after: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct RotateInvoiceSecretReq {
+    /// the new webhook_secret
+    #[schema(example = "2f1c9a0b7e2d4c55")]
+    pub secret: String,
+    /// seconds the old secret keeps signing webhooks next to the new one. Default 86400 (a day),
+    /// 0 drops it right away
+    #[schema(example = 86400)]
+    pub grace_period: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct SelectOptionReq {
+    #[schema(example = "Polygon")]
+    pub network: String,
+    #[schema(example = "USDC")]
+    pub token: String,
+}
+
+/// Fiat price of an invoice, with the rate locked at creation
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FiatQuote {
+    #[schema(example = "23.50")]
+    pub amount: String,
+    #[schema(example = "EUR")]
+    pub currency: String,
+    /// price of one token in `currency`
+    #[schema(example = "0.92")]
+    pub rate: String,
+    /// rate provider the rate came from
+    #[schema(example = "static")]
+    pub source: String,
+    #[schema(example = "2026-02-27T21:20:02.537Z")]
+    pub locked_at: DateTime<Utc>,
+}
+
+/// Core invoice plus the merchant data kept by the backend (see `InvoiceSchema` for docs).
+/// Amount and status are the merchant's view: with a tolerance the core invoice is
+/// created for the lowest accepted amount, and core has no Underpaid/Overpaid.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct InvoiceModel {
+    pub id: String,
+    pub address_index: u32,
+    pub address: String,
+    pub amount: String,
+    pub amount_raw: U256,
+    pub paid: String,
+    pub paid_raw: U256,
+    pub token: String,
+    pub network: String,
+    pub decimals: u8,
+    pub webhook_url: Option<String>,
+    pub webhook_secret_fingerprint: Option<String>,
+    pub webhook_max_retries: Option<u32>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub status: InvoiceStatusSchema,
+    #[serde(flatten)]
+    pub extras: InvoiceExtras,
+}
+
+impl InvoiceModel {
+    pub fn new(invoice: Invoice, mut extras: InvoiceExtras) -> Self {
+        let status = InvoiceStatusSchema::of(&invoice, extras.tolerance.as_ref());
+
+        // invoices created before the backend took over delivery still have theirs in core
+        let (webhook_url, webhook_secret, webhook_max_retries) = match extras.webhook.take() {
+            Some(webhook) => (Some(webhook.url), webhook.secret, webhook.max_retries),
+            None => (invoice.webhook_url, invoice.webhook_secret, invoice.webhook_max_retries),
+        };
+
+        let (amount, amount_raw) = match &extras.tolerance {
+            Some(tolerance) => (
+                tolerance.expected_amount.clone(),
+                parse_units(&tolerance.expected_amount, invoice.decimals)
+                    .map(|units| units.get_absolute())
+                    .unwrap_or(invoice.amount_raw),
+            ),
+            None => (invoice.amount, invoice.amount_raw),
+        };
+
+        Self {
+            id: invoice.id,
+            address_index: invoice.address_index,
+            address: invoice.address,
+            amount,
+            amount_raw,
+            paid: invoice.paid,
+            paid_raw: invoice.paid_raw,
+            token: invoice.token,
+            network: invoice.network,
+            decimals: invoice.decimals,
+            webhook_url,
+            webhook_secret_fingerprint: webhook_secret.as_deref().map(fingerprint),
+            webhook_max_retries,
+            created_at: invoice.created_at,
+            expires_at: invoice.expires_at,
+            status,
+            extras,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct InvoiceOptionModel {
+    #[schema(example = "Polygon")]
+    pub network: String,
+    #[schema(example = "USDC")]
+    pub token: String,
+    #[schema(example = "25.37")]
+    pub amount: String,
+    pub fiat: Option<FiatQuote>,
+}
+
+impl From<QuotedOption> for InvoiceOptionModel {
+    fn from(value: QuotedOption) -> Self {
+        Self {
+            network: value.network,
+            token: value.token,
+            amount: value.amount,
+            fiat: value.fiat,
+        }
+    }
+}
+
+/// Invoice whose payer hasn't chosen a network/token yet
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct PendingInvoiceModel {
+    #[schema(example = "abcef000-abcd-4bcd-8bcd-abcdef000000")]
+    pub id: String,
+    /// `AwaitingSelection`, or `Cancelled` once cancelled
+    #[schema(example = "AwaitingSelection")]
+    pub status: String,
+    pub options: Vec<InvoiceOptionModel>,
+    #[schema(example = "https://merchant.website/payment")]
+    pub webhook_url: Option<String>,
+    #[schema(example = "sha256:3f1c9a0b7e2d")]
+    pub webhook_secret_fingerprint: Option<String>,
+    #[schema(example = 5)]
+    pub webhook_max_retries: Option<u32>,
+    #[schema(example = "2026-02-27T21:20:02.537Z")]
+    pub created_at: DateTime<Utc>,
+    #[schema(example = "2026-02-27T21:35:02.537Z")]
+    pub expires_at: DateTime<Utc>,
+    #[schema(example = "order-10025")]
+    pub order_id: Option<String>,
+    #[schema(value_type = Option<Object>, example = json!({ "customer_id": 37 }))]
+    pub metadata: Option<serde_json::Value>,
+}
+
+impl PendingInvoiceModel {
+    pub fn new(invoice: PendingInvoice, extras: InvoiceExtras) -> Self {
+        let status = match invoice.is_cancelled() {
+            true => "Cancelled",
+            false => "AwaitingSelection",
+        };
+
+        Self {
+            id: invoice.id,
+            status: status.into(),
+            options: invoice.options.into_iter().map(Into::into).collect(),
+            webhook_url: invoice.webhook_url,
+            webhook_secret_fingerprint: invoice.webhook_secret.as_deref().map(fingerprint),
+            webhook_max_retries: invoice.webhook_max_retries,
+            created_at: invoice.created_at,
+            expires_at: invoice.expires_at,
+            order_id: extras.order_id,
+            metadata: extras.metadata,
+        }
+    }
+}
+
+/// Either a regular invoice or one still waiting for the payer's choice
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum InvoiceView {
+    Invoice(Box<InvoiceModel>),
+    AwaitingSelection(PendingInvoiceModel),
+}
+
+/// Narrows the admin event feed down. Send it as a text message to replace the current one
+#[derive(Clone, Default, Deserialize, Serialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EventFilterParams {
+    #[schema(example = "Polygon")]
+    pub network: Option<String>,
+    #[schema(example = "USDC")]
+    pub token: Option<String>,
+    /// status of the invoice right after the event. `Paid` also matches `Underpaid` and `Overpaid`
+    pub status: Option<InvoiceStatusSchema>,
+}
+
+impl EventFilterParams {
+    pub fn matches(&self, event: &Event) -> bool {
+        self.network.as_ref().is_none_or(|network| *network == event.invoice.network)
+            && self.token.as_ref().is_none_or(|token| *token == event.invoice.token)
+            && self.status.is_none_or(|status| status.matches(event.invoice.status))
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EventLogParams {
+    /// `next_cursor` of the previous page. Omit to start from the oldest event kept
+    pub after: Option<String>,
+    /// 100 by default, at most 500
+    pub limit: Option<u32>,
+    /// seconds to hold the request open while there's nothing new, at most 60
+    pub wait: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct EventLogEntry {
+    /// position of this event, `after` resumes right behind it
+    #[schema(example = "00000000000004d2")]
+    pub cursor: String,
+    #[schema(example = "2026-02-27T21:25:02.537Z")]
+    pub created_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: WebhookEventSchema,
+}
+
+impl From<LoggedEvent> for EventLogEntry {
+    fn from(value: LoggedEvent) -> Self {
+        Self {
+            cursor: encode_cursor(value.sequence),
+            created_at: value.created_at,
+            event: value.event,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct EventLogPage {
+    pub items: Vec<EventLogEntry>,
+    /// pass as `after` on the next request, even when `items` is empty
+    #[schema(example = "00000000000004d2")]
+    pub next_cursor: String,
+    /// more events are available right away
+    pub has_more: bool,
+}
+
+/// Webhook plus the merchant data of its invoice (see `WebhookSchema` for docs)
+#[derive(Serialize)]
+pub struct WebhookModel {
+    #[serde(flatten)]
+    pub webhook: Delivery,
+    /// current status of the invoice, `None` if it's gone
+    pub invoice_status: Option<InvoiceStatusSchema>,
+    #[serde(flatten)]
+    pub extras: InvoiceExtras,
+}
+
+impl WebhookModel {
+    pub fn new(webhook: Delivery, invoice: Option<&Invoice>, mut extras: InvoiceExtras) -> Self {
+        let invoice_status = invoice
+            .map(|invoice| InvoiceStatusSchema::of(invoice, extras.tolerance.as_ref()));
+        extras.webhook = None;
+
+        Self { webhook, invoice_status, extras }
+    }
 }
 
 #[derive(Serialize, ToSchema)]
@@ -69,6 +395,9 @@ impl<T> ApiResponse<T> {
 pub enum ApiError {
     BadRequest(String),
     NotFound(String),
+    Conflict(String),
+    Gone(String),
+    Unprocessable(String),
     InternalServerError(String),
 }
 
@@ -86,6 +415,9 @@ impl IntoResponse for ApiError {
         let (status, msg) = match self {
             ApiError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
             ApiError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
+            ApiError::Conflict(msg) => (StatusCode::CONFLICT, msg),
+            ApiError::Gone(msg) => (StatusCode::GONE, msg),
+            ApiError::Unprocessable(msg) => (StatusCode::UNPROCESSABLE_ENTITY, msg),
             ApiError::InternalServerError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
         };
 
@@ -1,5 +1,10 @@
-use crate::model::core::{InvoiceStatusSchema, PaymentStatusSchema};
+use crate::extras::InvoiceExtras;
+use crate::model::core::{InvoiceStatusSchema, PaymentSchema, PaymentStatusSchema};
+use crate::model::{InvoiceModel, InvoiceOptionModel};
+use crate::pending::PendingInvoice;
+use crate::watcher::{confirmations, ProcessedHead};
 use chrono::{DateTime, Utc};
+use necko3_core::deps::{format_units, U256};
 use necko3_core::model::{ChainConfig, Invoice, TokenConfig};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
@@ -25,8 +30,15 @@ pub struct PublicInvoiceModel {
     pub status: InvoiceStatusSchema,
 }
 
-impl From<Invoice> for PublicInvoiceModel {
-    fn from(value: Invoice) -> Self {
+impl PublicInvoiceModel {
+    /// Shows the amount the merchant asked for, not the lowest accepted one
+    pub fn new(invoice: Invoice, extras: &InvoiceExtras) -> Self {
+        InvoiceModel::new(invoice, extras.clone()).into()
+    }
+}
+
+impl From<InvoiceModel> for PublicInvoiceModel {
+    fn from(value: InvoiceModel) -> Self {
         Self {
             id: value.id,
             address: value.address,
@@ -36,11 +48,64 @@ impl From<Invoice> for PublicInvoiceModel {
             network: value.network,
             created_at: value.created_at,
             expires_at: value.expires_at,
-            status: value.status.into(),
+            status: value.status,
         }
     }
 }
 
+/// Networks/tokens the payer can choose from
+#[derive(ToSchema, Serialize, Deserialize)]
+pub struct PublicInvoiceOptionsModel {
+    #[schema(example = "abcef000-abcd-4bcd-8bcd-abcdef000000")]
+    pub id: String,
+    pub options: Vec<InvoiceOptionModel>,
+    #[schema(example = "2026-02-27T21:20:02.537Z")]
+    pub created_at: DateTime<Utc>,
+    #[schema(example = "2026-02-27T21:35:02.537Z")]
+    pub expires_at: DateTime<Utc>,
+}
+
+impl From<PendingInvoice> for PublicInvoiceOptionsModel {
+    fn from(value: PendingInvoice) -> Self {
+        Self {
+            id: value.id,
+            options: value.options.into_iter().map(Into::into).collect(),
+            created_at: value.created_at,
+            expires_at: value.expires_at,
+        }
+    }
+}
+
+/// How far a payment is from final, as far as the backend has processed the chain
+#[derive(Copy, Clone, Default, Serialize, Deserialize)]
+pub struct PaymentProgress {
+    pub confirmations: u64,
+    pub required_confirmations: u64,
+    pub estimated_seconds_to_finality: Option<u64>,
+}
+
+impl PaymentProgress {
+    /// `seconds_per_block` is how long the backend takes per block of the network, if known yet
+    pub fn new(payment: &PaymentSchema, head: Option<ProcessedHead>, seconds_per_block: Option<f64>) -> Self {
+        let confirmations = head
+            .map(|head| confirmations(head.block, payment.block_number))
+            .unwrap_or_default();
+        let required_confirmations = head
+            .map(|head| head.required_confirmations)
+            .unwrap_or_default();
+
+        let estimated_seconds_to_finality = match payment.status {
+            PaymentStatusSchema::Confirming => seconds_per_block.map(|seconds| {
+                (required_confirmations.saturating_sub(confirmations) as f64 * seconds).ceil() as u64
+            }),
+            PaymentStatusSchema::Confirmed => Some(0),
+            PaymentStatusSchema::Cancelled => None,
+        };
+
+        Self { confirmations, required_confirmations, estimated_seconds_to_finality }
+    }
+}
+
 #[derive(ToSchema, Serialize, Deserialize)]
 pub struct PublicPaymentModel {
     #[schema(example = "abcef000-abcd-4bcd-8bcd-abcdef000000")]
@@ -60,12 +125,50 @@ pub struct PublicPaymentModel {
     #[schema(example = "25.37")]
     pub amount: String,
     pub status: PaymentStatusSchema,
+    /// block the payment is in
+    #[schema(example = 100500)]
+    pub block_number: u64,
+    /// blocks on top of the payment's one, as far as the backend has processed the chain
+    #[schema(example = 12)]
+    pub confirmations: u64,
+    /// confirmations the chain needs before the payment is final
+    #[schema(example = 40)]
+    pub required_confirmations: u64,
+    /// rough seconds until the payment is final, from how fast the backend has been processing
+    /// the chain's blocks lately. 0 once confirmed, null while cancelled or before that is known
+    #[schema(example = 56)]
+    pub estimated_seconds_to_finality: Option<u64>,
     #[schema(example = "2026-02-27T21:20:02.537Z")]
     pub created_at: DateTime<Utc>,
 }
 
+impl PublicPaymentModel {
+    pub fn new(payment: PaymentSchema, decimals: u8, progress: PaymentProgress) -> anyhow::Result<Self> {
+        Ok(Self {
+            id: payment.id,
+            invoice_id: payment.invoice_id,
+            from: payment.from,
+            to: payment.to,
+            network: payment.network,
+            token: payment.token,
+            tx_hash: payment.tx_hash,
+            amount: format_units(payment.amount_raw, decimals)?,
+            status: payment.status,
+            block_number: payment.block_number,
+            confirmations: progress.confirmations,
+            required_confirmations: progress.required_confirmations,
+            estimated_seconds_to_finality: progress.estimated_seconds_to_finality,
+            created_at: payment.created_at,
+        })
+    }
+}
+
 #[derive(ToSchema, Serialize, Deserialize)]
 pub struct PublicChainModel {
+    #[schema(example = "Polygon")]
+    pub name: String,
+    #[schema(example = "POL")]
+    pub native_symbol: String,
     #[schema(example = 5)]
     pub block_lag: u8,
     #[schema(example = 40)]
@@ -79,6 +182,8 @@ pub struct PublicChainModel {
 impl From<ChainConfig> for PublicChainModel {
     fn from(value: ChainConfig) -> Self {
         Self {
+            name: value.name,
+            native_symbol: value.native_symbol,
             block_lag: value.block_lag,
             required_confirmations: value.required_confirmations,
             decimals: value.decimals,
@@ -105,4 +210,31 @@ impl From<TokenConfig> for PublicTokenModel {
             logo_url: value.logo_url,
         }
     }
-}
\ No newline at end of file
+}
+
+/// Everything a payment page shows, in one document
+#[derive(ToSchema, Serialize, Deserialize)]
+pub struct PublicCheckoutModel {
+    pub invoice: PublicInvoiceModel,
+    /// still to pay, "0" once the invoice is paid in full
+    #[schema(example = "15.37")]
+    pub remaining: String,
+    pub chain: PublicChainModel,
+    /// null when the invoice is paid in the chain's native coin
+    pub token: Option<PublicTokenModel>,
+    /// oldest first
+    pub payments: Vec<PublicPaymentModel>,
+    /// EIP-681 link for wallets and QR codes, for the remaining amount. Null unless the invoice
+    /// is pending and not paid in full, and when the network has no chain id set (`CHAIN_IDS`)
+    #[schema(example = "ethereum:0x3c499c542cef5e3811e1192ce70d8cc03d5c3359@137/transfer?address=0xabc123...&uint256=15370000")]
+    pub payment_uri: Option<String>,
+}
+
+/// EIP-681 payment request on the given chain: a token transfer, or a plain transfer of the native coin
+pub fn payment_uri(address: &str, token: Option<&PublicTokenModel>, amount_raw: U256, chain_id: u64) -> String {
+    match token {
+        Some(token) => format!("ethereum:{}@{}/transfer?address={}&uint256={}",
+                               token.contract_address, chain_id, address, amount_raw),
+        None => format!("ethereum:{}@{}?value={}", address, chain_id, amount_raw),
+    }
+}
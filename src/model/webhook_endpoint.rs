@@ -0,0 +1,161 @@
+use crate::delivery::{fingerprint, RetryPolicyOverrides};
+use crate::model::core::WebhookEventType;
+use crate::model::envelope::ApiVersion;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// A merchant-wide webhook URL. Invoices created without a `webhook_url` are delivered
+/// to every enabled endpoint subscribed to the event.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WebhookEndpoint {
+    pub id: String,
+    pub url: String,
+    pub event_types: Vec<WebhookEventType>,
+    pub description: Option<String>,
+    pub secret: String,
+    /// the secret before the last rotation, webhooks are signed with both until it expires
+    #[serde(default)]
+    pub previous_secret: Option<PreviousSecret>,
+    pub enabled: bool,
+    /// on top of the global retry policy
+    #[serde(default)]
+    pub retry_policy: RetryPolicyOverrides,
+    /// payload version, endpoints from before versioning stay on v1
+    #[serde(default)]
+    pub api_version: ApiVersion,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PreviousSecret {
+    pub secret: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl WebhookEndpoint {
+    pub fn subscribes_to(&self, event_type: WebhookEventType) -> bool {
+        self.enabled && self.event_types.contains(&event_type)
+    }
+
+    /// The previous secret while its grace period lasts
+    pub fn previous_secret(&self, now: DateTime<Utc>) -> Option<&PreviousSecret> {
+        self.previous_secret.as_ref().filter(|previous| previous.expires_at > now)
+    }
+
+    /// Secrets to sign with right now, newest first
+    pub fn signing_secrets(&self, now: DateTime<Utc>) -> Vec<&str> {
+        let mut secrets = vec![self.secret.as_str()];
+        if let Some(previous) = self.previous_secret(now) {
+            secrets.push(&previous.secret);
+        }
+        secrets
+    }
+}
+
+#[derive(Clone, Deserialize, ToSchema)]
+pub struct CreateWebhookEndpointReq {
+    #[schema(example = "https://merchant.website/payment")]
+    pub url: String,
+    #[schema(example = json!(["tx_detected", "invoice_paid"]))]
+    pub event_types: Vec<WebhookEventType>,
+    #[schema(example = "order service")]
+    pub description: Option<String>,
+    /// retries of webhooks sent to the endpoint, fields left out follow the global policy
+    pub retry_policy: Option<RetryPolicyOverrides>,
+    /// payload version the endpoint receives, the latest (v2) when left out
+    #[schema(example = "v2")]
+    pub api_version: Option<ApiVersion>,
+}
+
+/// Fields left out stay as they are
+#[derive(Clone, Deserialize, ToSchema)]
+pub struct UpdateWebhookEndpointReq {
+    #[schema(example = "https://merchant.website/payment")]
+    pub url: Option<String>,
+    #[schema(example = json!(["tx_detected", "invoice_paid"]))]
+    pub event_types: Option<Vec<WebhookEventType>>,
+    #[schema(example = "order service")]
+    pub description: Option<String>,
+    /// disabled endpoints get no new webhooks, queued ones are still sent
+    #[schema(example = true)]
+    pub enabled: Option<bool>,
+    /// replaces the endpoint's overrides as a whole, `{}` goes back to the global policy.
+    /// Webhooks already queued keep their number of retries
+    pub retry_policy: Option<RetryPolicyOverrides>,
+    /// applies to webhooks queued from now on
+    #[schema(example = "v2")]
+    pub api_version: Option<ApiVersion>,
+}
+
+#[derive(Clone, Default, Deserialize, ToSchema)]
+pub struct RotateWebhookSecretReq {
+    /// seconds the old secret keeps signing webhooks next to the new one. Default 86400 (a day),
+    /// 0 drops it right away
+    #[schema(example = 86400)]
+    pub grace_period: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WebhookEndpointModel {
+    #[schema(example = "abcef000-abcd-4bcd-8bcd-abcdef000000")]
+    pub id: String,
+    #[schema(example = "https://merchant.website/payment")]
+    pub url: String,
+    #[schema(example = json!(["tx_detected", "invoice_paid"]))]
+    pub event_types: Vec<WebhookEventType>,
+    #[schema(example = "order service")]
+    pub description: Option<String>,
+    /// the secret itself is only shown on creation and rotation
+    #[schema(example = "sha256:3f1c9a0b7e2d")]
+    pub secret_fingerprint: String,
+    /// set during the grace period after a rotation
+    #[schema(example = "sha256:8d04e6b21fa5")]
+    pub previous_secret_fingerprint: Option<String>,
+    /// when the previous secret stops signing webhooks
+    #[schema(example = "2026-02-29T10:00:00.000Z")]
+    pub previous_secret_expires_at: Option<DateTime<Utc>>,
+    #[schema(example = true)]
+    pub enabled: bool,
+    /// overrides of the global retry policy
+    pub retry_policy: RetryPolicyOverrides,
+    #[schema(example = "v2")]
+    pub api_version: ApiVersion,
+    #[schema(example = "2026-02-27T21:20:02.537Z")]
+    pub created_at: DateTime<Utc>,
+    #[schema(example = "2026-02-28T10:00:00.000Z")]
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<WebhookEndpoint> for WebhookEndpointModel {
+    fn from(value: WebhookEndpoint) -> Self {
+        let previous = value.previous_secret(Utc::now())
+            .map(|previous| (fingerprint(&previous.secret), previous.expires_at));
+
+        Self {
+            secret_fingerprint: fingerprint(&value.secret),
+            previous_secret_fingerprint: previous.as_ref().map(|(fingerprint, _)| fingerprint.clone()),
+            previous_secret_expires_at: previous.map(|(_, expires_at)| expires_at),
+            id: value.id,
+            url: value.url,
+            event_types: value.event_types,
+            description: value.description,
+            enabled: value.enabled,
+            retry_policy: value.retry_policy,
+            api_version: value.api_version,
+            created_at: value.created_at,
+            updated_at: value.updated_at,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CreatedWebhookEndpointModel {
+    /// HMAC secret for the X-Signature of webhooks sent to the endpoint, shown only once
+    /// (on creation, or on rotation)
+    #[schema(example = "nk3w_Yb2c...")]
+    pub secret: String,
+    #[serde(flatten)]
+    pub info: WebhookEndpointModel,
+}
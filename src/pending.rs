@@ -0,0 +1,241 @@
+use crate::extras::InvoiceWebhook;
+use crate::model::{FiatQuote, PaymentTolerance};
+use crate::store::JsonStore;
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// How long an unselected invoice is kept after it expired, so lookups still explain what happened
+const EXPIRED_RETENTION_HOURS: i64 = 24;
+
+/// How long a selection may take before another one may start over (e.g. after a crash).
+/// Creating the core invoice takes a few seconds at most
+const SELECTION_LEASE: TimeDelta = TimeDelta::seconds(60);
+
+/// One acceptable (network, token) pair of an invoice, with its amount quoted at creation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotedOption {
+    pub network: String,
+    pub token: String,
+    pub amount: String,
+    pub decimals: u8,
+    pub fiat: Option<FiatQuote>,
+    /// per-invoice tolerance, or the network/token default at creation
+    #[serde(default)]
+    pub tolerance: Option<PaymentTolerance>,
+}
+
+/// Invoice created with several options and waiting for the payer to pick one.
+/// It only becomes a core invoice (with a derived address) after the selection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingInvoice {
+    pub id: String,
+    pub options: Vec<QuotedOption>,
+    pub webhook_url: Option<String>,
+    pub webhook_secret: Option<String>,
+    pub webhook_max_retries: Option<u32>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// kept after a cancel, so lookups still find the invoice and a second cancel is refused
+    #[serde(default)]
+    pub cancelled_at: Option<DateTime<Utc>>,
+    /// a selection is creating the core invoice, the invoice goes away once that exists
+    #[serde(default)]
+    pub selecting_since: Option<DateTime<Utc>>,
+}
+
+impl PendingInvoice {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled_at.is_some()
+    }
+
+    pub fn is_selecting(&self) -> bool {
+        self.selecting_since.is_some_and(|since| since > Utc::now() - SELECTION_LEASE)
+    }
+
+    pub fn option(&self, network: &str, token: &str) -> Option<&QuotedOption> {
+        self.options.iter().find(|o| o.network == network && o.token == token)
+    }
+
+    /// Webhook settings the invoice keeps once an option is chosen
+    pub fn webhook(&self) -> Option<InvoiceWebhook> {
+        self.webhook_url.clone().map(|url| InvoiceWebhook {
+            url,
+            secret: self.webhook_secret.clone(),
+            max_retries: self.webhook_max_retries,
+            previous_secret: None,
+        })
+    }
+}
+
+pub enum Cancellation {
+    Cancelled(PendingInvoice),
+    /// it expired or was cancelled already, and stays as it was
+    Refused(PendingInvoice),
+}
+
+pub enum Selection {
+    /// marked as being selected, finish with `finish_selection` or `abort_selection`
+    Started(PendingInvoice),
+    /// it expired, was cancelled, or another selection is in progress, and stays as it was
+    Refused(PendingInvoice),
+}
+
+pub struct PendingInvoiceStore {
+    invoices: JsonStore<PendingInvoice>,
+}
+
+impl PendingInvoiceStore {
+    pub async fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let invoices: JsonStore<PendingInvoice> = JsonStore::open(path).await?;
+
+        let cutoff = Utc::now() - TimeDelta::hours(EXPIRED_RETENTION_HOURS);
+        invoices.retain(|invoice| invoice.expires_at > cutoff).await?;
+
+        Ok(Self { invoices })
+    }
+
+    pub async fn get(&self, id: &str) -> Option<PendingInvoice> {
+        self.invoices.get(id).await
+    }
+
+    pub async fn insert(&self, invoice: PendingInvoice) -> anyhow::Result<()> {
+        self.invoices.insert(invoice.id.clone(), invoice).await
+    }
+
+    /// `None` also while a selection is in progress, the core invoice gets the expiry it started with
+    pub async fn set_expiry(&self, id: &str, expires_at: DateTime<Utc>) -> anyhow::Result<Option<PendingInvoice>> {
+        let mut set = false;
+
+        let invoice = self.invoices.update(id, |invoice| {
+            if !invoice.is_selecting() {
+                invoice.expires_at = expires_at;
+                set = true;
+            }
+        }).await?;
+
+        Ok(invoice.filter(|_| set))
+    }
+
+    /// Nothing was signed with the old secret yet, so it's simply replaced
+    pub async fn set_webhook_secret(&self, id: &str, secret: String) -> anyhow::Result<Option<PendingInvoice>> {
+        self.invoices.update(id, |invoice| invoice.webhook_secret = Some(secret)).await
+    }
+
+    /// Cancels the invoice unless it expired, was cancelled already or is being selected.
+    /// Checked and changed in one step, so two cancels can't both succeed
+    pub async fn cancel(&self, id: &str) -> anyhow::Result<Option<Cancellation>> {
+        let mut cancelled = false;
+
+        let invoice = self.invoices.update(id, |invoice| {
+            if !invoice.is_cancelled() && !invoice.is_expired() && !invoice.is_selecting() {
+                invoice.cancelled_at = Some(Utc::now());
+                cancelled = true;
+            }
+        }).await?;
+
+        Ok(invoice.map(|invoice| match cancelled {
+            true => Cancellation::Cancelled(invoice),
+            false => Cancellation::Refused(invoice),
+        }))
+    }
+
+    /// Marks the invoice as being selected unless it expired, was cancelled or another selection
+    /// holds it. Checked and changed in one step, so only one of several concurrent selections
+    /// starts. The invoice stays until `finish_selection`, so a crash midway doesn't lose it
+    pub async fn begin_selection(&self, id: &str) -> anyhow::Result<Option<Selection>> {
+        let mut started = false;
+
+        let invoice = self.invoices.update(id, |invoice| {
+            if !invoice.is_cancelled() && !invoice.is_expired() && !invoice.is_selecting() {
+                invoice.selecting_since = Some(Utc::now());
+                started = true;
+            }
+        }).await?;
+
+        Ok(invoice.map(|invoice| match started {
+            true => Selection::Started(invoice),
+            false => Selection::Refused(invoice),
+        }))
+    }
+
+    /// The core invoice exists, nothing is left awaiting a selection
+    pub async fn finish_selection(&self, id: &str) -> anyhow::Result<()> {
+        self.invoices.remove(id).await?;
+        Ok(())
+    }
+
+    /// Back to awaiting a selection, unless a later selection took over after the lease
+    pub async fn abort_selection(&self, selected: &PendingInvoice) -> anyhow::Result<()> {
+        self.invoices.update(&selected.id, |invoice| {
+            if invoice.selecting_since == selected.selecting_since {
+                invoice.selecting_since = None;
+            }
+        }).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invoice(id: &str, expires_in: i64) -> PendingInvoice {
+        PendingInvoice {
+            id: id.to_owned(),
+            options: vec![],
+            webhook_url: None,
+            webhook_secret: None,
+            webhook_max_retries: None,
+            created_at: Utc::now(),
+            expires_at: Utc::now() + TimeDelta::seconds(expires_in),
+            cancelled_at: None,
+            selecting_since: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn cancels_once() {
+        let dir = std::env::temp_dir().join(format!("necko3-pending-{}", uuid::Uuid::new_v4()));
+        let store = PendingInvoiceStore::open(dir.join("pending.jsonl")).await.unwrap();
+        store.insert(invoice("open", 900)).await.unwrap();
+        store.insert(invoice("expired", -1)).await.unwrap();
+
+        assert!(matches!(store.cancel("open").await.unwrap(), Some(Cancellation::Cancelled(_))));
+        assert!(matches!(store.cancel("open").await.unwrap(), Some(Cancellation::Refused(_))));
+        assert!(store.get("open").await.is_some_and(|invoice| invoice.is_cancelled()));
+
+        assert!(matches!(store.cancel("expired").await.unwrap(), Some(Cancellation::Refused(_))));
+        assert!(store.get("expired").await.is_some_and(|invoice| !invoice.is_cancelled()));
+
+        assert!(store.cancel("missing").await.unwrap().is_none());
+
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn selects_once() {
+        let dir = std::env::temp_dir().join(format!("necko3-pending-{}", uuid::Uuid::new_v4()));
+        let store = PendingInvoiceStore::open(dir.join("pending.jsonl")).await.unwrap();
+        store.insert(invoice("open", 900)).await.unwrap();
+
+        let Some(Selection::Started(selected)) = store.begin_selection("open").await.unwrap() else {
+            panic!("selection didn't start");
+        };
+        assert!(matches!(store.begin_selection("open").await.unwrap(), Some(Selection::Refused(_))));
+        assert!(matches!(store.cancel("open").await.unwrap(), Some(Cancellation::Refused(_))));
+
+        // kept until the core invoice exists
+        store.abort_selection(&selected).await.unwrap();
+        assert!(matches!(store.begin_selection("open").await.unwrap(), Some(Selection::Started(_))));
+        store.finish_selection("open").await.unwrap();
+        assert!(store.get("open").await.is_none());
+
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+}
@@ -0,0 +1,57 @@
+use crate::rates::RateProvider;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use tokio::sync::Mutex;
+
+type Rates = HashMap<String, HashMap<String, String>>;
+
+/// Rates from a JSON file shaped like `{ "USDC": { "USD": "1", "EUR": "0.92" } }`.
+/// The file is re-read whenever it changes, so an external job can keep it fresh.
+pub struct FileRateProvider {
+    path: PathBuf,
+    cache: Mutex<Option<(SystemTime, Rates)>>,
+}
+
+impl FileRateProvider {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            cache: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RateProvider for FileRateProvider {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    async fn rate(&self, token: &str, fiat: &str) -> anyhow::Result<Option<String>> {
+        let modified = tokio::fs::metadata(&self.path).await?.modified()?;
+        let mut cache = self.cache.lock().await;
+
+        if cache.as_ref().is_none_or(|(loaded_at, _)| *loaded_at != modified) {
+            let raw = tokio::fs::read(&self.path).await?;
+            let rates: Rates = serde_json::from_slice(&raw)?;
+
+            // normalize symbols so lookups are case-insensitive
+            let rates = rates.into_iter()
+                .map(|(token, fiats)| (
+                    token.to_uppercase(),
+                    fiats.into_iter().map(|(f, r)| (f.to_uppercase(), r)).collect(),
+                ))
+                .collect();
+
+            *cache = Some((modified, rates));
+        }
+
+        let rate = cache.as_ref()
+            .and_then(|(_, rates)| rates.get(&token.to_uppercase()))
+            .and_then(|fiats| fiats.get(&fiat.to_uppercase()))
+            .cloned();
+
+        Ok(rate)
+    }
+}
@@ -0,0 +1,86 @@
+mod static_rates;
+mod file_rates;
+
+pub use static_rates::StaticRateProvider;
+pub use file_rates::FileRateProvider;
+
+use necko3_core::deps::{parse_units, U256};
+
+/// Precision used for fiat amounts and rates during conversion
+const RATE_DECIMALS: u8 = 18;
+
+/// Source of token prices in fiat. Implement it to plug in a live price oracle.
+#[async_trait::async_trait]
+pub trait RateProvider: Send + Sync {
+    /// Short name stored on invoices to show where the rate came from
+    fn name(&self) -> &str;
+
+    /// Price of one `token` in `fiat`, as a decimal string (e.g. "0.92").
+    /// `Ok(None)` means the pair isn't known to this provider.
+    async fn rate(&self, token: &str, fiat: &str) -> anyhow::Result<Option<String>>;
+}
+
+/// Converts `fiat_amount` into raw token units at `rate` (fiat per token).
+/// Rounds up, so the merchant never receives less than asked.
+pub fn fiat_to_token(fiat_amount: &str, rate: &str, decimals: u8) -> anyhow::Result<U256> {
+    let fiat = parse_fiat(fiat_amount)?;
+    let rate = parse_units(rate, RATE_DECIMALS)?.get_absolute();
+
+    if rate.is_zero() {
+        anyhow::bail!("Rate must be greater than zero");
+    }
+
+    let scale = U256::from(10).pow(U256::from(decimals));
+
+    Ok((fiat * scale + rate - U256::from(1)) / rate)
+}
+
+/// Parses a fiat amount at conversion precision, it has to be greater than zero
+pub fn parse_fiat(fiat_amount: &str) -> anyhow::Result<U256> {
+    let fiat = parse_units(fiat_amount, RATE_DECIMALS)?;
+
+    if fiat.is_negative() || fiat.get_absolute().is_zero() {
+        anyhow::bail!("Fiat amount must be greater than zero");
+    }
+
+    Ok(fiat.get_absolute())
+}
+
+/// `PRICE_ORACLE=static` reads `PRICE_RATES`, `PRICE_ORACLE=file` watches `PRICE_RATES_FILE`
+pub fn rate_provider_from_env() -> anyhow::Result<Box<dyn RateProvider>> {
+    let oracle = std::env::var("PRICE_ORACLE")
+        .unwrap_or_else(|_| "static".into());
+
+    match oracle.as_str() {
+        "static" => {
+            let rates = std::env::var("PRICE_RATES").unwrap_or_default();
+            Ok(Box::new(StaticRateProvider::parse(&rates)?))
+        }
+        "file" => {
+            let path = std::env::var("PRICE_RATES_FILE")
+                .unwrap_or_else(|_| "rates.json".into());
+            Ok(Box::new(FileRateProvider::new(path.into())))
+        }
+        other => anyhow::bail!("Unknown PRICE_ORACLE '{}'", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_rounding_up() {
+        // 10 USD at 3 USD per token, 6 decimals
+        assert_eq!(fiat_to_token("10", "3", 6).unwrap(), U256::from(3_333_334));
+        assert_eq!(fiat_to_token("1.5", "1", 6).unwrap(), U256::from(1_500_000));
+    }
+
+    #[test]
+    fn rejects_non_positive_amounts() {
+        assert!(parse_fiat("0").is_err());
+        assert!(parse_fiat("-5").is_err());
+        assert!(fiat_to_token("-5", "1", 6).is_err());
+        assert!(fiat_to_token("5", "0", 6).is_err());
+    }
+}
@@ -0,0 +1,39 @@
+use crate::rates::RateProvider;
+use std::collections::HashMap;
+
+/// Fixed rates, parsed from `TOKEN/FIAT=rate` pairs separated by commas
+/// (e.g. `USDC/USD=1,USDC/EUR=0.92`)
+pub struct StaticRateProvider {
+    rates: HashMap<(String, String), String>,
+}
+
+impl StaticRateProvider {
+    pub fn parse(raw: &str) -> anyhow::Result<Self> {
+        let mut rates = HashMap::new();
+
+        for pair in raw.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (symbols, rate) = pair.split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Bad rate '{}', expected TOKEN/FIAT=rate", pair))?;
+            let (token, fiat) = symbols.split_once('/')
+                .ok_or_else(|| anyhow::anyhow!("Bad rate '{}', expected TOKEN/FIAT=rate", pair))?;
+
+            rates.insert(
+                (token.trim().to_uppercase(), fiat.trim().to_uppercase()),
+                rate.trim().to_owned(),
+            );
+        }
+
+        Ok(Self { rates })
+    }
+}
+
+#[async_trait::async_trait]
+impl RateProvider for StaticRateProvider {
+    fn name(&self) -> &str {
+        "static"
+    }
+
+    async fn rate(&self, token: &str, fiat: &str) -> anyhow::Result<Option<String>> {
+        Ok(self.rates.get(&(token.to_uppercase(), fiat.to_uppercase())).cloned())
+    }
+}
@@ -0,0 +1,98 @@
+use crate::model::core::WebhookEventSchema;
+use crate::model::envelope::{ApiVersion, EventSnapshot};
+use crate::sink::EventSink;
+use lapin::options::{BasicPublishOptions, ConfirmSelectOptions, ExchangeDeclareOptions};
+use lapin::types::{AMQPValue, FieldTable};
+use lapin::{BasicProperties, Channel, Connection, ConnectionProperties, ExchangeKind};
+use tokio::sync::Mutex;
+
+/// Persistent messages on a durable topic exchange, routed by event type (e.g. `invoice_paid`),
+/// with publisher confirms. The message id is the event id. Bind queues with `#` for everything.
+/// A dropped connection is opened again on the next event
+pub struct AmqpSink {
+    url: String,
+    exchange: String,
+    api_version: ApiVersion,
+    /// the connection has to stay alive as long as its channel
+    channel: Mutex<Option<(Connection, Channel)>>,
+}
+
+impl AmqpSink {
+    pub async fn connect(url: String, exchange: String, api_version: ApiVersion) -> anyhow::Result<Self> {
+        let sink = Self {
+            url,
+            exchange,
+            api_version,
+            channel: Mutex::new(None),
+        };
+
+        // fail at startup rather than on the first event
+        let opened = sink.open().await?;
+        *sink.channel.lock().await = Some(opened);
+
+        Ok(sink)
+    }
+
+    async fn open(&self) -> anyhow::Result<(Connection, Channel)> {
+        let connection = Connection::connect(&self.url, ConnectionProperties::default()).await
+            .map_err(|e| anyhow::anyhow!("Failed to connect to AMQP broker: {}", e))?;
+        let channel = connection.create_channel().await?;
+
+        channel.confirm_select(ConfirmSelectOptions::default()).await?;
+        channel.exchange_declare(
+            &self.exchange,
+            ExchangeKind::Topic,
+            ExchangeDeclareOptions { durable: true, ..Default::default() },
+            FieldTable::default(),
+        ).await?;
+
+        Ok((connection, channel))
+    }
+}
+
+#[async_trait::async_trait]
+impl EventSink for AmqpSink {
+    fn name(&self) -> &str {
+        "amqp"
+    }
+
+    async fn publish(&self, event: &WebhookEventSchema, snapshot: &EventSnapshot) -> anyhow::Result<()> {
+        let payload = snapshot.encode(event, self.api_version)?;
+
+        let mut headers = FieldTable::default();
+        headers.insert("invoice_id".into(), AMQPValue::LongString(event.invoice_id().to_owned().into()));
+
+        let properties = BasicProperties::default()
+            .with_content_type("application/json".into())
+            .with_message_id(snapshot.event_id.as_str().into())
+            .with_delivery_mode(2)
+            .with_headers(headers);
+
+        let mut guard = self.channel.lock().await;
+
+        // a dropped connection is opened again, a failed open is retried along with the event
+        let (_, channel) = match guard.take() {
+            Some(open) if open.1.status().connected() => guard.insert(open),
+            _ => guard.insert(self.open().await?),
+        };
+
+        let published = async {
+            let confirmation = channel.basic_publish(
+                &self.exchange,
+                event.event_type().as_str(),
+                BasicPublishOptions::default(),
+                payload.as_bytes(),
+                properties,
+            ).await?.await?;
+
+            anyhow::ensure!(!confirmation.is_nack(), "Broker refused the message");
+            Ok(())
+        }.await;
+
+        if published.is_err() {
+            *guard = None;
+        }
+
+        published
+    }
+}
@@ -0,0 +1,43 @@
+use crate::delivery::{route, DeliveryStore, EndpointStore};
+use crate::extras::InvoiceExtrasStore;
+use crate::model::core::WebhookEventSchema;
+use crate::model::envelope::EventSnapshot;
+use crate::sink::EventSink;
+use std::sync::Arc;
+
+/// Webhooks, queued here for the backend's dispatcher. Only invoices created before the backend
+/// took over delivery still get the events core knows sent by core, to their `webhook_url`
+pub struct HttpSink {
+    deliveries: Arc<DeliveryStore>,
+    endpoints: Arc<EndpointStore>,
+    extras: Arc<InvoiceExtrasStore>,
+}
+
+impl HttpSink {
+    pub fn new(deliveries: Arc<DeliveryStore>, endpoints: Arc<EndpointStore>,
+               extras: Arc<InvoiceExtrasStore>) -> Self {
+        Self { deliveries, endpoints, extras }
+    }
+}
+
+#[async_trait::async_trait]
+impl EventSink for HttpSink {
+    fn name(&self) -> &str {
+        "http"
+    }
+
+    async fn publish(&self, event: &WebhookEventSchema, snapshot: &EventSnapshot) -> anyhow::Result<()> {
+        let sent_by_core = snapshot.invoice.webhook_url.is_some()
+            && self.extras.get(&snapshot.invoice.id).await.webhook.is_none();
+
+        if sent_by_core && event.event_type().is_core() {
+            return Ok(());
+        }
+
+        for delivery in route(&self.endpoints, &snapshot.invoice, event, snapshot).await {
+            self.deliveries.schedule(delivery).await?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,41 @@
+use crate::model::core::WebhookEventSchema;
+use crate::model::envelope::{ApiVersion, EventSnapshot};
+use crate::sink::EventSink;
+
+/// Core NATS, one message per event on `<subject>.<event_type>` (e.g. `necko3.events.invoice_paid`),
+/// so consumers can subscribe to `necko3.events.>` or pick event types. `Nats-Msg-Id` is the event id,
+/// for JetStream deduplication. The client reconnects by itself
+pub struct NatsSink {
+    client: async_nats::Client,
+    subject: String,
+    api_version: ApiVersion,
+}
+
+impl NatsSink {
+    pub async fn connect(url: &str, subject: String, api_version: ApiVersion) -> anyhow::Result<Self> {
+        let client = async_nats::connect(url).await
+            .map_err(|e| anyhow::anyhow!("Failed to connect to NATS at {}: {}", url, e))?;
+
+        Ok(Self { client, subject, api_version })
+    }
+}
+
+#[async_trait::async_trait]
+impl EventSink for NatsSink {
+    fn name(&self) -> &str {
+        "nats"
+    }
+
+    async fn publish(&self, event: &WebhookEventSchema, snapshot: &EventSnapshot) -> anyhow::Result<()> {
+        let subject = format!("{}.{}", self.subject, event.event_type().as_str());
+        let payload = snapshot.encode(event, self.api_version)?;
+
+        let mut headers = async_nats::HeaderMap::new();
+        headers.insert("Nats-Msg-Id", snapshot.event_id.as_str());
+
+        self.client.publish_with_headers(subject, headers, payload.into()).await?;
+        self.client.flush().await?;
+
+        Ok(())
+    }
+}
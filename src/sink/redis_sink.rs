@@ -0,0 +1,51 @@
+use crate::model::core::WebhookEventSchema;
+use crate::model::envelope::{ApiVersion, EventSnapshot};
+use crate::sink::EventSink;
+use redis::aio::ConnectionManager;
+
+/// Redis Streams, one entry per event with `event_id`, `event_type`, `invoice_id` and `payload` fields.
+/// Read it with consumer groups (`XREADGROUP`). The connection manager reconnects by itself
+pub struct RedisStreamSink {
+    connection: ConnectionManager,
+    stream: String,
+    /// approximate cap on the stream length, 0 to keep everything
+    max_len: u64,
+    api_version: ApiVersion,
+}
+
+impl RedisStreamSink {
+    pub async fn connect(url: &str, stream: String, max_len: u64, api_version: ApiVersion) -> anyhow::Result<Self> {
+        let client = redis::Client::open(url)?;
+        let connection = client.get_connection_manager().await
+            .map_err(|e| anyhow::anyhow!("Failed to connect to Redis at {}: {}", url, e))?;
+
+        Ok(Self { connection, stream, max_len, api_version })
+    }
+}
+
+#[async_trait::async_trait]
+impl EventSink for RedisStreamSink {
+    fn name(&self) -> &str {
+        "redis"
+    }
+
+    async fn publish(&self, event: &WebhookEventSchema, snapshot: &EventSnapshot) -> anyhow::Result<()> {
+        let payload = snapshot.encode(event, self.api_version)?;
+
+        let mut command = redis::cmd("XADD");
+        command.arg(&self.stream);
+        if self.max_len > 0 {
+            command.arg("MAXLEN").arg("~").arg(self.max_len);
+        }
+        command.arg("*")
+            .arg("event_id").arg(&snapshot.event_id)
+            .arg("event_type").arg(event.event_type().as_str())
+            .arg("invoice_id").arg(event.invoice_id())
+            .arg("payload").arg(payload);
+
+        let mut connection = self.connection.clone();
+        command.query_async::<String>(&mut connection).await?;
+
+        Ok(())
+    }
+}
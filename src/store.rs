@@ -0,0 +1,314 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Mutex, RwLock};
+use tracing::{info, warn};
+
+/// The log is compacted once it holds this many lines and `COMPACT_RATIO` times the live items
+const COMPACT_MIN_OPS: usize = 10_000;
+const COMPACT_RATIO: usize = 4;
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Op<T> {
+    Put { id: String, item: T },
+    Remove { id: String },
+}
+
+/// Keyed collection kept in memory and backed by an append-only JSON Lines file.
+/// Meant for backend-owned data that doesn't live in the core database (API keys, etc.).
+/// The log is replayed and compacted on startup, and compacted again while running once it
+/// grew well past the live items. A write appends one line instead of rewriting the whole
+/// collection, which the invoice-sized stores couldn't afford, and is synced to disk before
+/// it returns unless the store was opened `without_sync`.
+pub struct JsonStore<T> {
+    items: RwLock<HashMap<String, T>>,
+    log: Mutex<Log>,
+    sync: bool,
+}
+
+struct Log {
+    path: PathBuf,
+    file: File,
+    /// lines in the file
+    ops: usize,
+}
+
+impl<T> JsonStore<T>
+where
+    T: Clone + Serialize + DeserializeOwned,
+{
+    pub async fn open(path: PathBuf) -> anyhow::Result<Self> {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let mut items = HashMap::new();
+
+        match tokio::fs::read_to_string(&path).await {
+            Ok(raw) => {
+                let lines: Vec<&str> = raw.lines().filter(|l| !l.trim().is_empty()).collect();
+
+                for (n, line) in lines.iter().enumerate() {
+                    let op = match serde_json::from_str::<Op<T>>(line) {
+                        Ok(op) => op,
+                        // a write cut short by a crash, compacting below drops it
+                        Err(e) if n + 1 == lines.len() => {
+                            warn!(path = %path.display(), error = %e, "Skipping torn last line of the store");
+                            break;
+                        }
+                        Err(e) => return Err(e.into()),
+                    };
+
+                    match op {
+                        Op::Put { id, item } => { items.insert(id, item); }
+                        Op::Remove { id } => { items.remove(&id); }
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                // stores used to be a single JSON object under `.json`, rewritten on every write
+                let legacy = path.with_extension("json");
+                match tokio::fs::read(&legacy).await {
+                    Ok(raw) => {
+                        items = serde_json::from_slice(&raw)?;
+                        info!(path = %legacy.display(), count = items.len(), "Importing store from the old format");
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        let file = compact(&path, &items).await?;
+
+        info!(path = %path.display(), count = items.len(), "Store loaded");
+
+        Ok(Self {
+            log: Mutex::new(Log { path, file, ops: items.len() }),
+            items: RwLock::new(items),
+            sync: true,
+        })
+    }
+
+    /// Leaves syncing writes to the OS, for data that is cheap to lose in a crash
+    pub fn without_sync(mut self) -> Self {
+        self.sync = false;
+        self
+    }
+
+    pub async fn get(&self, id: &str) -> Option<T> {
+        self.items.read().await.get(id).cloned()
+    }
+
+    pub async fn list(&self) -> Vec<T> {
+        self.items.read().await.values().cloned().collect()
+    }
+
+    pub async fn entries(&self) -> Vec<(String, T)> {
+        self.items.read().await.iter()
+            .map(|(id, item)| (id.clone(), item.clone()))
+            .collect()
+    }
+
+    pub async fn insert(&self, id: String, item: T) -> anyhow::Result<()> {
+        let mut items = self.items.write().await;
+        self.append(&items, &Op::Put { id: id.clone(), item: item.clone() }).await?;
+        items.insert(id, item);
+        Ok(())
+    }
+
+    /// Inserts only if `id` is free, otherwise hands back the existing item untouched
+    pub async fn try_insert(&self, id: String, item: T) -> anyhow::Result<Option<T>> {
+        let mut items = self.items.write().await;
+
+        if let Some(existing) = items.get(&id) {
+            return Ok(Some(existing.clone()));
+        }
+
+        self.append(&items, &Op::Put { id: id.clone(), item: item.clone() }).await?;
+        items.insert(id, item);
+        Ok(None)
+    }
+
+    /// Applies `f` to the item in place. Returns the updated item, or `None` if it doesn't exist.
+    pub async fn update<F>(&self, id: &str, f: F) -> anyhow::Result<Option<T>>
+    where
+        F: FnOnce(&mut T),
+    {
+        let mut items = self.items.write().await;
+
+        let Some(mut updated) = items.get(id).cloned() else {
+            return Ok(None);
+        };
+        f(&mut updated);
+
+        self.append(&items, &Op::Put { id: id.to_owned(), item: updated.clone() }).await?;
+        items.insert(id.to_owned(), updated.clone());
+        Ok(Some(updated))
+    }
+
+    pub async fn remove(&self, id: &str) -> anyhow::Result<Option<T>> {
+        let mut items = self.items.write().await;
+
+        if !items.contains_key(id) {
+            return Ok(None);
+        }
+
+        self.append(&items, &Op::<T>::Remove { id: id.to_owned() }).await?;
+        Ok(items.remove(id))
+    }
+
+    /// Drops every item for which `keep` returns false
+    pub async fn retain<F>(&self, keep: F) -> anyhow::Result<()>
+    where
+        F: Fn(&T) -> bool,
+    {
+        let mut items = self.items.write().await;
+
+        let dropped: Vec<String> = items.iter()
+            .filter(|(_, item)| !keep(item))
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for id in dropped {
+            self.append(&items, &Op::<T>::Remove { id: id.clone() }).await?;
+            items.remove(&id);
+        }
+
+        Ok(())
+    }
+
+    /// Callers hold the items write lock, so log order always matches memory
+    async fn append(&self, items: &HashMap<String, T>, op: &Op<T>) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(op)?;
+        line.push(b'\n');
+
+        let mut log = self.log.lock().await;
+
+        // `items` doesn't have `op` applied yet, so it goes after the compacted log like any other
+        if log.ops >= COMPACT_MIN_OPS && log.ops > COMPACT_RATIO * items.len() {
+            log.file = compact(&log.path, items).await?;
+            log.ops = items.len();
+        }
+
+        log.file.write_all(&line).await?;
+        log.file.flush().await?;
+        if self.sync {
+            log.file.sync_data().await?;
+        }
+        log.ops += 1;
+
+        Ok(())
+    }
+}
+
+/// Rewrites the log at `path` with just `items` and opens it for appending
+async fn compact<T: Serialize>(path: &Path, items: &HashMap<String, T>) -> anyhow::Result<File> {
+    let tmp = path.with_extension("jsonl.tmp");
+    let mut compacted = Vec::new();
+    for (id, item) in items {
+        serde_json::to_writer(&mut compacted, &Op::Put { id: id.clone(), item })?;
+        compacted.push(b'\n');
+    }
+
+    let mut file = File::create(&tmp).await?;
+    file.write_all(&compacted).await?;
+    file.sync_all().await?;
+    tokio::fs::rename(&tmp, path).await?;
+
+    Ok(OpenOptions::new().append(true).open(path).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("necko3-store-{}", uuid::Uuid::new_v4())).join("items.jsonl")
+    }
+
+    #[tokio::test]
+    async fn replays_the_log() {
+        let path = temp_path();
+
+        let store: JsonStore<u32> = JsonStore::open(path.clone()).await.unwrap();
+        store.insert("a".into(), 1).await.unwrap();
+        store.insert("b".into(), 2).await.unwrap();
+        store.update("a", |item| *item += 10).await.unwrap();
+        store.remove("b").await.unwrap();
+        assert_eq!(store.try_insert("a".into(), 5).await.unwrap(), Some(11));
+        drop(store);
+
+        let store: JsonStore<u32> = JsonStore::open(path.clone()).await.unwrap();
+        assert_eq!(store.entries().await, vec![("a".to_owned(), 11)]);
+
+        // compacted down to the live item
+        let raw = tokio::fs::read_to_string(&path).await.unwrap();
+        assert_eq!(raw.lines().count(), 1);
+
+        tokio::fs::remove_dir_all(path.parent().unwrap()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn imports_the_old_format() {
+        let path = temp_path();
+        tokio::fs::create_dir_all(path.parent().unwrap()).await.unwrap();
+        tokio::fs::write(path.with_extension("json"), r#"{"a": 1, "b": 2}"#).await.unwrap();
+
+        let store: JsonStore<u32> = JsonStore::open(path.clone()).await.unwrap();
+        assert_eq!(store.get("a").await, Some(1));
+        assert_eq!(store.get("b").await, Some(2));
+        store.remove("b").await.unwrap();
+        drop(store);
+
+        // the log wins from now on
+        let store: JsonStore<u32> = JsonStore::open(path.clone()).await.unwrap();
+        assert_eq!(store.get("b").await, None);
+
+        tokio::fs::remove_dir_all(path.parent().unwrap()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn skips_a_torn_last_line() {
+        let path = temp_path();
+        tokio::fs::create_dir_all(path.parent().unwrap()).await.unwrap();
+        tokio::fs::write(&path, concat!(
+            r#"{"op":"put","id":"a","item":1}"#, "\n",
+            r#"{"op":"put","id":"b","it"#,
+        )).await.unwrap();
+
+        let store: JsonStore<u32> = JsonStore::open(path.clone()).await.unwrap();
+        assert_eq!(store.entries().await, vec![("a".to_owned(), 1)]);
+        drop(store);
+
+        // only the last line may be torn
+        tokio::fs::write(&path, concat!(
+            r#"{"op":"put","id":"b","it"#, "\n",
+            r#"{"op":"put","id":"a","item":1}"#, "\n",
+        )).await.unwrap();
+        assert!(JsonStore::<u32>::open(path.clone()).await.is_err());
+
+        tokio::fs::remove_dir_all(path.parent().unwrap()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn compacts_while_running() {
+        let path = temp_path();
+
+        let store: JsonStore<u32> = JsonStore::open(path.clone()).await.unwrap().without_sync();
+        for n in 0..COMPACT_MIN_OPS as u32 + 1 {
+            store.insert("a".into(), n).await.unwrap();
+        }
+
+        let raw = tokio::fs::read_to_string(&path).await.unwrap();
+        assert_eq!(raw.lines().count(), 2);
+        assert_eq!(store.get("a").await, Some(COMPACT_MIN_OPS as u32));
+
+        tokio::fs::remove_dir_all(path.parent().unwrap()).await.unwrap();
+    }
+}
@@ -0,0 +1,131 @@
+use crate::model::PaymentTolerance;
+use crate::store::JsonStore;
+use necko3_core::deps::{format_units, parse_units, U256};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use utoipa::ToSchema;
+
+/// Precision of percentage tolerances ("0.25%")
+const PERCENT_DECIMALS: u8 = 4;
+
+/// Amounts an invoice settles within, fixed when its address is derived.
+/// The core invoice is created for `min_amount`, so it turns Paid as soon as that arrives.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct InvoiceTolerance {
+    /// amount the merchant asked for
+    #[schema(example = "25.37")]
+    pub expected_amount: String,
+    /// smallest payment still accepted as paid
+    #[schema(example = "25.36")]
+    pub min_amount: String,
+    /// biggest payment not reported as overpaid
+    #[schema(example = "26.6385")]
+    pub max_amount: String,
+}
+
+impl InvoiceTolerance {
+    /// `None` when the tolerance doesn't widen anything, so the invoice behaves as before
+    pub fn resolve(tolerance: &PaymentTolerance, amount: &str, decimals: u8) -> anyhow::Result<Option<Self>> {
+        let amount_raw = parse_units(amount, decimals)?.get_absolute();
+
+        let under = match &tolerance.underpayment {
+            Some(spec) => tolerance_raw(spec, amount_raw, decimals)?,
+            None => U256::ZERO,
+        };
+        let over = match &tolerance.overpayment {
+            Some(spec) => tolerance_raw(spec, amount_raw, decimals)?,
+            None => U256::ZERO,
+        };
+
+        if under.is_zero() && over.is_zero() {
+            return Ok(None);
+        }
+
+        if under >= amount_raw {
+            anyhow::bail!("Underpayment tolerance must be smaller than the amount");
+        }
+
+        Ok(Some(Self {
+            expected_amount: amount.to_owned(),
+            min_amount: format_units(amount_raw - under, decimals)?,
+            max_amount: format_units(amount_raw + over, decimals)?,
+        }))
+    }
+}
+
+/// Checks a tolerance without an amount at hand, e.g. before storing it for a token
+pub fn validate(tolerance: &PaymentTolerance) -> anyhow::Result<()> {
+    // 18 decimals fit every token, the real precision is checked once the token is known
+    for spec in [&tolerance.underpayment, &tolerance.overpayment].into_iter().flatten() {
+        tolerance_raw(spec, U256::ZERO, 18)?;
+    }
+
+    if let Some(spec) = &tolerance.underpayment
+        && let Some(percent) = spec.trim().strip_suffix('%')
+        && parse_units(percent.trim(), PERCENT_DECIMALS)?.get_absolute() >= percent_scale() {
+        anyhow::bail!("Underpayment tolerance must be below 100%");
+    }
+
+    Ok(())
+}
+
+/// 100% at `PERCENT_DECIMALS` precision
+fn percent_scale() -> U256 {
+    U256::from(100) * U256::from(10).pow(U256::from(PERCENT_DECIMALS))
+}
+
+/// `spec` is either token units ("0.01") or a percentage of `amount_raw` ("1%").
+/// Percentages round down, so the tolerance never ends up wider than asked.
+fn tolerance_raw(spec: &str, amount_raw: U256, decimals: u8) -> anyhow::Result<U256> {
+    let spec = spec.trim();
+
+    let value = match spec.strip_suffix('%') {
+        Some(percent) => {
+            let percent = parse_units(percent.trim(), PERCENT_DECIMALS)?;
+            if percent.is_negative() {
+                anyhow::bail!("Tolerance '{}' must not be negative", spec);
+            }
+
+            amount_raw * percent.get_absolute() / percent_scale()
+        }
+        None => {
+            let absolute = parse_units(spec, decimals)?;
+            if absolute.is_negative() {
+                anyhow::bail!("Tolerance '{}' must not be negative", spec);
+            }
+
+            absolute.get_absolute()
+        }
+    };
+
+    Ok(value)
+}
+
+/// Default tolerances per (network, token), used for invoices that don't set their own
+pub struct ToleranceStore {
+    tolerances: JsonStore<PaymentTolerance>,
+}
+
+impl ToleranceStore {
+    pub async fn open(path: PathBuf) -> anyhow::Result<Self> {
+        Ok(Self {
+            tolerances: JsonStore::open(path).await?,
+        })
+    }
+
+    pub async fn get(&self, network: &str, token: &str) -> Option<PaymentTolerance> {
+        self.tolerances.get(&key(network, token)).await
+    }
+
+    pub async fn set(&self, network: &str, token: &str, tolerance: PaymentTolerance) -> anyhow::Result<()> {
+        self.tolerances.insert(key(network, token), tolerance).await
+    }
+
+    pub async fn remove(&self, network: &str, token: &str) -> anyhow::Result<Option<PaymentTolerance>> {
+        self.tolerances.remove(&key(network, token)).await
+    }
+}
+
+fn key(network: &str, token: &str) -> String {
+    format!("{}/{}", network, token)
+}
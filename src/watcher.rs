@@ -0,0 +1,552 @@
+use crate::block_times::BlockTimes;
+use crate::delivery::{DeliveryChange, DeliveryStore};
+use crate::event_log::{EventLog, LoggedEvent};
+use crate::events::{Event, EventBus, EventKind, WebhookState};
+use crate::extras::{InvoiceExtrasStore, Settlement};
+use crate::model::core::{InvoiceStatusSchema, PaymentSchema, PaymentStatusSchema, WebhookEventSchema,
+                         WebhookStatusSchema};
+use crate::model::public::PaymentProgress;
+use crate::model::InvoiceModel;
+use chrono::{DateTime, TimeDelta, Utc};
+use necko3_core::db::DatabaseAdapter;
+use necko3_core::deps::U256;
+use necko3_core::model::{Invoice, InvoiceFilter, InvoiceStatus, Pagination, PaymentFilter, WebhookFilter};
+use necko3_core::AppState;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+
+/// Page size when listing invoices, payments and webhooks from core
+const BATCH: u32 = 100;
+
+/// Settled invoices that expire this long before the newest logged event are still checked on
+/// startup. Covers the janitor expiring invoices a bit after their `expires_at`
+const CATCH_UP_MARGIN: TimeDelta = TimeDelta::hours(1);
+
+/// Blocks from the payment's one up to `head`, both included. 0 until the chain gets there.
+pub fn confirmations(head: u64, block_number: u64) -> u64 {
+    match head >= block_number {
+        true => head - block_number + 1,
+        false => 0,
+    }
+}
+
+#[derive(Copy, Clone)]
+/// How far the backend has processed a chain. Core's `last_processed_block`, not the chain's
+/// own head, which it trails by `block_lag` and by however far processing falls behind
+pub struct ProcessedHead {
+    /// last block the backend has processed
+    pub block: u64,
+    pub required_confirmations: u64,
+}
+
+/// How far the backend has processed `network`, `None` for an unknown chain. Feeds `block_times` along the way
+pub async fn processed_head(state: &AppState, block_times: &BlockTimes, network: &str) -> anyhow::Result<Option<ProcessedHead>> {
+    let chain = state.db.get_chain(network).await?;
+
+    let head = chain.map(|chain| {
+        let config = chain.config().read().unwrap();
+        ProcessedHead {
+            block: config.last_processed_block,
+            required_confirmations: config.required_confirmations,
+        }
+    });
+
+    if let Some(head) = head {
+        block_times.observe(network, head.block);
+    }
+
+    Ok(head)
+}
+
+struct TrackedPayment {
+    payment: PaymentSchema,
+    confirmations: u64,
+}
+
+struct TrackedWebhook {
+    status: WebhookStatusSchema,
+    attempts: u32,
+}
+
+struct Tracked {
+    status: InvoiceStatusSchema,
+    paid_raw: U256,
+    /// expiry the invoice_expiring_soon event was sent for, an extension resets it
+    expiring_soon: Option<DateTime<Utc>>,
+    payments: HashMap<String, TrackedPayment>,
+    webhooks: HashMap<String, TrackedWebhook>,
+}
+
+impl Tracked {
+    /// What the logged events say about an invoice this run hasn't seen yet, `None` if nothing
+    /// was logged for it. Confirmation counts and core webhooks aren't logged and start over.
+    fn from_log(entries: &[LoggedEvent], invoice: &InvoiceModel) -> Option<Self> {
+        let mut tracked = Tracked {
+            status: InvoiceStatusSchema::Pending,
+            paid_raw: U256::ZERO,
+            expiring_soon: None,
+            payments: HashMap::new(),
+            webhooks: HashMap::new(),
+        };
+
+        for entry in entries {
+            match &entry.event {
+                WebhookEventSchema::InvoiceCreated { .. } => {}
+                WebhookEventSchema::InvoicePartiallyPaid { .. } => tracked.paid_raw = entry.invoice.paid_raw,
+                WebhookEventSchema::InvoiceExpiringSoon { expires_at, .. } => tracked.expiring_soon = Some(*expires_at),
+                WebhookEventSchema::InvoicePaid { .. } => {
+                    tracked.status = entry.invoice.status;
+                    tracked.paid_raw = entry.invoice.paid_raw;
+                }
+                // reopening pushes the expiry out, and nothing is logged for it
+                WebhookEventSchema::InvoiceExpired { .. } if invoice.expires_at > entry.invoice.expires_at => {
+                    tracked.status = InvoiceStatusSchema::Pending;
+                }
+                WebhookEventSchema::InvoiceExpired { .. } => tracked.status = InvoiceStatusSchema::Expired,
+                WebhookEventSchema::InvoiceCancelled { .. } => tracked.status = InvoiceStatusSchema::Cancelled,
+                WebhookEventSchema::TxDetected { .. } | WebhookEventSchema::TxConfirmed { .. }
+                | WebhookEventSchema::PaymentCancelled { .. } => {
+                    if let Some(payment) = &entry.payment {
+                        tracked.payments.insert(payment.id.clone(), TrackedPayment {
+                            payment: payment.clone(),
+                            confirmations: 0,
+                        });
+                    }
+                }
+                WebhookEventSchema::PaymentReverted { .. } => {
+                    if let Some(payment) = &entry.payment {
+                        tracked.payments.remove(&payment.id);
+                    }
+                }
+            }
+        }
+
+        (!entries.is_empty()).then_some(tracked)
+    }
+}
+
+/// Invoices the API just created or cancelled. They may leave Pending before the next poll
+/// lists them, so the watcher fetches them by id once.
+#[derive(Default)]
+pub struct WatchList(Mutex<HashSet<String>>);
+
+impl WatchList {
+    pub fn add(&self, invoice_id: &str) {
+        self.0.lock().unwrap().insert(invoice_id.to_owned());
+    }
+
+    fn take(&self) -> HashSet<String> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+/// Core settles invoices on its own and has no notifications, so the watcher polls it
+/// and turns whatever changed between two polls into events on the `EventBus`.
+/// What it knew before a restart comes back from the event log.
+///
+/// Core can't list payments or webhooks of several invoices at once, so a poll costs two
+/// queries per pending (or still tracked) invoice on top of listing them. That is fine for a
+/// few hundred open invoices at the default `WATCHER_INTERVAL`; with more, raise the interval.
+pub struct Watcher {
+    state: Arc<AppState>,
+    extras: Arc<InvoiceExtrasStore>,
+    events: Arc<EventBus>,
+    log: Arc<EventLog>,
+    block_times: Arc<BlockTimes>,
+    watch_list: Arc<WatchList>,
+    /// how long before expiry invoice_expiring_soon is sent, zero to not send it
+    expiring_soon: TimeDelta,
+    tracked: HashMap<String, Tracked>,
+}
+
+impl Watcher {
+    pub fn new(state: Arc<AppState>, extras: Arc<InvoiceExtrasStore>, events: Arc<EventBus>,
+               log: Arc<EventLog>, block_times: Arc<BlockTimes>, watch_list: Arc<WatchList>) -> Self {
+        Self {
+            state,
+            extras,
+            events,
+            log,
+            block_times,
+            watch_list,
+            expiring_soon: TimeDelta::zero(),
+            tracked: HashMap::new(),
+        }
+    }
+
+    pub fn with_expiring_soon(self, expiring_soon: TimeDelta) -> Self {
+        Self { expiring_soon, ..self }
+    }
+
+    pub fn spawn(mut self, interval: Duration) {
+        tokio::spawn(async move {
+            info!(interval_sec = interval.as_secs(), "Watcher started");
+
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            let mut caught_up = false;
+
+            loop {
+                ticker.tick().await;
+
+                if !caught_up {
+                    match self.catch_up().await {
+                        Ok(()) => caught_up = true,
+                        Err(e) => warn!(error = %e, "Watcher catch-up failed"),
+                    }
+                }
+
+                if let Err(e) = self.poll().await {
+                    warn!(error = %e, "Watcher poll failed");
+                }
+            }
+        });
+    }
+
+    /// Checks the invoices that may have left Pending while the backend was down: any that
+    /// didn't expire well before the newest logged event. Nothing logged, nothing to catch up on
+    async fn catch_up(&mut self) -> anyhow::Result<()> {
+        let Some(newest) = self.log.newest().await else {
+            return Ok(());
+        };
+        let since = newest - CATCH_UP_MARGIN;
+
+        let mut heads = HashMap::new();
+        let mut failed = 0;
+
+        for status in [InvoiceStatus::Paid, InvoiceStatus::Expired, InvoiceStatus::Cancelled] {
+            let mut offset = 0;
+            loop {
+                let batch = self.state.db.get_invoices(InvoiceFilter {
+                    status: Some(status),
+                    address: None,
+                    network: None,
+                    token: None,
+                    pagination: Pagination { limit: BATCH, offset },
+                }).await?;
+                let fetched = batch.items.len() as u64;
+
+                for invoice in batch.items {
+                    let invoice_id = invoice.id.clone();
+
+                    let result = if invoice.expires_at >= since && !self.tracked.contains_key(&invoice.id) {
+                        self.check(invoice, &mut heads).await
+                    } else if status == InvoiceStatus::Paid {
+                        // paid before settlements were recorded, or while the backend was down
+                        let extras = self.extras.get(&invoice.id).await;
+                        self.settle(&InvoiceModel::new(invoice, extras)).await
+                    } else {
+                        Ok(())
+                    };
+
+                    if let Err(e) = result {
+                        warn!(invoice_id, error = %e, "Watcher catch-up failed for invoice");
+                        failed += 1;
+                    }
+                }
+
+                offset += fetched;
+                if fetched < BATCH as u64 || offset >= batch.total {
+                    break;
+                }
+            }
+        }
+
+        // checked invoices are tracked now, so the next attempt only redoes the failed ones
+        anyhow::ensure!(failed == 0, "{} invoices failed, retrying on the next poll", failed);
+
+        Ok(())
+    }
+
+    async fn poll(&mut self) -> anyhow::Result<()> {
+        let mut invoices = HashMap::new();
+
+        let mut offset = 0;
+        loop {
+            let batch = self.state.db.get_invoices(InvoiceFilter {
+                status: Some(InvoiceStatus::Pending),
+                address: None,
+                network: None,
+                token: None,
+                pagination: Pagination { limit: BATCH, offset },
+            }).await?;
+            let fetched = batch.items.len() as u64;
+
+            for invoice in batch.items {
+                invoices.insert(invoice.id.clone(), invoice);
+            }
+
+            offset += fetched;
+            if fetched < BATCH as u64 || offset >= batch.total {
+                break;
+            }
+        }
+
+        // invoices that left Pending since the last poll, or still wait for confirmations,
+        // and the ones the API touched, in case they left Pending before this poll saw them
+        let left: HashSet<String> = self.tracked.keys().cloned()
+            .chain(self.watch_list.take())
+            .filter(|id| !invoices.contains_key(id))
+            .collect();
+
+        // one invoice failing doesn't hold up the others, it goes back on the watch list for the next poll
+        for id in left {
+            match self.state.db.get_invoice(&id).await {
+                Ok(Some(invoice)) => { invoices.insert(id, invoice); }
+                Ok(None) => { self.tracked.remove(&id); }
+                Err(e) => {
+                    warn!(invoice_id = id, error = %e, "Watcher failed to load invoice");
+                    self.watch_list.add(&id);
+                }
+            }
+        }
+
+        let mut heads = HashMap::new();
+        for invoice in invoices.into_values() {
+            let invoice_id = invoice.id.clone();
+            if let Err(e) = self.check(invoice, &mut heads).await {
+                warn!(invoice_id, error = %e, "Watcher failed to check invoice");
+                self.watch_list.add(&invoice_id);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Emits what changed since the invoice was last checked. Its new state is only kept once
+    /// every event is logged, an error leaves it to be rebuilt from the log on the next poll
+    async fn check(&mut self, invoice: Invoice, heads: &mut HashMap<String, Option<ProcessedHead>>) -> anyhow::Result<()> {
+        let payments = self.state.db.get_payments(PaymentFilter {
+            invoice_id: Some(invoice.id.clone()),
+            pagination: Pagination { limit: BATCH, offset: 0 },
+            ..Default::default()
+        }).await?;
+
+        let webhooks = self.state.db.get_webhooks(WebhookFilter {
+            invoice_id: Some(invoice.id.clone()),
+            event_type: None,
+            url: None,
+            status: None,
+            pagination: Pagination { limit: BATCH, offset: 0 },
+        }).await?;
+
+        let head = match heads.get(&invoice.network) {
+            Some(head) => *head,
+            None => {
+                let head = processed_head(&self.state, &self.block_times, &invoice.network).await?;
+                heads.insert(invoice.network.clone(), head);
+                head
+            }
+        };
+
+        let seconds_per_block = self.block_times.seconds_per_block(&invoice.network);
+
+        let extras = self.extras.get(&invoice.id).await;
+        let invoice = InvoiceModel::new(invoice, extras);
+        let previous = match self.tracked.remove(&invoice.id) {
+            Some(tracked) => Some(tracked),
+            None => Tracked::from_log(&self.log.for_invoice(&invoice.id).await, &invoice),
+        };
+
+        let mut current = Tracked {
+            status: invoice.status,
+            paid_raw: invoice.paid_raw,
+            expiring_soon: previous.as_ref().and_then(|tracked| tracked.expiring_soon),
+            payments: HashMap::new(),
+            webhooks: HashMap::new(),
+        };
+
+        // nothing was logged for the invoice yet
+        if previous.is_none() {
+            self.emit(Event::invoice(EventKind::InvoiceCreated, invoice.clone())).await?;
+        }
+
+        for payment in payments.items {
+            let payment = PaymentSchema::from(payment);
+            let progress = PaymentProgress::new(&payment, head, seconds_per_block);
+            let confirmations = progress.confirmations;
+
+            let before = previous.as_ref().and_then(|tracked| tracked.payments.get(&payment.id));
+
+            let mut kinds = vec![];
+            match before {
+                None => kinds.push(EventKind::PaymentDetected),
+                Some(before) if before.payment.status == payment.status
+                    && before.confirmations < confirmations => {
+                    kinds.push(EventKind::PaymentConfirmations);
+                }
+                Some(_) => {}
+            }
+
+            if before.is_none_or(|before| before.payment.status != payment.status) {
+                match payment.status {
+                    PaymentStatusSchema::Confirmed => kinds.push(EventKind::PaymentConfirmed),
+                    PaymentStatusSchema::Cancelled => kinds.push(EventKind::PaymentCancelled),
+                    PaymentStatusSchema::Confirming => {}
+                }
+            }
+
+            for kind in kinds {
+                self.emit(Event::payment(kind, invoice.clone(), payment.clone(), progress)).await?;
+            }
+
+            current.payments.insert(payment.id.clone(), TrackedPayment {
+                payment,
+                confirmations,
+            });
+        }
+
+        if let Some(previous) = &previous {
+            for (id, before) in &previous.payments {
+                if !current.payments.contains_key(id) {
+                    let progress = PaymentProgress {
+                        required_confirmations: head.map(|head| head.required_confirmations).unwrap_or_default(),
+                        ..Default::default()
+                    };
+                    self.emit(Event::payment(EventKind::PaymentReverted, invoice.clone(),
+                                             before.payment.clone(), progress)).await?;
+                }
+            }
+        }
+
+        let paid_before = previous.as_ref().map(|tracked| tracked.paid_raw).unwrap_or_default();
+
+        if matches!(invoice.status, InvoiceStatusSchema::Pending) && invoice.paid_raw > paid_before {
+            self.emit(Event::invoice(EventKind::InvoicePartiallyPaid, invoice.clone())).await?;
+        }
+
+        let expiring = matches!(invoice.status, InvoiceStatusSchema::Pending)
+            && !self.expiring_soon.is_zero()
+            && invoice.expires_at - Utc::now() <= self.expiring_soon;
+
+        if expiring && current.expiring_soon != Some(invoice.expires_at) {
+            current.expiring_soon = Some(invoice.expires_at);
+            self.emit(Event::invoice(EventKind::InvoiceExpiringSoon, invoice.clone())).await?;
+        }
+
+        let was_pending = previous.as_ref()
+            .is_none_or(|tracked| matches!(tracked.status, InvoiceStatusSchema::Pending));
+
+        let kind = match invoice.status {
+            InvoiceStatusSchema::Paid | InvoiceStatusSchema::Underpaid
+            | InvoiceStatusSchema::Overpaid => Some(EventKind::InvoicePaid),
+            InvoiceStatusSchema::Expired => Some(EventKind::InvoiceExpired),
+            InvoiceStatusSchema::Cancelled => Some(EventKind::InvoiceCancelled),
+            InvoiceStatusSchema::Pending => None,
+        };
+
+        if let Some(kind) = kind && was_pending {
+            self.emit(Event::invoice(kind, invoice.clone())).await?;
+        }
+
+        self.settle(&invoice).await?;
+
+        // core only sends webhooks of invoices from before the backend took over delivery,
+        // the backend's own ones are reported as they change (see `watch_deliveries`)
+        for webhook in webhooks.items {
+            let webhook = WebhookState::from(webhook);
+            let before = previous.as_ref().and_then(|tracked| tracked.webhooks.get(&webhook.id))
+                .map(|before| (before.status, before.attempts));
+
+            let kind = EventKind::of_webhook(before, webhook.status, webhook.attempts);
+
+            current.webhooks.insert(webhook.id.clone(), TrackedWebhook {
+                status: webhook.status,
+                attempts: webhook.attempts,
+            });
+
+            if let Some(kind) = kind {
+                self.emit(Event::webhook(kind, invoice.clone(), webhook)).await?;
+            }
+        }
+
+        // keep watching until nothing about the invoice can change anymore
+        let settled = !matches!(current.status, InvoiceStatusSchema::Pending)
+            && current.payments.values()
+                .all(|tracked| !matches!(tracked.payment.status, PaymentStatusSchema::Confirming))
+            && current.webhooks.values()
+                .all(|webhook| !matches!(webhook.status, WebhookStatusSchema::Pending
+                    | WebhookStatusSchema::Processing));
+
+        if !settled {
+            self.tracked.insert(invoice.id, current);
+        }
+
+        Ok(())
+    }
+
+    /// Keeps what a paid invoice settled as, it can still go from `Paid` to `Overpaid`
+    async fn settle(&self, invoice: &InvoiceModel) -> anyhow::Result<()> {
+        if !InvoiceStatusSchema::Paid.matches(invoice.status) {
+            return Ok(());
+        }
+
+        self.extras.settle(&invoice.id, Settlement {
+            status: invoice.status,
+            address: invoice.address.clone(),
+            network: invoice.network.clone(),
+            token: invoice.token.clone(),
+            created_at: invoice.created_at,
+        }).await
+    }
+
+    /// Webhook-worthy events are logged before anyone hears about them. The event sinks
+    /// (webhooks, message buses) pick them up from the log
+    async fn emit(&self, mut event: Event) -> anyhow::Result<()> {
+        if let Some(logged) = event.webhook_event() {
+            let entry = self.log.append(logged, event.invoice.clone(), event.payment.clone()).await?;
+            event.sequence = Some(entry.sequence);
+        }
+
+        self.events.publish(event);
+        Ok(())
+    }
+}
+
+/// Turns changes of the webhooks the backend delivers into webhook_* events. Unlike core's,
+/// they don't have to be polled for
+pub fn watch_deliveries(state: Arc<AppState>, extras: Arc<InvoiceExtrasStore>, deliveries: Arc<DeliveryStore>,
+                        events: Arc<EventBus>) {
+    let mut changes = deliveries.subscribe();
+
+    tokio::spawn(async move {
+        loop {
+            let change = match changes.recv().await {
+                Ok(change) => change,
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(missed, "Webhook changes came in faster than they were reported");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            if let Err(e) = delivery_changed(&state, &extras, &events, change).await {
+                warn!(error = %e, "Failed to report webhook change");
+            }
+        }
+    });
+}
+
+async fn delivery_changed(state: &AppState, extras: &InvoiceExtrasStore, events: &EventBus,
+                          change: DeliveryChange) -> anyhow::Result<()> {
+    let delivery = change.delivery;
+
+    let Some(kind) = EventKind::of_webhook(change.before, delivery.status, delivery.attempts) else {
+        return Ok(());
+    };
+
+    let Some(invoice) = state.db.get_invoice(&delivery.invoice_id).await? else {
+        return Ok(());
+    };
+
+    let invoice_extras = extras.get(&invoice.id).await;
+    let invoice = InvoiceModel::new(invoice, invoice_extras);
+    events.publish(Event::webhook(kind, invoice, WebhookState::from(&delivery)));
+
+    Ok(())
+}